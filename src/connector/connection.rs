@@ -1,12 +1,17 @@
 use std::fmt::{Debug, Formatter};
+use std::future::Future;
 use std::sync::{Arc};
-use std::sync::atomic::AtomicBool;
 use tokio::sync::Mutex;
 use async_trait::async_trait;
 use once_cell::sync::Lazy;
 use quaint_forked::{pooled::Quaint};
 use quaint_forked::connector::start_owned_transaction;
+use quaint_forked::prelude::Queryable;
+use crate::connector::functions::register_sqlite_functions;
+use crate::connector::isolation::{IsolationLevel, sqlite_begin_statement};
+use crate::connector::retry::RetryPolicy;
 use crate::connector::transaction::SQLTransaction;
+use crate::error::{SQLError, SQLErrorKind};
 use crate::migration::migrate::SQLMigration;
 use crate::schema::dialect::SQLDialect;
 use crate::url::url_utils;
@@ -18,6 +23,8 @@ pub struct SQLConnection {
     dialect: SQLDialect,
     pool: Quaint,
     memory_mode: bool,
+    isolation_level: Option<IsolationLevel>,
+    retry_policy: RetryPolicy,
 }
 
 impl Debug for SQLConnection {
@@ -28,11 +35,90 @@ impl Debug for SQLConnection {
 
 impl SQLConnection {
 
-    pub async fn new(dialect: SQLDialect, url: &str, reset: bool) -> Self {
-        SQLMigration::create_database_if_needed(dialect, url, reset).await;
-        let url = url_utils::normalized_url(dialect, url);
-        let pool = Quaint::builder(url.as_str()).unwrap().build();
-        Self { dialect, pool, memory_mode: url.to_string().contains(":memory:") }
+    pub async fn new(dialect: SQLDialect, url: &str, reset: bool) -> Result<Self> {
+        if dialect.is_sqlite() {
+            register_sqlite_functions();
+        }
+        SQLMigration::create_database_if_needed(dialect, url, reset).await?;
+        let mut url = url_utils::normalized_url(dialect, url)?;
+        let isolation_level = url_utils::take_isolation_level(dialect, &mut url)?;
+        let pool = Quaint::builder(&url_utils::connection_string(dialect, &url)?).map_err(|e| {
+            SQLError::new(SQLErrorKind::InvalidURL, dialect, e.to_string())
+        })?.build();
+        Ok(Self { dialect, pool, memory_mode: url.to_string().contains(":memory:"), isolation_level, retry_policy: RetryPolicy::default() })
+    }
+
+    // the default for transactions started without an explicit level, overrides the url
+    pub fn with_isolation_level(mut self, isolation_level: Option<IsolationLevel>) -> Self {
+        self.isolation_level = isolation_level;
+        self
+    }
+
+    pub fn isolation_level(&self) -> Option<IsolationLevel> {
+        self.isolation_level
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub async fn transaction_with_isolation_level(&self, isolation_level: Option<IsolationLevel>) -> Result<Arc<dyn Transaction>> {
+        if self.memory_mode && self.dialect.is_sqlite() {
+            return self.sqlite_memory_transaction().await;
+        }
+        Ok(Arc::new(self.begin(isolation_level).await?))
+    }
+
+    // runs `f` in a new transaction and replays it when it fails on a serialization failure or a deadlock
+    pub async fn run_transaction<F, Fut, T>(&self, isolation_level: Option<IsolationLevel>, f: F) -> Result<T> where
+        F: Fn(Arc<dyn Transaction>) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        if self.memory_mode && self.dialect.is_sqlite() {
+            return f(self.sqlite_memory_transaction().await?).await;
+        }
+        let mut attempt = 1;
+        loop {
+            let transaction = Arc::new(self.begin(isolation_level).await?);
+            let result = match f(transaction.clone() as Arc<dyn Transaction>).await {
+                Ok(value) => transaction.commit().await.map(|_| value),
+                Err(err) => {
+                    let _ = transaction.abort().await;
+                    Err(err)
+                }
+            };
+            match result {
+                Err(_) if transaction.is_conflicted() && attempt < self.retry_policy.max_attempts => {
+                    tokio::time::sleep(self.retry_policy.delay(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn begin(&self, isolation_level: Option<IsolationLevel>) -> Result<SQLTransaction> {
+        match self.pool.check_out().await {
+            Ok(pooled_connection) => {
+                let pooled_connection = Arc::new(pooled_connection);
+                if self.dialect.is_sqlite() {
+                    if let Some(isolation_level) = isolation_level {
+                        pooled_connection.raw_cmd(sqlite_begin_statement(isolation_level)).await.map_err(|e| {
+                            SQLError::new(SQLErrorKind::Connection, self.dialect, format!("cannot start transaction: {}", e))
+                        })?;
+                        return Ok(SQLTransaction::new_begun(self.dialect, pooled_connection));
+                    }
+                }
+                let transaction = start_owned_transaction(pooled_connection.clone(), isolation_level).await.map_err(|e| {
+                    SQLError::new(SQLErrorKind::Connection, self.dialect, format!("cannot start transaction: {}", e))
+                })?;
+                Ok(SQLTransaction::new(self.dialect, pooled_connection, Some(Arc::new(transaction))))
+            }
+            Err(err) => {
+                Err(Error::new(format!("cannot create pooled connection: {}", err.to_string())))
+            }
+        }
     }
 
     async fn sqlite_memory_transaction(&self) -> Result<Arc<dyn Transaction>> {
@@ -62,24 +148,7 @@ static UNIQUE_TRANSACTION: Lazy<Mutex<Option<Arc<dyn Transaction>>>> = Lazy::new
 impl Connection for SQLConnection {
 
     async fn transaction(&self) -> Result<Arc<dyn Transaction>> {
-        if self.memory_mode && self.dialect.is_sqlite() {
-            return self.sqlite_memory_transaction().await;
-        }
-        match self.pool.check_out().await {
-            Ok(pooled_connection) => {
-                let pooled_connection = Arc::new(pooled_connection);
-                let transaction = start_owned_transaction(pooled_connection.clone(), None).await.unwrap();
-                Ok(Arc::new(SQLTransaction {
-                    dialect: self.dialect,
-                    conn: pooled_connection,
-                    tran: Some(Arc::new(transaction)),
-                    committed: Arc::new(AtomicBool::new(false)),
-                }))
-            }
-            Err(err) => {
-                Err(Error::new(format!("cannot create pooled connection: {}", err.to_string())))
-            }
-        }
+        self.transaction_with_isolation_level(self.isolation_level).await
     }
 
     async fn no_transaction(&self) -> Result<Arc<dyn Transaction>> {
@@ -0,0 +1,81 @@
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::sync::Once;
+use libsqlite3_sys as ffi;
+use regex::Regex;
+
+static REGISTER: Once = Once::new();
+
+// SQLite has no built-in `REGEXP`, the function is installed as an auto extension so every
+// connection the pool opens afterwards has it
+pub(crate) fn register_sqlite_functions() {
+    REGISTER.call_once(|| unsafe {
+        // the declared entry point type differs between libsqlite3-sys releases
+        let entry = sqlite_functions_init as *const ();
+        #[allow(clippy::missing_transmute_annotations)]
+        ffi::sqlite3_auto_extension(Some(std::mem::transmute(entry)));
+    });
+}
+
+unsafe extern "C" fn sqlite_functions_init(db: *mut ffi::sqlite3, _err: *mut *mut c_char, _api: *const c_void) -> c_int {
+    ffi::sqlite3_create_function_v2(
+        db,
+        c"regexp".as_ptr(),
+        2,
+        ffi::SQLITE_UTF8 | ffi::SQLITE_DETERMINISTIC,
+        std::ptr::null_mut(),
+        Some(regexp),
+        None,
+        None,
+        None,
+    )
+}
+
+// `x REGEXP y` calls `regexp(y, x)`, the compiled pattern is kept as auxiliary data while the
+// statement runs with the same pattern
+unsafe extern "C" fn regexp(ctx: *mut ffi::sqlite3_context, argc: c_int, argv: *mut *mut ffi::sqlite3_value) {
+    if argc != 2 {
+        return result_error(ctx, "regexp takes a pattern and a text");
+    }
+    let args = std::slice::from_raw_parts(argv, 2);
+    let Some(text) = value_text(args[1]) else {
+        return ffi::sqlite3_result_null(ctx);
+    };
+    let mut regex = ffi::sqlite3_get_auxdata(ctx, 0) as *const Regex;
+    if regex.is_null() {
+        let Some(pattern) = value_text(args[0]) else {
+            return ffi::sqlite3_result_null(ctx);
+        };
+        let compiled = match Regex::new(pattern) {
+            Ok(compiled) => Box::into_raw(Box::new(compiled)),
+            Err(err) => return result_error(ctx, &err.to_string()),
+        };
+        ffi::sqlite3_set_auxdata(ctx, 0, compiled as *mut c_void, Some(drop_regex));
+        // SQLite may drop the auxiliary data right away, it is read back before use
+        regex = ffi::sqlite3_get_auxdata(ctx, 0) as *const Regex;
+        if regex.is_null() {
+            let compiled = Regex::new(pattern).unwrap();
+            return ffi::sqlite3_result_int(ctx, compiled.is_match(text) as c_int);
+        }
+    }
+    ffi::sqlite3_result_int(ctx, (*regex).is_match(text) as c_int)
+}
+
+unsafe fn value_text<'a>(value: *mut ffi::sqlite3_value) -> Option<&'a str> {
+    if ffi::sqlite3_value_type(value) == ffi::SQLITE_NULL {
+        return None;
+    }
+    let text = ffi::sqlite3_value_text(value);
+    if text.is_null() {
+        return None;
+    }
+    CStr::from_ptr(text as *const c_char).to_str().ok()
+}
+
+unsafe fn result_error(ctx: *mut ffi::sqlite3_context, message: &str) {
+    let message = CString::new(message.replace('\0', "")).unwrap();
+    ffi::sqlite3_result_error(ctx, message.as_ptr(), -1);
+}
+
+unsafe extern "C" fn drop_regex(regex: *mut c_void) {
+    drop(Box::from_raw(regex as *mut Regex));
+}
@@ -0,0 +1,25 @@
+pub use quaint_forked::connector::IsolationLevel;
+
+pub(crate) const ISOLATION_LEVEL_PARAM: &str = "isolation_level";
+
+// accepts `serializable`, `repeatable_read`, `REPEATABLE READ`, `RepeatableRead` and alike
+pub fn parse_isolation_level(value: &str) -> Option<IsolationLevel> {
+    let normalized: String = value.chars().filter(|c| c.is_ascii_alphabetic()).map(|c| c.to_ascii_lowercase()).collect();
+    match normalized.as_str() {
+        "readuncommitted" => Some(IsolationLevel::ReadUncommitted),
+        "readcommitted" => Some(IsolationLevel::ReadCommitted),
+        "repeatableread" => Some(IsolationLevel::RepeatableRead),
+        "snapshot" => Some(IsolationLevel::Snapshot),
+        "serializable" => Some(IsolationLevel::Serializable),
+        _ => None,
+    }
+}
+
+// SQLite is always serializable, the level decides how early the write lock is taken
+pub(crate) fn sqlite_begin_statement(level: IsolationLevel) -> &'static str {
+    match level {
+        IsolationLevel::ReadUncommitted | IsolationLevel::ReadCommitted => "BEGIN DEFERRED",
+        IsolationLevel::RepeatableRead | IsolationLevel::Snapshot => "BEGIN IMMEDIATE",
+        IsolationLevel::Serializable => "BEGIN EXCLUSIVE",
+    }
+}
@@ -1,5 +1,10 @@
 pub mod transaction;
 pub mod connection;
+pub mod isolation;
+pub mod retry;
+pub(crate) mod functions;
 
 pub use connection::SQLConnection;
-pub use transaction::SQLTransaction;
\ No newline at end of file
+pub use transaction::SQLTransaction;
+pub use isolation::IsolationLevel;
+pub use retry::RetryPolicy;
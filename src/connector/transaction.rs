@@ -1,6 +1,7 @@
 use std::fmt::{Debug, Formatter};
+use std::path::Path;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use async_trait::async_trait;
 use itertools::Itertools;
 use quaint_forked::{prelude::*, ast::Query as QuaintQuery};
@@ -10,14 +11,19 @@ use quaint_forked::pooled::PooledConnection;
 use quaint_forked::connector::owned_transaction::OwnedTransaction;
 use quaint_forked::connector::start_owned_transaction;
 use teo_runtime::model::Model;
+use crate::error::{is_retryable, SQLError, SQLErrorKind};
 use crate::execution::Execution;
 use crate::migration::migrate::SQLMigration;
-use crate::query::Query;
+use crate::migration::history::{MigrationFile, MigrationHistory, MigrationStatus};
+use crate::migration::plan::MigrationPlan;
+use crate::query::{escape_wisdom, Query};
+use crate::exts::model::ModelExt;
+use crate::stmts::select::r#where::WhereClause::And;
 use crate::stmts::SQL;
 use crate::schema::dialect::SQLDialect;
 use crate::schema::value::decode::RowDecoder;
-use crate::schema::value::encode::ToSQLString;
-use crate::schema::value::encode::PSQLArrayToSQLString;
+use crate::schema::value::encode::{json_path_segments, SQLEscape, ToSQLString};
+use crate::stmts::params::SQLParams;
 use teo_runtime::action::Action;
 use teo_runtime::model::object::input::Input;
 use teo_runtime::model::Object;
@@ -25,11 +31,16 @@ use teo_runtime::connection::connection::Connection;
 use teo_teon::{teon, Value};
 use teo_result::{Result, Error};
 use teo_runtime::connection::transaction;
+use teo_runtime::model::field::Field;
 use teo_runtime::model::field::column_named::ColumnNamed;
 use teo_runtime::connection::transaction::Transaction;
 use teo_runtime::model::field::typed::Typed;
+use teo_parser::r#type::Type;
 use teo_runtime::object::error_ext;
 use teo_runtime::request::Ctx;
+use teo_runtime::namespace::Namespace;
+use teo_runtime::index::Type as IndexType;
+use indexmap::IndexMap;
 use key_path::KeyPath;
 
 #[derive(Clone)]
@@ -38,6 +49,35 @@ pub struct SQLTransaction {
     pub conn: Arc<PooledConnection>,
     pub tran: Option<Arc<OwnedTransaction>>,
     pub committed: Arc<AtomicBool>,
+    pub savepoint: Option<String>,
+    savepoint_counter: Arc<AtomicUsize>,
+    // SQLite transactions opened with BEGIN IMMEDIATE or EXCLUSIVE live on the connection itself
+    begun: Option<Arc<BegunTransaction>>,
+    // set when a statement failed in a way that replaying the transaction may fix
+    conflicted: Arc<AtomicBool>,
+}
+
+// a raw BEGIN has no guard of its own, the transaction is rolled back when the last handle is
+// dropped without a commit or an abort
+struct BegunTransaction {
+    conn: Arc<PooledConnection>,
+    finished: AtomicBool,
+}
+
+impl Drop for BegunTransaction {
+
+    fn drop(&mut self) {
+        if self.finished.load(Ordering::SeqCst) {
+            return;
+        }
+        // the spawned task keeps the connection checked out until the rollback is done
+        let conn = self.conn.clone();
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(async move {
+                let _ = conn.raw_cmd("ROLLBACK").await;
+            });
+        }
+    }
 }
 
 impl Debug for SQLTransaction {
@@ -50,7 +90,37 @@ impl Debug for SQLTransaction {
 impl SQLTransaction {
     pub(super) fn new(dialect: SQLDialect, conn: Arc<PooledConnection>, tran: Option<Arc<OwnedTransaction>>) -> Self {
         Self {
-            dialect, conn, tran, committed: Arc::new(AtomicBool::new(false))
+            dialect, conn, tran, committed: Arc::new(AtomicBool::new(false)), savepoint: None, savepoint_counter: Arc::new(AtomicUsize::new(0)), begun: None, conflicted: Arc::new(AtomicBool::new(false))
+        }
+    }
+
+    pub(super) fn new_begun(dialect: SQLDialect, conn: Arc<PooledConnection>) -> Self {
+        let begun = Arc::new(BegunTransaction { conn: conn.clone(), finished: AtomicBool::new(false) });
+        Self { begun: Some(begun), ..Self::new(dialect, conn, None) }
+    }
+
+    // SQL Server names savepoints with SAVE TRANSACTION and has no way to release them
+    fn savepoint_stmt(&self, name: &str) -> String {
+        if self.dialect.is_mssql() {
+            format!("SAVE TRANSACTION {name}")
+        } else {
+            format!("SAVEPOINT {name}")
+        }
+    }
+
+    fn release_savepoint_stmt(&self, name: &str) -> Option<String> {
+        if self.dialect.is_mssql() {
+            None
+        } else {
+            Some(format!("RELEASE SAVEPOINT {name}"))
+        }
+    }
+
+    fn rollback_to_savepoint_stmt(&self, name: &str) -> String {
+        if self.dialect.is_mssql() {
+            format!("ROLLBACK TRANSACTION {name}")
+        } else {
+            format!("ROLLBACK TO SAVEPOINT {name}")
         }
     }
 }
@@ -81,29 +151,36 @@ impl SQLTransaction {
         let model = object.model();
         let keys = object.keys_for_save();
         let auto_keys = &model.cache.auto_keys;
+        let version_field = model.version_field();
+        let mut params = SQLParams::new(self.dialect());
         let mut values: Vec<(&str, String)> = vec![];
         for key in keys {
             if let Some(field) = model.field(key) {
                 let column_name = field.column_name();
-                let val = object.get_value(key).unwrap();
+                let val = Self::initial_value(object, version_field, field, key)?;
                 if !(field.auto_increment && val.is_null()) {
-                    values.push((column_name, PSQLArrayToSQLString::to_string_with_ft(&val, self.dialect(), field.r#type())));
+                    values.push((column_name, params.push(&val, field.r#type())));
                 }
             } else if let Some(property) = model.property(key) {
                 let val: Value = object.get_property_value(key).await?;
-                values.push((key, PSQLArrayToSQLString::to_string_with_ft(&val, self.dialect(), property.r#type())));
+                values.push((key, params.push(&val, property.r#type())));
             }
         }
         let value_refs: Vec<(&str, &str)> = values.iter().map(|(k, v)| (*k, v.as_str())).collect();
-        let stmt = SQL::insert_into(&model.table_name).values(value_refs).returning(auto_keys).to_string(self.dialect());
+        let returning: Vec<&str> = auto_keys.iter().map(|k| model.field(k).unwrap().column_name()).collect();
+        let (stmt, params) = SQL::insert_into(&model.table_name).values(value_refs).returning(&returning).params(params).to_statement(self.dialect());
         // println!("create stmt: {}", stmt);
-        if self.dialect() == SQLDialect::PostgreSQL {
-            match self.queryable().query(QuaintQuery::from(stmt)).await {
+        // keys come back with RETURNING on PostgreSQL and OUTPUT INSERTED on SQL Server
+        if self.dialect() == SQLDialect::PostgreSQL || self.dialect() == SQLDialect::MSSQL {
+            match self.queryable().query_raw(&stmt, &params).await {
                 Ok(result_set) => {
                     let columns = result_set.columns().clone();
                     let result = result_set.into_iter().next();
                     if result.is_some() {
-                        let value = Execution::row_to_value(object.namespace(), model, &result.unwrap(), &columns, self.dialect());
+                        let value = match Execution::row_to_value(object.namespace(), model, &result.unwrap(), &columns, self.dialect()) {
+                            Ok(value) => value,
+                            Err(err) => return Err(error_ext::unknown_database_write_error(path, err.to_string())),
+                        };
                         for (k, v) in value.as_dictionary().unwrap() {
                             object.set_value(k, v.clone())?;
                         }
@@ -115,7 +192,7 @@ impl SQLTransaction {
                 }
             }
         } else {
-            match self.conn().query(QuaintQuery::from(stmt)).await {
+            match self.conn().query_raw(&stmt, &params).await {
                 Ok(result) => {
                     if let Some(id) = result.last_insert_id() {
                         for key in auto_keys {
@@ -138,41 +215,207 @@ impl SQLTransaction {
     async fn update_object(&self, object: &Object, path: KeyPath) -> teo_runtime::path::Result<()> {
         let model = object.model();
         let keys = object.keys_for_save();
+        let version_field = model.version_field();
+        let mut params = SQLParams::new(self.dialect());
         let mut values: Vec<(&str, String)> = vec![];
         for key in &keys {
             if let Some(field) = model.field(key) {
                 let column_name = field.column_name();
-                if let Some(updator) = object.get_atomic_updator(key) {
+                if version_field.is_some_and(|v| v.name() == *key) {
+                    // the version column is managed here and never written from input
+                    continue;
+                } else if let Some(updator) = object.get_atomic_updator(key) {
                     let (key, val) = Input::key_value(updator.as_dictionary().unwrap());
-                    match key {
-                        "increment" => values.push((column_name, format!("{} + {}", column_name, ToSQLString::to_string(&val, self.dialect())))),
-                        "decrement" => values.push((column_name, format!("{} - {}", column_name, ToSQLString::to_string(&val, self.dialect())))),
-                        "multiply" => values.push((column_name, format!("{} * {}", column_name, ToSQLString::to_string(&val, self.dialect())))),
-                        "divide" => values.push((column_name, format!("{} / {}", column_name, ToSQLString::to_string(&val, self.dialect())))),
-                        "push" => values.push((column_name, format!("ARRAY_APPEND({}, {})", column_name, ToSQLString::to_string(&val, self.dialect())))),
-                        _ => unreachable!(),
-                    }
+                    values.push((column_name, Self::atomic_update_value(column_name, key, &val, field.r#type(), &mut params)));
                 } else {
                     let val = object.get_value(key).unwrap();
-                    values.push((column_name, PSQLArrayToSQLString::to_string_with_ft(&val, self.dialect(), field.r#type())));
+                    values.push((column_name, params.push(&val, field.r#type())));
                 }
             } else if let Some(property) = model.property(key) {
                 let val: Value = object.get_property_value(key).await?;
-                values.push((key, PSQLArrayToSQLString::to_string_with_ft(&val, self.dialect(), property.r#type())));
+                values.push((key, params.push(&val, property.r#type())));
             }
         }
         let value_refs: Vec<(&str, &str)> = values.iter().map(|(k, v)| (*k, v.as_str())).collect();
         let identifier = object.identifier();
-        let r#where = Query::where_from_previous_identifier(object, self.dialect());
+        let mut r#where = Query::where_from_previous_identifier(object, self.dialect(), &mut params);
         if !value_refs.is_empty() {
-            let stmt = SQL::update(&model.table_name).values(value_refs).r#where(&r#where).to_string(self.dialect());
+            let mut value_refs = value_refs;
+            let version = version_field.map(|field| {
+                let column_name = field.column_name();
+                (column_name, format!("{} + 1", column_name.escape(self.dialect())), object.get_value(field.name()).unwrap())
+            });
+            if let Some((column_name, increment, current)) = &version {
+                value_refs.push((*column_name, increment.as_str()));
+                let current = params.push(current, version_field.unwrap().r#type());
+                r#where = versioned_where(r#where, column_name, &current, self.dialect());
+            }
+            let (stmt, params) = SQL::update(&model.table_name).values(value_refs).r#where(&r#where).params(params).to_statement(self.dialect());
             // println!("update stmt: {}", stmt);
-            let result = self.conn().execute(QuaintQuery::from(stmt)).await;
-            if result.is_err() {
-                return Err(error_ext::unknown_database_write_error(path.clone(), format!("{:?}", result.err().unwrap())));
+            let result = self.conn().execute_raw(&stmt, &params).await;
+            match result {
+                Err(err) => return Err(self.handle_err_result(err, path.clone())),
+                Ok(0) if version.is_some() => return Err(version_conflict(self.dialect(), &model.table_name).into()),
+                Ok(_) => (),
             }
         }
-        let result = Execution::query(object.namespace(), self.queryable(), model, &teon!({"where": identifier, "take": 1i64}), self.dialect(), path.clone()).await?;
+        let result = Execution::query(object.namespace(), self.queryable(), &self.conflicted, model, &teon!({"where": identifier, "take": 1i64}), self.dialect(), path.clone()).await?;
+        if result.is_empty() {
+            Err(error_ext::not_found(path.clone()))
+        } else {
+            object.set_from_database_result_value(result.get(0).unwrap(), None, None);
+            Ok(())
+        }
+    }
+
+    fn atomic_update_value(column_name: &str, updator: &str, val: &Value, r#type: &Type, params: &mut SQLParams) -> String {
+        match updator {
+            "increment" => format!("{} + {}", column_name, params.push(val, r#type)),
+            "decrement" => format!("{} - {}", column_name, params.push(val, r#type)),
+            "multiply" => format!("{} * {}", column_name, params.push(val, r#type)),
+            "divide" => format!("{} / {}", column_name, params.push(val, r#type)),
+            "push" => format!("ARRAY_APPEND({}, {})", column_name, params.push_untyped(val)),
+            "merge" => Self::json_merge_value(&escape_wisdom(column_name, params.dialect()), val, params),
+            "setPath" => {
+                let segments = json_path_segments(val.get("path").unwrap());
+                let value = val.get("value").cloned().unwrap_or(Value::Null);
+                Self::json_set_value(&escape_wisdom(column_name, params.dialect()), vec![(segments, value)], params)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // top level keys of `val` replace those of the stored document
+    fn json_merge_value(column_name: &str, val: &Value, params: &mut SQLParams) -> String {
+        if val.as_dictionary().unwrap().is_empty() {
+            return column_name.to_owned();
+        }
+        if params.dialect() == SQLDialect::PostgreSQL {
+            return format!("COALESCE({column_name}::jsonb, '{{}}'::jsonb) || {}", params.push_json(val));
+        }
+        let entries = val.as_dictionary().unwrap().iter().map(|(k, v)| (vec![k.clone()], v.clone())).collect();
+        Self::json_set_value(column_name, entries, params)
+    }
+
+    // missing documents are created, intermediate objects are expected to exist
+    fn json_set_value(column_name: &str, entries: Vec<(Vec<String>, Value)>, params: &mut SQLParams) -> String {
+        let dialect = params.dialect();
+        let mut result = match dialect {
+            SQLDialect::PostgreSQL => format!("COALESCE({column_name}::jsonb, '{{}}'::jsonb)"),
+            SQLDialect::MySQL => format!("COALESCE({column_name}, JSON_OBJECT())"),
+            _ => format!("COALESCE({column_name}, '{{}}')"),
+        };
+        let mut pairs: Vec<String> = vec![];
+        for (segments, value) in entries {
+            let path = params.push_json_path(&segments);
+            let value = params.push_json(&value);
+            match dialect {
+                SQLDialect::PostgreSQL => result = format!("jsonb_set({result}, {path}, {value}, true)"),
+                SQLDialect::MSSQL => result = format!("JSON_MODIFY({result}, {path}, {value})"),
+                _ => pairs.push(format!("{path}, {value}")),
+            }
+        }
+        match dialect {
+            SQLDialect::MySQL => format!("JSON_SET({result}, {})", pairs.join(", ")),
+            SQLDialect::SQLite => format!("json_set({result}, {})", pairs.join(", ")),
+            _ => result,
+        }
+    }
+
+    // `update` maps field names to new values or to atomic updators like `{ "increment": 1 }`
+    fn update_values<'a>(model: &'a Model, update: &Value, qualifier: Option<&str>, params: &mut SQLParams, path: &KeyPath) -> teo_runtime::path::Result<Vec<(&'a str, String)>> {
+        let mut values: Vec<(&str, String)> = vec![];
+        for (key, value) in update.as_dictionary().unwrap() {
+            let Some(field) = model.field(key) else {
+                return Err(error_ext::unknown_database_write_error(path.clone() + key.as_str(), format!("field `{}' is not defined", key)));
+            };
+            let column_name = field.column_name();
+            let atomic = value.as_dictionary().filter(|d| d.len() == 1).map(|d| Input::key_value(d));
+            match atomic {
+                Some(("set", val)) => values.push((column_name, params.push(val, field.r#type()))),
+                Some((updator, val)) if ["increment", "decrement", "multiply", "divide", "push", "merge", "setPath"].contains(&updator) => {
+                    let column = match qualifier {
+                        Some(qualifier) => format!("{}.{}", qualifier, column_name.escape(params.dialect())),
+                        None => column_name.to_owned(),
+                    };
+                    values.push((column_name, Self::atomic_update_value(&column, updator, val, field.r#type(), params)));
+                }
+                _ => values.push((column_name, params.push(value, field.r#type()))),
+            }
+        }
+        Ok(values)
+    }
+
+    pub async fn update_many(&self, namespace: &Namespace, model: &Model, r#where: &Value, update: &Value, path: KeyPath) -> teo_runtime::path::Result<u64> {
+        let mut params = SQLParams::new(self.dialect());
+        let values = Self::update_values(model, update, None, &mut params, &path)?;
+        if values.is_empty() {
+            return Ok(0);
+        }
+        let r#where = Query::r#where(namespace, model, r#where, self.dialect(), None, &mut params)?;
+        let value_refs: Vec<(&str, &str)> = values.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        let (stmt, params) = SQL::update(&model.table_name).values(value_refs).r#where(&r#where).params(params).to_statement(self.dialect());
+        self.queryable().execute_raw(&stmt, &params).await.map_err(|err| self.handle_err_result(err, path))
+    }
+
+    pub async fn delete_many(&self, namespace: &Namespace, model: &Model, r#where: &Value, path: KeyPath) -> teo_runtime::path::Result<u64> {
+        let mut params = SQLParams::new(self.dialect());
+        let r#where = Query::r#where(namespace, model, r#where, self.dialect(), None, &mut params)?;
+        let (stmt, params) = SQL::delete_from(&model.table_name).r#where(r#where).params(params).to_statement(self.dialect());
+        self.queryable().execute_raw(&stmt, &params).await.map_err(|err| self.handle_err_result(err, path))
+    }
+
+    // inserts the object, or applies `update` to the row that already holds one of its unique keys
+    pub async fn upsert_object(&self, object: &Object, update: &Value, path: KeyPath) -> teo_runtime::path::Result<()> {
+        if self.dialect() == SQLDialect::MSSQL {
+            return Err(error_ext::unknown_database_write_error(path, "upsert is not supported on SQL Server".to_owned()));
+        }
+        let model = object.model();
+        let version_field = model.version_field();
+        let mut params = SQLParams::new(self.dialect());
+        let mut values: Vec<(&str, String)> = vec![];
+        let mut identifier: IndexMap<String, Value> = IndexMap::new();
+        let keys = object.keys_for_save();
+        let conflict_index = model.indexes().iter().find(|index| {
+            (index.r#type() == IndexType::Primary || index.r#type() == IndexType::Unique) && index.keys().iter().all(|k| {
+                keys.contains(&k.as_str()) && !object.get_value(k).unwrap().is_null()
+            })
+        });
+        let Some(conflict_index) = conflict_index else {
+            return Err(error_ext::unknown_database_write_error(path, "upsert requires the values of a unique index".to_owned()));
+        };
+        for key in &keys {
+            if let Some(field) = model.field(key) {
+                let column_name = field.column_name();
+                let val = Self::initial_value(object, version_field, field, key)?;
+                if conflict_index.keys().iter().any(|k| k.as_str() == *key) {
+                    identifier.insert(key.to_string(), val.clone());
+                }
+                if !(field.auto_increment && val.is_null()) {
+                    values.push((column_name, params.push(&val, field.r#type())));
+                }
+            } else if let Some(property) = model.property(key) {
+                let val: Value = object.get_property_value(key).await?;
+                values.push((key, params.push(&val, property.r#type())));
+            }
+        }
+        let conflict_columns: Vec<&str> = conflict_index.keys().iter().map(|k| model.field(k).unwrap().column_name()).collect();
+        let qualifier = model.table_name.escape(self.dialect());
+        let mut updates = Self::update_values(model, update, Some(&qualifier), &mut params, &path)?;
+        // an updated row moves to the next version like any other update
+        if let Some(field) = version_field {
+            let column_name = field.column_name();
+            if !updates.is_empty() && !updates.iter().any(|(k, _)| *k == column_name) {
+                updates.push((column_name, format!("{qualifier}.{} + 1", column_name.escape(self.dialect()))));
+            }
+        }
+        let value_refs: Vec<(&str, &str)> = values.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        let update_refs: Vec<(&str, &str)> = updates.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        let (stmt, params) = SQL::insert_into(&model.table_name).values(value_refs).on_conflict(conflict_columns).do_update(update_refs).params(params).to_statement(self.dialect());
+        if let Err(err) = self.queryable().execute_raw(&stmt, &params).await {
+            return Err(self.handle_err_result(err, path));
+        }
+        let result = Execution::query(object.namespace(), self.queryable(), &self.conflicted, model, &teon!({"where": Value::Dictionary(identifier), "take": 1i64}), self.dialect(), path.clone()).await?;
         if result.is_empty() {
             Err(error_ext::not_found(path.clone()))
         } else {
@@ -181,7 +424,173 @@ impl SQLTransaction {
         }
     }
 
+    // version fields start at 0, updates compare against the stored version
+    fn initial_value(object: &Object, version_field: Option<&Field>, field: &Field, key: &str) -> Result<Value> {
+        let val = object.get_value(key).unwrap();
+        if val.is_null() && version_field.is_some_and(|v| v.name() == key) {
+            let val = if field.r#type().is_int() { Value::Int(0) } else { Value::Int64(0) };
+            object.set_value(key, val.clone())?;
+            return Ok(val);
+        }
+        Ok(val)
+    }
+
+    // objects sharing a model and a column set are inserted together, in chunks that fit the parameter limit
+    pub async fn create_objects(&self, objects: &Vec<Object>, path: KeyPath) -> teo_runtime::path::Result<()> {
+        let mut groups: Vec<(&str, Vec<String>, Vec<(usize, Vec<(Value, Type)>)>)> = vec![];
+        for (index, object) in objects.iter().enumerate() {
+            let model = object.model();
+            let version_field = model.version_field();
+            let mut columns: Vec<String> = vec![];
+            let mut values: Vec<(Value, Type)> = vec![];
+            for key in object.keys_for_save() {
+                if let Some(field) = model.field(key) {
+                    let val = Self::initial_value(object, version_field, field, key)?;
+                    if !(field.auto_increment && val.is_null()) {
+                        columns.push(field.column_name().to_owned());
+                        values.push((val, field.r#type().clone()));
+                    }
+                } else if let Some(property) = model.property(key) {
+                    let val: Value = object.get_property_value(key).await?;
+                    columns.push(key.to_owned());
+                    values.push((val, property.r#type().clone()));
+                }
+            }
+            if let Some(group) = groups.iter_mut().find(|(t, c, _)| *t == model.table_name.as_str() && c == &columns) {
+                group.2.push((index, values));
+            } else {
+                groups.push((model.table_name.as_str(), columns, vec![(index, values)]));
+            }
+        }
+        for (table_name, columns, rows) in groups {
+            let model = objects[rows[0].0].model();
+            let auto_keys = &model.cache.auto_keys;
+            // returned rows come back in no particular order, they are matched to the objects
+            // through a unique index that every row provides a value for
+            let match_index = if auto_keys.is_empty() || self.dialect() == SQLDialect::MySQL {
+                None
+            } else {
+                model.indexes().iter().find(|index| {
+                    (index.r#type() == IndexType::Primary || index.r#type() == IndexType::Unique) && index.keys().iter().all(|k| {
+                        !auto_keys.contains(k) && model.field(k).is_some_and(|f| columns.iter().any(|c| c == f.column_name())) && rows.iter().all(|(index, _)| {
+                            !objects[*index].get_value(k).unwrap().is_null()
+                        })
+                    })
+                })
+            };
+            let returning: Vec<String> = auto_keys.iter().chain(match_index.iter().flat_map(|index| index.keys().iter())).map(|k| {
+                model.field(k).unwrap().column_name().to_owned()
+            }).collect();
+            let chunk_size = if columns.is_empty() || (!auto_keys.is_empty() && self.dialect() != SQLDialect::MySQL && match_index.is_none()) {
+                // without columns or a way to match returned keys, rows are inserted one by one
+                1
+            } else if self.dialect() == SQLDialect::MSSQL {
+                // a table value constructor takes at most 1000 rows
+                (self.dialect().max_params() / columns.len()).clamp(1, 1000)
+            } else {
+                (self.dialect().max_params() / columns.len()).max(1)
+            };
+            for chunk in rows.chunks(chunk_size) {
+                let mut params = SQLParams::new(self.dialect());
+                let values: Vec<Vec<String>> = chunk.iter().map(|(_, row)| {
+                    row.iter().map(|(val, t)| params.push(val, t)).collect()
+                }).collect();
+                let mut stmt = SQL::insert_into(table_name);
+                stmt.columns(columns.iter().map(|c| c.as_str()).collect());
+                for row in &values {
+                    stmt.row(row.iter().map(|v| v.as_str()).collect());
+                }
+                stmt.returning(&returning).params(params);
+                let (stmt, params) = stmt.to_statement(self.dialect());
+                let result_set = match self.queryable().query_raw(&stmt, &params).await {
+                    Ok(result_set) => result_set,
+                    Err(err) => return Err(self.handle_err_result(err, path)),
+                };
+                if auto_keys.is_empty() {
+                    continue;
+                }
+                if self.dialect() == SQLDialect::MySQL {
+                    // generated keys of a multi-row insert are consecutive from the first one
+                    if auto_keys.iter().any(|k| columns.iter().any(|c| c == model.field(k).unwrap().column_name())) {
+                        continue;
+                    }
+                    if let Some(first_id) = result_set.last_insert_id() {
+                        for (offset, (index, _)) in chunk.iter().enumerate() {
+                            let id = first_id + offset as u64;
+                            for key in auto_keys {
+                                if model.field(key).unwrap().r#type().is_int() {
+                                    objects[*index].set_value(key, Value::Int(id as i32))?;
+                                } else {
+                                    objects[*index].set_value(key, Value::Int64(id as i64))?;
+                                }
+                            }
+                        }
+                    }
+                } else {
+                    let result_columns = result_set.columns().clone();
+                    for row in result_set.into_iter() {
+                        let value = match Execution::row_to_value(objects[chunk[0].0].namespace(), model, &row, &result_columns, self.dialect()) {
+                            Ok(value) => value,
+                            Err(err) => return Err(error_ext::unknown_database_write_error(path, err.to_string())),
+                        };
+                        let value = value.as_dictionary().unwrap();
+                        let object = match match_index {
+                            Some(match_index) => chunk.iter().map(|(index, _)| &objects[*index]).find(|object| {
+                                match_index.keys().iter().all(|k| value.get(k.as_str()).is_some_and(|v| same_key(v, &object.get_value(k).unwrap())))
+                            }),
+                            None => Some(&objects[chunk[0].0]),
+                        };
+                        let Some(object) = object else {
+                            return Err(error_ext::unknown_database_write_error(path, "cannot match inserted rows to their objects".to_owned()));
+                        };
+                        for (k, v) in value {
+                            if auto_keys.contains(k) {
+                                object.set_value(k, v.clone())?;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn migration_plan(&self, models: Vec<&Model>, reset_database: bool) -> Result<MigrationPlan> {
+        SQLMigration::plan(self.dialect(), self.queryable(), models, reset_database).await
+    }
+
+    pub async fn generate_migration(&self, models: Vec<&Model>, dir: &Path, name: &str) -> Result<Option<MigrationFile>> {
+        MigrationHistory::generate(self.dialect(), self.queryable(), models, dir, name).await
+    }
+
+    pub async fn apply_pending_migrations(&self, dir: &Path) -> Result<Vec<MigrationFile>> {
+        MigrationHistory::apply_pending(self.dialect(), self.queryable(), dir, self.is_transaction()).await
+    }
+
+    pub async fn rollback_migrations(&self, dir: &Path, count: usize) -> Result<Vec<MigrationFile>> {
+        MigrationHistory::rollback(self.dialect(), self.queryable(), dir, count, self.is_transaction()).await
+    }
+
+    pub async fn migration_status(&self, dir: &Path) -> Result<Vec<MigrationStatus>> {
+        MigrationHistory::status(self.dialect(), self.queryable(), dir).await
+    }
+
+    pub fn is_conflicted(&self) -> bool {
+        self.conflicted.load(Ordering::SeqCst)
+    }
+
+    fn record_conflict(&self, err: &quaint_forked::error::Error) -> bool {
+        let retryable = is_retryable(self.dialect(), err);
+        if retryable {
+            self.conflicted.store(true, Ordering::SeqCst);
+        }
+        retryable
+    }
+
     fn handle_err_result(&self, err: quaint_forked::error::Error, path: KeyPath) -> teo_runtime::path::Error {
+        if self.record_conflict(&err) {
+            return error_ext::unknown_database_write_error(path, SQLError::new(SQLErrorKind::TransactionConflict, self.dialect(), err.to_string()).to_string());
+        }
         match err.kind() {
             UniqueConstraintViolation { constraint } => {
                 match constraint {
@@ -211,14 +620,20 @@ impl SQLTransaction {
 #[async_trait]
 impl Transaction for SQLTransaction {
 
+    // a dry run prints the statements it would have run unless silenced
     async fn migrate(&self, models: Vec<&Model>, dry_run: bool, reset_database: bool, silent: bool) -> Result<()> {
-        SQLMigration::migrate(self.dialect(), self.queryable(), models, self).await
+        let plan = SQLMigration::migrate(self.dialect(), self.queryable(), models, dry_run, reset_database).await?;
+        if dry_run && !silent && !plan.is_empty() {
+            print!("{}", plan);
+        }
+        Ok(())
     }
 
     async fn purge(&self, models: Vec<&Model>) -> Result<()> {
         for model in models {
-            let escape = self.dialect().escape();
-            self.conn().execute(QuaintQuery::from(format!("DELETE FROM {escape}{}{escape}", &model.table_name))).await.unwrap();
+            self.conn().execute(QuaintQuery::from(format!("DELETE FROM {}", model.table_name.escape(self.dialect())))).await.map_err(|e| {
+                Error::new(format!("cannot purge table `{}': {}", model.table_name, e))
+            })?;
         }
         Ok(())
     }
@@ -252,19 +667,20 @@ impl Transaction for SQLTransaction {
             return Err(error_ext::object_is_not_saved_thus_cant_be_deleted(path));
         }
         let model = object.model();
-        let r#where = Query::where_from_identifier(object, self.dialect());
-        let stmt = SQL::delete_from(&model.table_name).r#where(r#where).to_string(self.dialect());
+        let mut params = SQLParams::new(self.dialect());
+        let r#where = Query::where_from_identifier(object, self.dialect(), &mut params);
+        let (stmt, params) = SQL::delete_from(&model.table_name).r#where(r#where).params(params).to_statement(self.dialect());
         // println!("see delete stmt: {}", stmt);
-        let result = self.queryable().execute(QuaintQuery::from(stmt)).await;
-        if result.is_err() {
-            return Err(error_ext::unknown_database_write_error(path, format!("{:?}", result.err().unwrap())));
+        let result = self.queryable().execute_raw(&stmt, &params).await;
+        if let Err(err) = result {
+            return Err(self.handle_err_result(err, path));
         } else {
             Ok(())
         }
     }
 
     async fn find_unique(&self, model: &'static Model, finder: &Value, ignore_select_and_include: bool, action: Action, transaction_ctx: transaction::Ctx, req_ctx: Option<Ctx>, path: KeyPath) -> teo_runtime::path::Result<Option<Object>> {
-        let objects = Execution::query_objects(transaction_ctx.namespace(), self.queryable(), model, finder, self.dialect(), action, transaction_ctx, req_ctx, path).await?;
+        let objects = Execution::query_objects(transaction_ctx.namespace(), self.queryable(), &self.conflicted, model, finder, self.dialect(), action, transaction_ctx, req_ctx, path).await?;
         if objects.is_empty() {
             Ok(None)
         } else {
@@ -273,27 +689,27 @@ impl Transaction for SQLTransaction {
     }
 
     async fn find_many(&self, model: &'static Model, finder: &Value, ignore_select_and_include: bool, action: Action, transaction_ctx: transaction::Ctx, req_ctx: Option<Ctx>, path: KeyPath) -> teo_runtime::path::Result<Vec<Object>> {
-        Execution::query_objects(transaction_ctx.namespace(), self.queryable(), model, finder, self.dialect(), action, transaction_ctx, req_ctx, path).await
+        Execution::query_objects(transaction_ctx.namespace(), self.queryable(), &self.conflicted, model, finder, self.dialect(), action, transaction_ctx, req_ctx, path).await
     }
 
     async fn count(&self, model: &'static Model, finder: &Value, transaction_ctx: transaction::Ctx, path: KeyPath) -> teo_runtime::path::Result<Value> {
-        Execution::query_count(transaction_ctx.namespace(), self.queryable(), model, finder, self.dialect(), path).await
+        Execution::query_count(transaction_ctx.namespace(), self.queryable(), &self.conflicted, model, finder, self.dialect(), path).await
     }
 
     async fn count_objects(&self, model: &'static Model, finder: &Value, transaction_ctx: transaction::Ctx, path: KeyPath) -> teo_runtime::path::Result<usize> {
-        Execution::query_count_objects(transaction_ctx.namespace(), self.queryable(), model, finder, self.dialect(), path).await
+        Execution::query_count_objects(transaction_ctx.namespace(), self.queryable(), &self.conflicted, model, finder, self.dialect(), path).await
     }
 
     async fn count_fields(&self, model: &'static Model, finder: &Value, transaction_ctx: transaction::Ctx, path: KeyPath) -> teo_runtime::path::Result<Value> {
-        Execution::query_count_fields(transaction_ctx.namespace(), self.queryable(), model, finder, self.dialect(), path).await
+        Execution::query_count_fields(transaction_ctx.namespace(), self.queryable(), &self.conflicted, model, finder, self.dialect(), path).await
     }
 
     async fn aggregate(&self, model: &'static Model, finder: &Value, transaction_ctx: transaction::Ctx, path: KeyPath) -> teo_runtime::path::Result<Value> {
-        Execution::query_aggregate(transaction_ctx.namespace(), self.queryable(), model, finder, self.dialect(), path).await
+        Execution::query_aggregate(transaction_ctx.namespace(), self.queryable(), &self.conflicted, model, finder, self.dialect(), path).await
     }
 
     async fn group_by(&self, model: &'static Model, finder: &Value, transaction_ctx: transaction::Ctx, path: KeyPath) -> teo_runtime::path::Result<Vec<Value>> {
-        Execution::query_group_by(transaction_ctx.namespace(), self.queryable(), model, finder, self.dialect(), path).await
+        Execution::query_group_by(transaction_ctx.namespace(), self.queryable(), &self.conflicted, model, finder, self.dialect(), path).await
     }
 
     fn is_committed(&self) -> bool {
@@ -301,36 +717,156 @@ impl Transaction for SQLTransaction {
     }
 
     fn is_transaction(&self) -> bool {
-        self.tran.is_some()
+        self.tran.is_some() || self.begun.is_some()
     }
 
     async fn commit(&self) -> Result<()> {
-        if let Some(tran) = &self.tran {
+        if let Some(savepoint) = &self.savepoint {
+            if let Some(stmt) = self.release_savepoint_stmt(savepoint) {
+                self.queryable().raw_cmd(&stmt).await.map_err(|e| Error::new(format!("cannot release savepoint `{}': {}", savepoint, e)))?;
+            }
+        } else if let Some(tran) = &self.tran {
             match tran.commit().await {
                 Ok(()) => (),
-                Err(err) => return Err(Error::new(err.to_string()))
+                Err(err) => {
+                    self.record_conflict(&err);
+                    return Err(Error::new(err.to_string()))
+                }
             }
+        } else if let Some(begun) = &self.begun {
+            self.conn().raw_cmd("COMMIT").await.map_err(|e| {
+                self.record_conflict(&e);
+                Error::new(e.to_string())
+            })?;
+            begun.finished.store(true, Ordering::SeqCst);
         }
         self.committed.store(true, Ordering::SeqCst);
         Ok(())
     }
 
     async fn abort(&self) -> Result<()> {
-        if let Some(tran) = &self.tran {
+        if let Some(savepoint) = &self.savepoint {
+            let stmt = self.rollback_to_savepoint_stmt(savepoint);
+            self.queryable().raw_cmd(&stmt).await.map_err(|e| Error::new(format!("cannot roll back to savepoint `{}': {}", savepoint, e)))?;
+        } else if let Some(tran) = &self.tran {
             match tran.rollback().await {
                 Ok(()) => (),
                 Err(err) => return Err(Error::new(err.to_string()))
             };
+        } else if let Some(begun) = &self.begun {
+            self.conn().raw_cmd("ROLLBACK").await.map_err(|e| Error::new(e.to_string()))?;
+            begun.finished.store(true, Ordering::SeqCst);
         }
         Ok(())
     }
 
+    // inside a transaction, spawning opens a savepoint so the nested unit can fail on its own
     async fn spawn(&self) -> Result<Arc<dyn Transaction>> {
-        Ok(Arc::new(SQLTransaction {
-            dialect: self.dialect,
-            conn: self.conn.clone(),
-            tran: Some(Arc::new(start_owned_transaction(self.conn.clone(), None).await.unwrap())),
-            committed: Arc::new(AtomicBool::new(false)),
-        }))
+        if self.is_transaction() {
+            let name = format!("teo_savepoint_{}", self.savepoint_counter.fetch_add(1, Ordering::SeqCst) + 1);
+            self.queryable().raw_cmd(&self.savepoint_stmt(&name)).await.map_err(|e| {
+                SQLError::new(SQLErrorKind::Connection, self.dialect, format!("cannot create savepoint `{}': {}", name, e))
+            })?;
+            return Ok(Arc::new(SQLTransaction {
+                dialect: self.dialect,
+                conn: self.conn.clone(),
+                tran: self.tran.clone(),
+                committed: Arc::new(AtomicBool::new(false)),
+                savepoint: Some(name),
+                savepoint_counter: self.savepoint_counter.clone(),
+                begun: self.begun.clone(),
+                conflicted: self.conflicted.clone(),
+            }));
+        }
+        let tran = start_owned_transaction(self.conn.clone(), None).await.map_err(|e| {
+            SQLError::new(SQLErrorKind::Connection, self.dialect, format!("cannot start transaction: {}", e))
+        })?;
+        Ok(Arc::new(SQLTransaction::new(self.dialect, self.conn.clone(), Some(Arc::new(tran)))))
+    }
+}
+
+// the update only applies to the version that was read
+fn versioned_where(r#where: String, column_name: &str, current: &str, dialect: SQLDialect) -> String {
+    And(vec![r#where, Query::where_item(column_name.escape(dialect), "=", current)]).to_string(dialect)
+}
+
+// the typed error stays readable through `SQLError::from_error`
+fn version_conflict(dialect: SQLDialect, table_name: &str) -> Error {
+    Error::from(SQLError::new(SQLErrorKind::VersionConflict, dialect, "the record was changed or deleted by another writer").with_table(Some(table_name)))
+}
+
+// decoded keys may come back with another width or precision than the values given
+fn same_key(decoded: &Value, given: &Value) -> bool {
+    match (decoded, given) {
+        (Value::Int(_) | Value::Int64(_), Value::Int(_) | Value::Int64(_)) => key_integer(decoded) == key_integer(given),
+        (Value::Float32(_) | Value::Float(_), Value::Float32(_) | Value::Float(_)) => key_float(decoded) == key_float(given),
+        (Value::DateTime(a), Value::DateTime(b)) => a.timestamp_millis() == b.timestamp_millis(),
+        _ => decoded == given,
+    }
+}
+
+fn key_integer(value: &Value) -> Option<i64> {
+    match value {
+        Value::Int(i) => Some(*i as i64),
+        Value::Int64(i) => Some(*i),
+        _ => None,
+    }
+}
+
+fn key_float(value: &Value) -> Option<f64> {
+    match value {
+        Value::Float32(f) => Some(*f as f64),
+        Value::Float(f) => Some(*f),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use teo_teon::Value;
+    use crate::error::{SQLError, SQLErrorKind};
+    use crate::query::Query;
+    use crate::schema::dialect::SQLDialect;
+    use crate::schema::value::encode::SQLEscape;
+    use crate::stmts::params::SQLParams;
+    use crate::stmts::SQL;
+    use super::{same_key, version_conflict, versioned_where};
+
+    const DIALECTS: [SQLDialect; 4] = [SQLDialect::PostgreSQL, SQLDialect::MySQL, SQLDialect::SQLite, SQLDialect::MSSQL];
+
+    #[test]
+    fn returned_keys_match_across_widths_and_precisions() {
+        assert!(same_key(&Value::Int64(3), &Value::Int(3)));
+        assert!(!same_key(&Value::Int64(3), &Value::Int(4)));
+        assert!(same_key(&Value::Float(0.5), &Value::Float32(0.5)));
+        let given = Utc.timestamp_nanos(1_700_000_000_123_456_789);
+        let decoded = Utc.timestamp_millis_opt(1_700_000_000_123).unwrap();
+        assert!(same_key(&Value::DateTime(decoded), &Value::DateTime(given)));
+        assert!(!same_key(&Value::String("a".to_owned()), &Value::String("b".to_owned())));
+    }
+
+    #[test]
+    fn versioned_updates_compare_the_read_version() {
+        for dialect in DIALECTS {
+            let mut params = SQLParams::new(dialect);
+            let name = params.push_untyped(&Value::String("A".to_owned()));
+            let increment = format!("{} + 1", "version".escape(dialect));
+            let r#where = Query::where_item("id".escape(dialect), "=", &params.push_untyped(&Value::Int(1)));
+            let current = params.push_untyped(&Value::Int(4));
+            let r#where = versioned_where(r#where, "version", &current, dialect);
+            let (sql, _) = SQL::update("users").values(vec![("name", name.as_str()), ("version", increment.as_str())]).r#where(&r#where).params(params).to_statement(dialect);
+            let expected = match dialect {
+                SQLDialect::PostgreSQL => r#"UPDATE "users" SET "name" = $1,"version" = "version" + 1 WHERE "id" = $2 AND "version" = $3;"#,
+                SQLDialect::MySQL => "UPDATE `users` SET `name` = ?,`version` = `version` + 1 WHERE `id` = ? AND `version` = ?;",
+                SQLDialect::SQLite => "UPDATE `users` SET `name` = ?1,`version` = `version` + 1 WHERE `id` = ?2 AND `version` = ?3;",
+                SQLDialect::MSSQL => "UPDATE [users] SET [name] = @P1,[version] = [version] + 1 WHERE [id] = @P2 AND [version] = @P3;",
+            };
+            assert_eq!(sql, expected);
+            let error = version_conflict(dialect, "users");
+            let error = SQLError::from_error(&error).unwrap();
+            assert_eq!(error.kind, SQLErrorKind::VersionConflict);
+            assert_eq!(error.table.as_deref(), Some("users"));
+        }
     }
 }
@@ -0,0 +1,101 @@
+use std::fmt::{Display, Formatter};
+use crate::schema::dialect::SQLDialect;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SQLErrorKind {
+    InvalidURL,
+    Connection,
+    Statement,
+    UnsupportedType,
+    Migration,
+    TransactionConflict,
+    VersionConflict,
+}
+
+impl SQLErrorKind {
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SQLErrorKind::InvalidURL => "invalid url",
+            SQLErrorKind::Connection => "connection error",
+            SQLErrorKind::Statement => "statement error",
+            SQLErrorKind::UnsupportedType => "unsupported type",
+            SQLErrorKind::Migration => "migration error",
+            SQLErrorKind::TransactionConflict => "transaction conflict",
+            SQLErrorKind::VersionConflict => "version conflict",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SQLError {
+    pub kind: SQLErrorKind,
+    pub dialect: SQLDialect,
+    pub message: String,
+    pub table: Option<String>,
+    pub statement: Option<String>,
+}
+
+impl SQLError {
+
+    pub(crate) fn new(kind: SQLErrorKind, dialect: SQLDialect, message: impl Into<String>) -> Self {
+        Self { kind, dialect, message: message.into(), table: None, statement: None }
+    }
+
+    pub(crate) fn statement(dialect: SQLDialect, table: Option<&str>, statement: &str, err: impl Display) -> Self {
+        Self::new(SQLErrorKind::Statement, dialect, err.to_string()).with_table(table).with_statement(statement)
+    }
+
+    pub(crate) fn with_table(mut self, table: Option<&str>) -> Self {
+        self.table = table.map(|t| t.to_owned());
+        self
+    }
+
+    pub(crate) fn with_statement(mut self, statement: &str) -> Self {
+        self.statement = Some(statement.to_owned());
+        self
+    }
+
+    // the typed error behind a runtime error converted from it
+    pub fn from_error(error: &teo_result::Error) -> Option<&SQLError> {
+        error.platform_native_object::<SQLError>()
+    }
+}
+
+impl Display for SQLError {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} ({:?}", self.kind.as_str(), self.message, self.dialect)?;
+        if let Some(table) = &self.table {
+            write!(f, ", table `{}'", table)?;
+        }
+        if let Some(statement) = &self.statement {
+            write!(f, ", statement `{}'", statement)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl std::error::Error for SQLError { }
+
+// serialization failures, deadlocks and lock timeouts, the transaction can succeed when replayed
+pub(crate) fn is_retryable(dialect: SQLDialect, err: &quaint_forked::error::Error) -> bool {
+    let Some(code) = err.original_code() else {
+        return false;
+    };
+    match dialect {
+        SQLDialect::PostgreSQL => code == "40001" || code == "40P01",
+        SQLDialect::MySQL => code == "1213" || code == "1205",
+        SQLDialect::MSSQL => code == "1205",
+        SQLDialect::SQLite => code == "5" || code == "517",
+    }
+}
+
+// the message is kept for display, the error itself travels along and is read back with `SQLError::from_error`
+impl From<SQLError> for teo_result::Error {
+    fn from(value: SQLError) -> Self {
+        let mut error = teo_result::Error::new(value.to_string());
+        error.assign_platform_native_object(value);
+        error
+    }
+}
@@ -1,17 +1,19 @@
 use std::borrow::Cow;
-use array_tool::vec::Uniq;
 use std::backtrace::Backtrace;
-use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use async_recursion::async_recursion;
 use indexmap::IndexMap;
 use key_path::KeyPath;
 use quaint_forked::prelude::{Queryable, ResultRow};
-use quaint_forked::ast::{Query as QuaintQuery};
 use teo_parser::r#type::Type;
-use crate::query::Query;
+use teo_result::Result;
+use serde_json::Value as JsonValue;
+use crate::error::is_retryable;
+use crate::query::{Query, RELATION_COUNT_PREFIX, ROW_NUMBER_COLUMN};
 use crate::schema::dialect::SQLDialect;
 use crate::schema::value::decode::RowDecoder;
-use crate::schema::value::encode::{SQLEscape, ToSQLString, ToWrapped};
+use crate::schema::value::encode::{SQLEscape, ToWrapped};
+use crate::stmts::params::SQLParams;
 use teo_runtime::action::Action;
 use teo_runtime::connection::transaction;
 use teo_runtime::model::field::column_named::ColumnNamed;
@@ -31,16 +33,27 @@ pub(crate) struct Execution { }
 
 impl Execution {
 
-    pub(crate) fn row_to_value(namespace: &Namespace, model: &Model, row: &ResultRow, columns: &Vec<String>, dialect: SQLDialect) -> Value {
-        Value::Dictionary(columns.iter().filter_map(|column_name| {
+    pub(crate) fn row_to_value(namespace: &Namespace, model: &Model, row: &ResultRow, columns: &Vec<String>, dialect: SQLDialect) -> Result<Value> {
+        let mut counts: IndexMap<String, Value> = IndexMap::new();
+        let mut retval: IndexMap<String, Value> = columns.iter().filter_map(|column_name| {
             if let Some(field) = model.field_with_column_name(column_name) {
                 if field.auto_increment && dialect == SQLDialect::PostgreSQL {
-                    Some((field.name().to_owned(), RowDecoder::decode_serial(field.is_optional(), row, column_name)))
+                    Some(Ok((field.name().to_owned(), RowDecoder::decode_serial(field.is_optional(), row, column_name))))
                 } else {
-                    Some((field.name().to_owned(), RowDecoder::decode(field.r#type(), field.is_optional(), row, column_name, dialect)))
+                    Some(RowDecoder::decode(field.r#type(), field.is_optional(), row, column_name, dialect).map(|v| (field.name().to_owned(), v)))
                 }
             } else if let Some(property) = model.property_with_column_name(column_name) {
-                Some((property.column_name().to_owned(), RowDecoder::decode(property.r#type(), property.is_optional(), row, column_name, dialect)))
+                Some(RowDecoder::decode(property.r#type(), property.is_optional(), row, column_name, dialect).map(|v| (property.column_name().to_owned(), v)))
+            } else if column_name.starts_with("_teo_") { // window function helpers
+                None
+            } else if let Some(relation) = model.relation(column_name) {
+                let (opposite_model, _) = namespace.opposite_relation(relation);
+                let json = RowDecoder::decode_json_column(row.get(column_name.as_str()));
+                Some(Self::json_to_values(namespace, opposite_model, &json).map(|v| (relation.name().to_owned(), Value::Array(v))))
+            } else if let Some(relation_name) = column_name.strip_prefix(RELATION_COUNT_PREFIX).and_then(|n| n.strip_prefix(".")) {
+                let count = row.get(column_name.as_str()).and_then(|v| v.as_i64()).unwrap_or(0);
+                counts.insert(relation_name.to_owned(), Value::Int64(count));
+                None
             } else if column_name.contains(".") {
                 let names: Vec<&str> = column_name.split(".").collect();
                 let relation_name = names[0];
@@ -51,15 +64,19 @@ impl Execution {
                     let relation = model.relation(relation_name).unwrap();
                     let opposite_model = namespace.model_at_path(&relation.model_path()).unwrap();
                     let field = opposite_model.field(field_name).unwrap();
-                    Some((column_name.to_owned(), RowDecoder::decode(field.r#type(), field.is_optional(), row, column_name, dialect)))
+                    Some(RowDecoder::decode(field.r#type(), field.is_optional(), row, column_name, dialect).map(|v| (column_name.to_owned(), v)))
                 }
             } else {
                 panic!("Unhandled key {}.", column_name);
             }
-        }).collect())
+        }).collect::<Result<IndexMap<String, Value>>>()?;
+        if !counts.is_empty() {
+            retval.insert(RELATION_COUNT_PREFIX.to_owned(), Value::Dictionary(counts));
+        }
+        Ok(Value::Dictionary(retval))
     }
 
-    fn row_to_aggregate_value(model: &Model, row: &ResultRow, columns: &Vec<String>, dialect: SQLDialect) -> Value {
+    fn row_to_aggregate_value(model: &Model, row: &ResultRow, columns: &Vec<String>, dialect: SQLDialect) -> Result<Value> {
         let mut retval: IndexMap<String, Value> = IndexMap::new();
         for column in columns {
             let result_key = column.as_str();
@@ -74,24 +91,32 @@ impl Execution {
                     let count: i64 = row.get(result_key).unwrap().as_i64().unwrap();
                     retval.get_mut(group).unwrap().as_dictionary_mut().unwrap().insert(field_name.to_string(), teon!(count));
                 } else if group == "_avg" || group == "_sum" { // force f64
-                    let v = RowDecoder::decode(&Type::Float, true, &row, result_key, dialect);
+                    let v = RowDecoder::decode(&Type::Float, true, &row, result_key, dialect)?;
                     retval.get_mut(group).unwrap().as_dictionary_mut().unwrap().insert(field_name.to_string(), v);
                 } else { // field type
                     let field = model.field(field_name).unwrap();
-                    let v = RowDecoder::decode(field.r#type(), true, &row, result_key, dialect);
+                    let v = RowDecoder::decode(field.r#type(), true, &row, result_key, dialect)?;
                     retval.get_mut(group).unwrap().as_dictionary_mut().unwrap().insert(field_name.to_string(), v);
                 }
             } else if let Some(field) = model.field_with_column_name(result_key) {
-                retval.insert(field.name().to_owned(), RowDecoder::decode(field.r#type(), field.is_optional(), row, result_key, dialect));
+                retval.insert(field.name().to_owned(), RowDecoder::decode(field.r#type(), field.is_optional(), row, result_key, dialect)?);
             } else if let Some(property) = model.property(result_key) {
-                retval.insert(property.name().to_owned(), RowDecoder::decode(property.r#type(), property.is_optional(), row, result_key, dialect));
+                retval.insert(property.name().to_owned(), RowDecoder::decode(property.r#type(), property.is_optional(), row, result_key, dialect)?);
             }
         }
-        Value::Dictionary(retval)
+        Ok(Value::Dictionary(retval))
     }
 
-    pub(crate) async fn query_objects<'a>(namespace: &Namespace, conn: &'a dyn Queryable, model: &'static Model, finder: &'a Value, dialect: SQLDialect, action: Action, transaction_ctx: transaction::Ctx, req_ctx: Option<request::Ctx>, path: KeyPath) -> teo_runtime::path::Result<Vec<Object>> {
-        let values = Self::query(namespace, conn, model, finder, dialect, path).await?;
+    // retryable failures mark the transaction so `run_transaction` can replay it
+    fn find_error(err: quaint_forked::error::Error, dialect: SQLDialect, conflicted: &AtomicBool, path: KeyPath) -> teo_runtime::path::Error {
+        if is_retryable(dialect, &err) {
+            conflicted.store(true, Ordering::SeqCst);
+        }
+        error_ext::unknown_database_find_error(path, format!("{:?}", err))
+    }
+
+    pub(crate) async fn query_objects<'a>(namespace: &Namespace, conn: &'a dyn Queryable, conflicted: &AtomicBool, model: &'static Model, finder: &'a Value, dialect: SQLDialect, action: Action, transaction_ctx: transaction::Ctx, req_ctx: Option<request::Ctx>, path: KeyPath) -> teo_runtime::path::Result<Vec<Object>> {
+        let values = Self::query(namespace, conn, conflicted, model, finder, dialect, path).await?;
         let select = finder.as_dictionary().unwrap().get("select");
         let include = finder.as_dictionary().unwrap().get("include");
         let mut results = vec![];
@@ -104,61 +129,52 @@ impl Execution {
     }
 
     #[async_recursion]
-    async fn query_internal(namespace: &Namespace, conn: &dyn Queryable, model: &Model, value: &Value, dialect: SQLDialect, additional_where: Option<String>, additional_left_join: Option<String>, join_table_results: Option<Vec<String>>, force_negative_take: bool, additional_distinct: Option<Vec<String>>, path: KeyPath) -> teo_runtime::path::Result<Vec<Value>> {
-        let _select = value.get("select");
+    async fn query_internal(namespace: &Namespace, conn: &dyn Queryable, conflicted: &AtomicBool, model: &Model, value: &Value, dialect: SQLDialect, additional_where: Option<String>, additional_left_join: Option<String>, join_table_results: Option<Vec<String>>, partition: Option<Vec<String>>, mut params: SQLParams, path: KeyPath) -> teo_runtime::path::Result<Vec<Value>> {
         let include = value.get("include");
-        let original_distinct = value.get("distinct").map(|v| if v.as_array().unwrap().is_empty() { None } else { Some(v.as_array().unwrap()) }).flatten();
-        let distinct = Self::merge_distinct(original_distinct, additional_distinct);
-        let skip = value.get("skip");
-        let take = value.get("take");
-        let should_in_memory_take_skip = distinct.is_some() && (skip.is_some() || take.is_some());
-        let value_for_build = if should_in_memory_take_skip {
-            Self::without_paging_and_skip_take(value)
+        let distinct = Query::distinct_columns(model, value, dialect, None).map_err(|err| {
+            error_ext::unknown_database_find_error(path.clone(), err.to_string())
+        })?;
+        let paged = ["skip", "take", "pageSize", "pageNumber"].iter().any(|k| value.get(k).is_some());
+        // included rows are paged per parent, distinct rows are picked before paging
+        let stmt = if !distinct.is_empty() || (paged && partition.is_some()) {
+            Query::build_windowed(namespace, model, value, dialect, additional_where, additional_left_join, join_table_results, &partition.unwrap_or_default(), &distinct, &mut params)?
         } else {
-            Cow::Borrowed(value)
+            Query::build(namespace, model, value, dialect, additional_where, additional_left_join, join_table_results, false, &mut params)?
         };
-        let stmt = Query::build(namespace, model, value_for_build.as_ref(), dialect, additional_where, additional_left_join, join_table_results, force_negative_take)?;
         //println!("see sql query stmt: {}", &stmt);
+        let (stmt, values) = params.finish(stmt);
         let reverse = Input::has_negative_take(value);
-        let rows = match conn.query(QuaintQuery::from(stmt)).await {
+        let rows = match conn.query_raw(&stmt, &values).await {
             Ok(rows) => rows,
             Err(err) => {
-                return Err(error_ext::unknown_database_find_error(path.clone(), format!("{:?}", err)));
+                return Err(Self::find_error(err, dialect, conflicted, path.clone()));
             }
         };
         if rows.is_empty() {
             return Ok(vec![])
         }
         let columns = rows.columns().clone();
-        let mut results = rows.into_iter().map(|row| Self::row_to_value(namespace, model, &row, &columns, dialect)).collect::<Vec<Value>>();
+        let mut results = rows.into_iter().map(|row| Self::row_to_value(namespace, model, &row, &columns, dialect)).collect::<Result<Vec<Value>>>().map_err(|err| {
+            error_ext::unknown_database_find_error(path.clone(), err.to_string())
+        })?;
         if reverse {
             results.reverse();
         }
-        if let Some(distinct) = distinct {
-            let distinct_keys = distinct.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
-            results = results.unique_via(|a, b| {
-                Self::sub_hashmap(a, &distinct_keys) == Self::sub_hashmap(b, &distinct_keys)
-            });
-        }
-        if should_in_memory_take_skip {
-            let skip = skip.map(|s| s.as_int64().unwrap()).unwrap_or(0) as usize;
-            let take = take.map(|s| s.as_int64().unwrap().abs() as u64).unwrap_or(0) as usize;
-            results = results.into_iter().enumerate().filter(|(i, _r)| {
-                *i >= skip && *i < (skip + take)
-            }).map(|(_i, r)| r.clone()).collect();
-            if reverse {
-                results.reverse();
-            }
+        if Query::loads_relations_with_join(value) {
+            return Ok(results);
         }
         if let Some(include) = include.map(|i| i.as_dictionary().unwrap()) {
             for (key, value) in include {
-                let skip = value.as_dictionary().map(|m| m.get("skip")).flatten().map(|v| v.as_int64().unwrap());
-                let take = value.as_dictionary().map(|m| m.get("take")).flatten().map(|v| v.as_int64().unwrap());
-                let take_abs = take.map(|t| t.abs() as u64);
-                let negative_take = take.map(|v| v.is_negative()).unwrap_or(false);
-                let inner_distinct = value.as_dictionary().map(|m| m.get("distinct")).flatten().map(|v| if v.as_array().unwrap().is_empty() { None } else { Some(v.as_array().unwrap()) }).flatten();
+                if key.as_str() == RELATION_COUNT_PREFIX {
+                    continue;
+                }
                 let relation = model.relation(key).unwrap();
                 let (opposite_model, _) = namespace.opposite_relation(relation);
+                let nested_query = if value.is_dictionary() {
+                    Cow::Borrowed(value)
+                } else {
+                    Cow::Owned(teon!({}))
+                };
                 if !relation.has_join_table() {
                     let fields = relation.fields();
                     let opposite_fields = relation.references();
@@ -167,29 +183,25 @@ impl Execution {
                     } else {
                         opposite_fields.iter().map(|f| opposite_model.field(f).unwrap().column_name().escape(dialect)).collect::<Vec<String>>().join(",").to_wrapped()
                     };
+                    let mut params = SQLParams::new(dialect);
                     let values = if opposite_fields.len() == 1 {
                         // in a (?,?,?,?,?) format
                         let field_name = fields.get(0).unwrap();
                         results.iter().map(|v| {
-                            ToSQLString::to_string(&v.as_dictionary().unwrap().get(*field_name).unwrap(), dialect)
+                            params.push_untyped(v.as_dictionary().unwrap().get(*field_name).unwrap())
                         }).collect::<Vec<String>>().join(",").to_wrapped()
                     } else {
                         // in a (VALUES (?,?),(?,?)) format
                         format!("(VALUES {})", results.iter().map(|o| {
-                            fields.iter().map(|f| ToSQLString::to_string(&o.as_dictionary().unwrap().get(*f).unwrap(), dialect)).collect::<Vec<String>>().join(",").to_wrapped()
+                            fields.iter().map(|f| params.push_untyped(o.as_dictionary().unwrap().get(*f).unwrap())).collect::<Vec<String>>().join(",").to_wrapped()
                         }).collect::<Vec<String>>().join(","))
                     };
                     let where_addition = Query::where_item(&names, "IN", &values);
-                    let nested_query = if value.is_dictionary() {
-                        Self::without_paging_and_skip_take_distinct(value)
-                    } else {
-                        Cow::Owned(teon!({}))
-                    };
-                    let included_values = Self::query_internal(namespace, conn, opposite_model, &nested_query, dialect, Some(where_addition), None, None, negative_take, None, path.clone()).await?;
+                    let nested_query = Self::with_selected_keys(nested_query, &opposite_fields);
+                    let partition = opposite_fields.iter().map(|f| opposite_model.field(f).unwrap().column_name().escape(dialect)).collect();
+                    let included_values = Self::query_internal(namespace, conn, conflicted, opposite_model, &nested_query, dialect, Some(where_addition), None, None, Some(partition), params, path.clone()).await?;
                     // println!("see included: {:?}", included_values);
                     for result in results.iter_mut() {
-                        let mut skipped = 0;
-                        let mut taken = 0;
                         if relation.is_vec {
                             result.as_dictionary_mut().unwrap().insert(relation.name().to_owned(), Value::Array(vec![]));
                         }
@@ -206,22 +218,10 @@ impl Execution {
                                 }
                             }
                             if matched {
-                                if (skip.is_none() || skip.unwrap() <= skipped) && (take.is_none() || taken < take_abs.unwrap()) {
-                                    if result.get(relation.name()).is_none() {
-                                        result.as_dictionary_mut().unwrap().insert(relation.name().to_owned(), Value::Array(vec![]));
-                                    }
-                                    if negative_take {
-                                        result.as_dictionary_mut().unwrap().get_mut(relation.name()).unwrap().as_array_mut().unwrap().insert(0, included_value.clone());
-                                    } else {
-                                        result.as_dictionary_mut().unwrap().get_mut(relation.name()).unwrap().as_array_mut().unwrap().push(included_value.clone());
-                                    }
-                                    taken += 1;
-                                    if take.is_some() && (taken >= take_abs.unwrap()) {
-                                        break;
-                                    }
-                                } else {
-                                    skipped += 1;
+                                if result.get(relation.name()).is_none() {
+                                    result.as_dictionary_mut().unwrap().insert(relation.name().to_owned(), Value::Array(vec![]));
                                 }
+                                result.as_dictionary_mut().unwrap().get_mut(relation.name()).unwrap().as_array_mut().unwrap().push(included_value.clone());
                             }
                         }
                     }
@@ -242,45 +242,35 @@ impl Execution {
                     } else {
                         through_relation.fields().iter().map(|f| format!("j.{}", through_table.field(f).unwrap().column_name().escape(dialect))).collect::<Vec<String>>().join(",").to_wrapped()
                     };
+                    let mut params = SQLParams::new(dialect);
                     let values = if through_relation.len() == 1 { // (?,?,?,?,?) format
                         let references = through_relation.references();
                         let field_name = references.get(0).unwrap();
                         results.iter().map(|v| {
-                            ToSQLString::to_string(&v.as_dictionary().unwrap().get(*field_name).unwrap(), dialect)
+                            params.push_untyped(v.as_dictionary().unwrap().get(*field_name).unwrap())
                         }).collect::<Vec<String>>().join(",").to_wrapped()
                     } else { // (VALUES (?,?),(?,?)) format
                         let pairs = results.iter().map(|o| {
-                            through_relation.references().iter().map(|f| ToSQLString::to_string(&o.as_dictionary().unwrap().get(*f).unwrap(), dialect)).collect::<Vec<String>>().join(",").to_wrapped()
+                            through_relation.references().iter().map(|f| params.push_untyped(o.as_dictionary().unwrap().get(*f).unwrap())).collect::<Vec<String>>().join(",").to_wrapped()
                         }).collect::<Vec<String>>().join(",");
                         format!("(VALUES {})", pairs)
                     };
                     let where_addition = Query::where_item(&names, "IN", &values);
-                    let nested_query = if value.is_dictionary() {
-                        Self::without_paging_and_skip_take(value)
-                    } else {
-                        Cow::Owned(teon!({}))
-                    };
                     let join_table_results = through_relation.iter().map(|(f, r)| {
                         let through_column_name = through_model.field(f).unwrap().column_name().to_string();
                         if dialect == SQLDialect::PostgreSQL {
                             format!("j.{} AS \"{}.{}\"", through_column_name.as_str().escape(dialect), opposite_relation.unwrap().name(), r)
                         } else {
-                            format!("j.{} AS `{}.{}`", through_column_name, opposite_relation.unwrap().name(), r)
+                            format!("j.{} AS {}", through_column_name, format!("{}.{}", opposite_relation.unwrap().name(), r).escape(dialect))
                         }
                     }).collect();
-                    let additional_inner_distinct = if inner_distinct.is_some() {
-                        Some(through_relation.iter().map(|(_f, r)| {
-                            format!("{}.{}", opposite_relation.unwrap().name(), r)
-                        }).collect())
-                    } else {
-                        None
-                    };
-                    let included_values = Self::query_internal(namespace, conn, opposite_model, &nested_query, dialect, Some(where_addition), Some(left_join), Some(join_table_results), negative_take, additional_inner_distinct, path.clone()).await?;
+                    let partition = through_relation.iter().map(|(_f, r)| {
+                        format!("{}.{}", opposite_relation.unwrap().name(), r).escape(dialect)
+                    }).collect();
+                    let included_values = Self::query_internal(namespace, conn, conflicted, opposite_model, &nested_query, dialect, Some(where_addition), Some(left_join), Some(join_table_results), Some(partition), params, path.clone()).await?;
                     // println!("see included {:?}", included_values);
                     for result in results.iter_mut() {
                         result.as_dictionary_mut().unwrap().insert(relation.name().to_owned(), Value::Array(vec![]));
-                        let mut skipped = 0;
-                        let mut taken = 0;
                         for included_value in included_values.iter() {
                             let mut matched = true;
                             for (_field, reference) in through_relation.iter() {
@@ -295,19 +285,7 @@ impl Execution {
                                 }
                             }
                             if matched {
-                                if (skip.is_none() || skip.unwrap() <= skipped) && (take.is_none() || taken < take_abs.unwrap()) {
-                                    if negative_take {
-                                        result.as_dictionary_mut().unwrap().get_mut(relation.name()).unwrap().as_array_mut().unwrap().insert(0, included_value.clone());
-                                    } else {
-                                        result.as_dictionary_mut().unwrap().get_mut(relation.name()).unwrap().as_array_mut().unwrap().push(included_value.clone());
-                                    }
-                                    taken += 1;
-                                    if take.is_some() && (taken >= take_abs.unwrap()) {
-                                        break;
-                                    }
-                                } else {
-                                    skipped += 1;
-                                }
+                                result.as_dictionary_mut().unwrap().get_mut(relation.name()).unwrap().as_array_mut().unwrap().push(included_value.clone());
                             }
                         }
                     }
@@ -317,62 +295,115 @@ impl Execution {
         Ok(results)
     }
 
-    pub(crate) async fn query(namespace: &Namespace, conn: &dyn Queryable, model: &Model, finder: &Value, dialect: SQLDialect, path: KeyPath) -> teo_runtime::path::Result<Vec<Value>> {
-       Self::query_internal(namespace, conn, model, finder, dialect, None, None, None, false, None, path).await
+    // rows of a relation loaded with JSON aggregation, keyed by column and relation names
+    fn json_to_values(namespace: &Namespace, model: &Model, json: &JsonValue) -> Result<Vec<Value>> {
+        let Some(rows) = json.as_array() else {
+            return Ok(vec![]);
+        };
+        let mut rows: Vec<&serde_json::Map<String, JsonValue>> = rows.iter().filter_map(|r| r.as_object()).collect();
+        rows.sort_by_key(|r| r.get(ROW_NUMBER_COLUMN).and_then(|n| n.as_i64()).unwrap_or(0));
+        rows.into_iter().map(|row| {
+            Ok(Value::Dictionary(row.iter().filter_map(|(key, value)| {
+                if let Some(field) = model.field_with_column_name(key) {
+                    Some(RowDecoder::decode_json(field.r#type(), value).map(|v| (field.name().to_owned(), v)))
+                } else if let Some(property) = model.property_with_column_name(key) {
+                    Some(RowDecoder::decode_json(property.r#type(), value).map(|v| (property.column_name().to_owned(), v)))
+                } else if let Some(relation) = model.relation(key) {
+                    let (opposite_model, _) = namespace.opposite_relation(relation);
+                    Some(Self::json_to_values(namespace, opposite_model, value).map(|v| (relation.name().to_owned(), Value::Array(v))))
+                } else {
+                    None
+                }
+            }).collect::<Result<IndexMap<String, Value>>>()?))
+        }).collect()
     }
 
-    pub(crate) async fn query_aggregate(namespace: &Namespace, conn: &dyn Queryable, model: &Model, finder: &Value, dialect: SQLDialect, path: KeyPath) -> teo_runtime::path::Result<Value> {
-        let stmt = Query::build_for_aggregate(namespace, model, finder, dialect)?;
-        match conn.query(QuaintQuery::from(&*stmt)).await {
+    // included rows are matched to their parents by these keys, so a narrowing select must keep them
+    fn with_selected_keys<'a>(value: Cow<'a, Value>, keys: &Vec<&str>) -> Cow<'a, Value> {
+        let Some(select) = value.get("select").and_then(|s| s.as_dictionary()) else {
+            return value;
+        };
+        let mut select = select.clone();
+        if select.values().any(|v| v.as_bool() == Some(true)) {
+            for key in keys {
+                select.insert(key.to_string(), Value::Bool(true));
+            }
+        } else {
+            for key in keys {
+                select.shift_remove(*key);
+            }
+        }
+        let mut map = value.as_dictionary().unwrap().clone();
+        map.insert("select".to_owned(), Value::Dictionary(select));
+        Cow::Owned(Value::Dictionary(map))
+    }
+
+    pub(crate) async fn query(namespace: &Namespace, conn: &dyn Queryable, conflicted: &AtomicBool, model: &Model, finder: &Value, dialect: SQLDialect, path: KeyPath) -> teo_runtime::path::Result<Vec<Value>> {
+       Self::query_internal(namespace, conn, conflicted, model, finder, dialect, None, None, None, None, SQLParams::new(dialect), path).await
+    }
+
+    pub(crate) async fn query_aggregate(namespace: &Namespace, conn: &dyn Queryable, conflicted: &AtomicBool, model: &Model, finder: &Value, dialect: SQLDialect, path: KeyPath) -> teo_runtime::path::Result<Value> {
+        let mut params = SQLParams::new(dialect);
+        let stmt = Query::build_for_aggregate(namespace, model, finder, dialect, &mut params)?;
+        let (stmt, values) = params.finish(stmt);
+        match conn.query_raw(&stmt, &values).await {
             Ok(result_set) => {
                 let columns = result_set.columns().clone();
                 let result = result_set.into_iter().next().unwrap();
-                Ok(Self::row_to_aggregate_value(model, &result, &columns, dialect))
+                Self::row_to_aggregate_value(model, &result, &columns, dialect).map_err(|err| {
+                    error_ext::unknown_database_find_error(path, err.to_string())
+                })
             },
             Err(err) => {
-                return Err(error_ext::unknown_database_find_error(path, format!("{:?}", err)));
+                return Err(Self::find_error(err, dialect, conflicted, path));
             }
         }
     }
 
-    pub(crate) async fn query_group_by(namespace: &Namespace, conn: &dyn Queryable, model: &Model, finder: &Value, dialect: SQLDialect, path: KeyPath) -> teo_runtime::path::Result<Vec<Value>> {
-        let stmt = Query::build_for_group_by(namespace, model, finder, dialect)?;
-        let rows = match conn.query(QuaintQuery::from(stmt)).await {
+    pub(crate) async fn query_group_by(namespace: &Namespace, conn: &dyn Queryable, conflicted: &AtomicBool, model: &Model, finder: &Value, dialect: SQLDialect, path: KeyPath) -> teo_runtime::path::Result<Vec<Value>> {
+        let mut params = SQLParams::new(dialect);
+        let stmt = Query::build_for_group_by(namespace, model, finder, dialect, &mut params)?;
+        let (stmt, values) = params.finish(stmt);
+        let rows = match conn.query_raw(&stmt, &values).await {
             Ok(rows) => rows,
             Err(err) => {
-                return Err(error_ext::unknown_database_find_error(path.clone(), format!("{:?}", err)));
+                return Err(Self::find_error(err, dialect, conflicted, path.clone()));
             }
         };
         let columns = rows.columns().clone();
-        Ok(rows.into_iter().map(|r| {
+        rows.into_iter().map(|r| {
             Self::row_to_aggregate_value(model, &r, &columns, dialect)
-        }).collect::<Vec<Value>>())
+        }).collect::<Result<Vec<Value>>>().map_err(|err| {
+            error_ext::unknown_database_find_error(path, err.to_string())
+        })
     }
 
-    pub(crate) async fn query_count(namespace: &Namespace, conn: &dyn Queryable, model: &Model, finder: &Value, dialect: SQLDialect, path: KeyPath) -> teo_runtime::path::Result<Value> {
+    pub(crate) async fn query_count(namespace: &Namespace, conn: &dyn Queryable, conflicted: &AtomicBool, model: &Model, finder: &Value, dialect: SQLDialect, path: KeyPath) -> teo_runtime::path::Result<Value> {
         if finder.get("select").is_some() {
-            Self::query_count_fields(namespace, conn, model, finder, dialect, path).await
+            Self::query_count_fields(namespace, conn, conflicted, model, finder, dialect, path).await
         } else {
-            let result = Self::query_count_objects(namespace, conn, model, finder, dialect, path).await?;
+            let result = Self::query_count_objects(namespace, conn, conflicted, model, finder, dialect, path).await?;
             Ok(Value::Int64(result as i64))
         }
     }
 
-    pub(crate) async fn query_count_objects(namespace: &Namespace, conn: &dyn Queryable, model: &Model, finder: &Value, dialect: SQLDialect, path: KeyPath) -> teo_runtime::path::Result<usize> {
-        let stmt = Query::build_for_count(namespace, model, finder, dialect, None, None, None, false)?;
-        match conn.query(QuaintQuery::from(stmt)).await {
+    pub(crate) async fn query_count_objects(namespace: &Namespace, conn: &dyn Queryable, conflicted: &AtomicBool, model: &Model, finder: &Value, dialect: SQLDialect, path: KeyPath) -> teo_runtime::path::Result<usize> {
+        let mut params = SQLParams::new(dialect);
+        let stmt = Query::build_for_count(namespace, model, finder, dialect, None, None, None, false, &mut params)?;
+        let (stmt, values) = params.finish(stmt);
+        match conn.query_raw(&stmt, &values).await {
             Ok(result) => {
                 let result = result.into_iter().next().unwrap();
                 let count: i64 = result.into_iter().next().unwrap().as_i64().unwrap();
                 Ok(count as usize)
             },
             Err(err) => {
-                return Err(error_ext::unknown_database_find_error(path.clone(), format!("{:?}", err)));
+                return Err(Self::find_error(err, dialect, conflicted, path.clone()));
             }
         }
     }
 
-    pub(crate) async fn query_count_fields(namespace: &Namespace, conn: &dyn Queryable, model: &Model, finder: &Value, dialect: SQLDialect, path: KeyPath) -> teo_runtime::path::Result<Value> {
+    pub(crate) async fn query_count_fields(namespace: &Namespace, conn: &dyn Queryable, conflicted: &AtomicBool, model: &Model, finder: &Value, dialect: SQLDialect, path: KeyPath) -> teo_runtime::path::Result<Value> {
         let new_finder = Value::Dictionary(finder.as_dictionary().unwrap().iter().map(|(k, v)| {
             if k.as_str() == "select" {
                 ("_count".to_owned(), v.clone())
@@ -380,64 +411,7 @@ impl Execution {
                 (k.to_owned(), v.clone())
             }
         }).collect());
-        let aggregate_value = Self::query_aggregate(namespace, conn, model, &new_finder, dialect, path).await?;
+        let aggregate_value = Self::query_aggregate(namespace, conn, conflicted, model, &new_finder, dialect, path).await?;
         Ok(aggregate_value.get("_count").unwrap().clone())
     }
-
-    fn without_paging_and_skip_take(value: &Value) -> Cow<Value> {
-        let map = value.as_dictionary().unwrap();
-        if map.contains_key("take") || map.contains_key("skip") || map.contains_key("pageSize") || map.contains_key("pageNumber") {
-            let mut map = map.clone();
-            map.remove("take");
-            map.remove("skip");
-            map.remove("pageSize");
-            map.remove("pageNumber");
-            Cow::Owned(Value::Dictionary(map))
-        } else {
-            Cow::Borrowed(value)
-        }
-    }
-
-    fn without_paging_and_skip_take_distinct(value: &Value) -> Cow<Value> {
-        let map = value.as_dictionary().unwrap();
-        if map.contains_key("take") || map.contains_key("skip") || map.contains_key("pageSize") || map.contains_key("pageNumber") {
-            let mut map = map.clone();
-            map.remove("take");
-            map.remove("skip");
-            map.remove("pageSize");
-            map.remove("pageNumber");
-            map.remove("distinct");
-            Cow::Owned(Value::Dictionary(map))
-        } else {
-            Cow::Borrowed(value)
-        }
-    }
-
-    fn sub_hashmap(value: &Value, keys: &Vec<&str>) -> HashMap<String, Value> {
-        let map = value.as_dictionary().unwrap();
-        let mut retval = HashMap::new();
-        for key in keys {
-            retval.insert(key.to_string(), map.get(*key).cloned().unwrap_or(Value::Null));
-        }
-        retval
-    }
-
-    fn merge_distinct(value1: Option<&Vec<Value>>, value2: Option<Vec<String>>) -> Option<Vec<String>> {
-        let mut result: Vec<String> = vec![];
-        if let Some(value1) = value1 {
-            for v in value1 {
-                result.push(v.as_str().unwrap().to_string());
-            }
-        }
-        if let Some(value2) = value2 {
-            for v in value2 {
-                result.push(v.to_string())
-            }
-        }
-        if result.is_empty() {
-            None
-        } else {
-            Some(result)
-        }
-    }
 }
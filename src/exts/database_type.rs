@@ -3,10 +3,13 @@ use teo_runtime::database::mysql::r#type::MySQLType;
 use teo_runtime::database::postgres::r#type::PostgreSQLType;
 use teo_runtime::database::r#type::DatabaseType;
 use teo_runtime::database::sqlite::r#type::SQLiteType;
+use crate::schema::dialect::SQLDialect;
 
 pub trait DatabaseTypeToSQLString {
 
     fn to_sql_string(&self) -> String;
+
+    fn to_dialect_sql_string(&self, dialect: SQLDialect) -> String;
 }
 
 impl DatabaseTypeToSQLString for DatabaseType {
@@ -20,6 +23,13 @@ impl DatabaseTypeToSQLString for DatabaseType {
             DatabaseType::MongoDBType(_) => panic!(),
         }
     }
+
+    fn to_dialect_sql_string(&self, dialect: SQLDialect) -> String {
+        match (dialect, self) {
+            (SQLDialect::MSSQL, DatabaseType::MySQLType(t)) => to_mssql_string(t),
+            _ => self.to_sql_string(),
+        }
+    }
 }
 
 fn to_mysql_string(t: &MySQLType) -> String {
@@ -88,6 +98,30 @@ fn to_mysql_string(t: &MySQLType) -> String {
     }
 }
 
+// SQL Server columns are described with the MySQL type family
+fn to_mssql_string(t: &MySQLType) -> String {
+    match t {
+        MySQLType::VarChar(len) => format!("NVARCHAR({})", len),
+        MySQLType::Char(len) => format!("NCHAR({})", len),
+        MySQLType::Text | MySQLType::TinyText | MySQLType::MediumText | MySQLType::LongText | MySQLType::Json => "NVARCHAR(MAX)".to_string(),
+        MySQLType::Enum(_) => "NVARCHAR(255)".to_string(),
+        MySQLType::Bit(_) => "BIT".to_string(),
+        MySQLType::TinyInt(_, _) => "TINYINT".to_string(),
+        MySQLType::SmallInt(_, _) | MySQLType::Year => "SMALLINT".to_string(),
+        MySQLType::MediumInt(_, _) | MySQLType::Int(_, _) => "INT".to_string(),
+        MySQLType::BigInt(_, _) => "BIGINT".to_string(),
+        MySQLType::Float => "REAL".to_string(),
+        MySQLType::Double => "FLOAT(53)".to_string(),
+        MySQLType::Decimal(p, s) => format!("DECIMAL({}, {})", (*p).min(38), (*s).min(38)),
+        MySQLType::DateTime(fsp) => format!("DATETIME2({})", (*fsp).min(7)),
+        MySQLType::Timestamp(_) => "DATETIME2(7)".to_string(),
+        MySQLType::Date => "DATE".to_string(),
+        MySQLType::Time(_) => "TIME".to_string(),
+        MySQLType::Binary => "BINARY".to_string(),
+        MySQLType::VarBinary | MySQLType::TinyBlob | MySQLType::Blob | MySQLType::MediumBlob | MySQLType::LongBlob => "VARBINARY(MAX)".to_string(),
+    }
+}
+
 fn to_postgres_string(t: &PostgreSQLType) -> String {
     match t {
         PostgreSQLType::Text => "TEXT".to_string(),
@@ -3,6 +3,7 @@ use teo_runtime::index::Type;
 use teo_runtime::model::index::Item;
 use crate::exts::sort::SortExt;
 use crate::schema::dialect::SQLDialect;
+use crate::schema::value::encode::SQLEscape;
 
 pub trait IndexExt {
 
@@ -76,7 +77,7 @@ impl IndexExt for teo_runtime::model::Index {
                 SQLDialect::MySQL => "PRIMARY".to_owned(),
                 SQLDialect::SQLite => format!("sqlite_autoindex_{}_1", table_name),
                 SQLDialect::PostgreSQL => self.normalize_name_psql(table_name),
-                _ => unreachable!()
+                SQLDialect::MSSQL => format!("PK_{}", table_name),
             },
             _ => match dialect {
                 SQLDialect::PostgreSQL => self.normalize_name_psql(table_name),
@@ -86,30 +87,25 @@ impl IndexExt for teo_runtime::model::Index {
     }
 
     fn to_sql_drop(&self, dialect: SQLDialect, table_name: &str) -> String {
-        let escape = dialect.escape();
-        let index_name_cow = self.sql_name(table_name, dialect);
-        let index_name = index_name_cow.as_ref();
+        let index_name = self.sql_name(table_name, dialect).as_ref().escape(dialect);
         if dialect == SQLDialect::PostgreSQL {
-            format!("DROP INDEX {escape}{index_name}{escape}")
+            format!("DROP INDEX {index_name}")
         } else {
-            format!("DROP INDEX {escape}{index_name}{escape} ON {escape}{table_name}{escape}")
+            format!("DROP INDEX {index_name} ON {}", table_name.escape(dialect))
         }
     }
 
     fn to_sql_create(&self, dialect: SQLDialect, table_name: &str) -> String {
-        let escape = dialect.escape();
-        let index_name_cow = self.sql_name(table_name, dialect);
-        let index_name = index_name_cow.as_ref();
+        let index_name = self.sql_name(table_name, dialect).as_ref().escape(dialect);
         let unique = if self.r#type().is_unique() { "UNIQUE " } else { "" };
         let fields: Vec<String> = self.items.iter().map(|item| {
             Self::sql_format_item(dialect, item, false)
         }).collect();
-        format!("CREATE {unique}INDEX {escape}{index_name}{escape} ON {escape}{table_name}{escape}({})", fields.join(","))
+        format!("CREATE {unique}INDEX {index_name} ON {}({})", table_name.escape(dialect), fields.join(","))
     }
 
     fn sql_format_item(dialect: SQLDialect, item: &Item, table_create_mode: bool) -> String {
-        let escape = dialect.escape();
-        let name = &item.field;
+        let name = item.field.escape(dialect);
         let sort = item.sort.to_str();
         let len = if let Some(len) = item.len {
             if dialect == SQLDialect::MySQL {
@@ -121,9 +117,9 @@ impl IndexExt for teo_runtime::model::Index {
             Cow::Borrowed("")
         };
         if table_create_mode && dialect == SQLDialect::PostgreSQL {
-            format!("{escape}{name}{escape}")
+            name
         } else {
-            format!("{escape}{name}{escape}{len} {sort}")
+            format!("{name}{len} {sort}")
         }
     }
 }
\ No newline at end of file
@@ -1,3 +1,4 @@
 pub mod index;
 pub mod database_type;
-pub mod sort;
\ No newline at end of file
+pub mod sort;
+pub mod model;
@@ -0,0 +1,36 @@
+use teo_runtime::model::field::Field;
+use teo_runtime::model::field::typed::Typed;
+use teo_runtime::model::Model;
+
+pub trait ModelExt {
+
+    fn version_field(&self) -> Option<&Field>;
+
+    fn full_text_fields(&self) -> Vec<&Field>;
+
+    fn insensitive_index_fields(&self) -> Vec<&Field>;
+}
+
+impl ModelExt for Model {
+
+    // a model opts into optimistic locking by marking an integer field with `version` data
+    fn version_field(&self) -> Option<&Field> {
+        self.fields().into_iter().find(|field| {
+            field.data.get("version").and_then(|v| v.as_bool()).unwrap_or(false) && (field.r#type().is_int() || field.r#type().is_int64())
+        })
+    }
+
+    // string fields marked with `fullText` data are indexed for the `search` filter
+    fn full_text_fields(&self) -> Vec<&Field> {
+        self.fields().into_iter().filter(|field| {
+            field.data.get("fullText").and_then(|v| v.as_bool()).unwrap_or(false) && field.r#type().unwrap_optional().is_string()
+        }).collect()
+    }
+
+    // string fields marked with `insensitiveIndex` data get an index for `mode: insensitive` lookups
+    fn insensitive_index_fields(&self) -> Vec<&Field> {
+        self.fields().into_iter().filter(|field| {
+            field.data.get("insensitiveIndex").and_then(|v| v.as_bool()).unwrap_or(false) && field.r#type().unwrap_optional().is_string()
+        }).collect()
+    }
+}
@@ -7,3 +7,4 @@ pub mod query;
 pub mod exts;
 pub(crate) mod url;
 pub mod coder;
+pub mod error;
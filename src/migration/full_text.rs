@@ -0,0 +1,76 @@
+use crate::schema::dialect::SQLDialect;
+use crate::schema::value::encode::SQLEscape;
+
+// PostgreSQL parses documents and queries with this configuration
+pub(crate) const FULL_TEXT_CONFIG: &str = "simple";
+
+// SQLite keeps full text columns of a table in an external content FTS5 table with this prefix
+pub(crate) const FULL_TEXT_TABLE_PREFIX: &str = "_teo_fts_";
+
+const FULL_TEXT_INDEX_SUFFIX: &str = "_fts_idx";
+
+pub(crate) fn full_text_table_name(table_name: &str) -> String {
+    format!("{FULL_TEXT_TABLE_PREFIX}{table_name}")
+}
+
+pub(crate) fn full_text_index_name(table_name: &str, column_name: &str) -> String {
+    format!("{table_name}_{column_name}{FULL_TEXT_INDEX_SUFFIX}")
+}
+
+pub(crate) fn is_full_text_index_name(index_name: &str) -> bool {
+    index_name.ends_with(FULL_TEXT_INDEX_SUFFIX)
+}
+
+pub(crate) fn psql_document(column: &str) -> String {
+    format!("to_tsvector('{FULL_TEXT_CONFIG}', {column})")
+}
+
+// one index per column on PostgreSQL and MySQL
+pub(crate) fn create_index_statement(dialect: SQLDialect, table_name: &str, column_name: &str) -> String {
+    let index_name = full_text_index_name(table_name, column_name).escape(dialect);
+    let table = table_name.escape(dialect);
+    let column = column_name.escape(dialect);
+    match dialect {
+        SQLDialect::PostgreSQL => format!("CREATE INDEX {index_name} ON {table} USING GIN ({})", psql_document(&column)),
+        _ => format!("CREATE FULLTEXT INDEX {index_name} ON {table}({column})"),
+    }
+}
+
+pub(crate) fn drop_index_statement(dialect: SQLDialect, table_name: &str, index_name: &str) -> String {
+    if dialect == SQLDialect::PostgreSQL {
+        format!("DROP INDEX {}", index_name.escape(dialect))
+    } else {
+        format!("DROP INDEX {} ON {}", index_name.escape(dialect), table_name.escape(dialect))
+    }
+}
+
+pub(crate) fn sqlite_create_table_statement(table_name: &str, column_names: &Vec<&str>) -> String {
+    let columns = column_names.iter().map(|c| format!("`{c}`")).collect::<Vec<String>>().join(", ");
+    format!("CREATE VIRTUAL TABLE `{}` USING fts5({columns}, content='{table_name}', content_rowid='rowid')", full_text_table_name(table_name))
+}
+
+// the FTS5 table is filled from the existing rows and kept in sync by triggers
+pub(crate) fn sqlite_create_statements(table_name: &str, column_names: &Vec<&str>) -> Vec<String> {
+    let fts = full_text_table_name(table_name);
+    let columns = column_names.iter().map(|c| format!("`{c}`")).collect::<Vec<String>>().join(", ");
+    let values = |row: &str| column_names.iter().map(|c| format!("{row}.`{c}`")).collect::<Vec<String>>().join(", ");
+    let insert = format!("INSERT INTO `{fts}`(rowid, {columns}) VALUES (new.rowid, {})", values("new"));
+    let delete = format!("INSERT INTO `{fts}`(`{fts}`, rowid, {columns}) VALUES ('delete', old.rowid, {})", values("old"));
+    vec![
+        sqlite_create_table_statement(table_name, column_names),
+        format!("CREATE TRIGGER `{fts}_ai` AFTER INSERT ON `{table_name}` BEGIN {insert}; END"),
+        format!("CREATE TRIGGER `{fts}_ad` AFTER DELETE ON `{table_name}` BEGIN {delete}; END"),
+        format!("CREATE TRIGGER `{fts}_au` AFTER UPDATE ON `{table_name}` BEGIN {delete}; {insert}; END"),
+        format!("INSERT INTO `{fts}`(`{fts}`) VALUES ('rebuild')"),
+    ]
+}
+
+pub(crate) fn sqlite_drop_statements(table_name: &str) -> Vec<String> {
+    let fts = full_text_table_name(table_name);
+    vec![
+        format!("DROP TRIGGER IF EXISTS `{fts}_ai`"),
+        format!("DROP TRIGGER IF EXISTS `{fts}_ad`"),
+        format!("DROP TRIGGER IF EXISTS `{fts}_au`"),
+        format!("DROP TABLE IF EXISTS `{fts}`"),
+    ]
+}
@@ -0,0 +1,418 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use chrono::Utc;
+use quaint_forked::prelude::Queryable;
+use quaint_forked::ast::Query;
+use teo_parser::r#type::Type;
+use teo_runtime::model::Model;
+use teo_teon::Value;
+use teo_result::{Error, Result};
+use crate::migration::migrate::SQLMigration;
+use crate::migration::plan::MigrationPlan;
+use crate::schema::column::SQLColumn;
+use crate::schema::dialect::SQLDialect;
+use crate::schema::value::encode::{SQLEscape, ToSQLString};
+use crate::stmts::params::SQLParams;
+use crate::stmts::SQL;
+
+pub(crate) const HISTORY_TABLE: &str = "_teo_migrations";
+const UP_FILE: &str = "up.sql";
+const DOWN_FILE: &str = "down.sql";
+const IRREVERSIBLE: &str = "-- irreversible";
+
+#[derive(Debug, Clone)]
+pub struct MigrationFile {
+    pub version: String,
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl MigrationFile {
+
+    pub fn id(&self) -> String {
+        format!("{}_{}", self.version, self.name)
+    }
+
+    fn up_statements(&self) -> Result<Vec<String>> {
+        read_statements(&self.path.join(UP_FILE))
+    }
+
+    fn down_statements(&self) -> Result<Vec<String>> {
+        read_statements(&self.path.join(DOWN_FILE))
+    }
+
+    fn is_irreversible(&self) -> Result<bool> {
+        Ok(read_file(&self.path.join(DOWN_FILE))?.lines().any(|line| line.trim_start().starts_with(IRREVERSIBLE)))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: String,
+    pub name: String,
+    pub applied_at: Option<String>,
+    pub file_exists: bool,
+}
+
+impl MigrationStatus {
+
+    pub fn is_applied(&self) -> bool {
+        self.applied_at.is_some()
+    }
+
+    pub fn is_pending(&self) -> bool {
+        !self.is_applied()
+    }
+}
+
+pub(crate) struct MigrationHistory { }
+
+impl MigrationHistory {
+
+    pub(crate) async fn generate(dialect: SQLDialect, conn: &dyn Queryable, models: Vec<&Model>, dir: &Path, name: &str) -> Result<Option<MigrationFile>> {
+        let plan = SQLMigration::plan(dialect, conn, models, false).await?;
+        if plan.is_empty() {
+            return Ok(None);
+        }
+        let version = next_version(&read_migration_files(dir)?);
+        let name = sanitize_name(name);
+        let path = dir.join(format!("{version}_{name}"));
+        fs::create_dir_all(&path).map_err(|e| Error::new(format!("cannot create migration directory `{}': {}", path.display(), e)))?;
+        write_file(&path.join(UP_FILE), &plan.to_string())?;
+        write_file(&path.join(DOWN_FILE), &down_script(&plan))?;
+        Ok(Some(MigrationFile { version, name, path }))
+    }
+
+    // each version runs with its history row as one unit, a savepoint when the caller holds a transaction
+    pub(crate) async fn apply_pending(dialect: SQLDialect, conn: &dyn Queryable, dir: &Path, in_transaction: bool) -> Result<Vec<MigrationFile>> {
+        Self::create_history_table_if_needed(dialect, conn).await?;
+        let applied = Self::applied_versions(dialect, conn).await?;
+        let mut result = vec![];
+        for file in read_migration_files(dir)? {
+            if applied.iter().any(|(version, _)| version == &file.version) {
+                continue;
+            }
+            let statements = file.up_statements()?;
+            // SQLite table rebuilds disable foreign keys, which only works outside the version's transaction
+            let rebuilds = statements.iter().any(|s| SQLMigration::is_foreign_keys_pragma(s));
+            if rebuilds {
+                SQLMigration::disable_foreign_keys(dialect, conn, None).await?;
+            }
+            let unit = Unit::begin(dialect, conn, in_transaction, &file).await?;
+            let applied = async {
+                for stmt in statements.iter().filter(|s| !SQLMigration::is_foreign_keys_pragma(s)) {
+                    if SQLMigration::is_foreign_key_check(stmt) {
+                        SQLMigration::check_foreign_keys(dialect, conn, None).await?;
+                    } else {
+                        execute(conn, stmt, &file).await?;
+                    }
+                }
+                Self::record(dialect, conn, &file).await
+            }.await;
+            let applied = unit.end(conn, applied, &file).await;
+            if rebuilds {
+                SQLMigration::enable_foreign_keys(dialect, conn, None).await?;
+            }
+            applied?;
+            result.push(file);
+        }
+        Ok(result)
+    }
+
+    pub(crate) async fn rollback(dialect: SQLDialect, conn: &dyn Queryable, dir: &Path, count: usize, in_transaction: bool) -> Result<Vec<MigrationFile>> {
+        Self::create_history_table_if_needed(dialect, conn).await?;
+        let files = read_migration_files(dir)?;
+        let applied = Self::applied_versions(dialect, conn).await?;
+        // every version is checked before any of them is reverted
+        let mut targets = vec![];
+        for (version, _) in applied.iter().rev().take(count) {
+            let Some(file) = files.iter().find(|f| &f.version == version) else {
+                return Err(Error::new(format!("migration `{version}' is applied but its file is missing from `{}'", dir.display())));
+            };
+            if file.is_irreversible()? {
+                return Err(Error::new(format!("migration `{}' is irreversible and cannot be rolled back", file.id())));
+            }
+            targets.push((file, file.down_statements()?));
+        }
+        let mut result = vec![];
+        for (file, statements) in targets {
+            let unit = Unit::begin(dialect, conn, in_transaction, file).await?;
+            let reverted = async {
+                for stmt in &statements {
+                    execute(conn, stmt, file).await?;
+                }
+                Self::forget(dialect, conn, file).await
+            }.await;
+            unit.end(conn, reverted, file).await?;
+            result.push(file.clone());
+        }
+        Ok(result)
+    }
+
+    pub(crate) async fn status(dialect: SQLDialect, conn: &dyn Queryable, dir: &Path) -> Result<Vec<MigrationStatus>> {
+        Self::create_history_table_if_needed(dialect, conn).await?;
+        let files = read_migration_files(dir)?;
+        let applied = Self::applied_versions(dialect, conn).await?;
+        let mut result: Vec<MigrationStatus> = files.iter().map(|file| MigrationStatus {
+            version: file.version.clone(),
+            name: file.name.clone(),
+            applied_at: applied.iter().find(|(version, _)| version == &file.version).map(|(_, applied_at)| applied_at.clone()),
+            file_exists: true,
+        }).collect();
+        for (version, applied_at) in applied.iter() {
+            if !files.iter().any(|f| &f.version == version) {
+                result.push(MigrationStatus {
+                    version: version.clone(),
+                    name: Self::applied_name(dialect, conn, version).await?,
+                    applied_at: Some(applied_at.clone()),
+                    file_exists: false,
+                });
+            }
+        }
+        result.sort_by(|a, b| a.version.cmp(&b.version));
+        Ok(result)
+    }
+
+    async fn create_history_table_if_needed(dialect: SQLDialect, conn: &dyn Queryable) -> Result<()> {
+        let mut stmt = SQL::create().table(HISTORY_TABLE);
+        stmt.if_not_exists();
+        stmt.column(SQLColumn::new("version".to_owned(), dialect.string_type(), true, false, None, true));
+        stmt.column(SQLColumn::new("name".to_owned(), dialect.string_type(), true, false, None, false));
+        stmt.column(SQLColumn::new("applied_at".to_owned(), dialect.string_type(), true, false, None, false));
+        let stmt = stmt.to_string(dialect);
+        conn.execute(Query::from(stmt.as_str())).await.map_err(|e| Error::new(format!("cannot create migration history table: {}", e)))?;
+        Ok(())
+    }
+
+    async fn applied_versions(dialect: SQLDialect, conn: &dyn Queryable) -> Result<Vec<(String, String)>> {
+        let sql = format!("SELECT {}, {} FROM {} ORDER BY {} ASC", "version".escape(dialect), "applied_at".escape(dialect), HISTORY_TABLE.escape(dialect), "version".escape(dialect));
+        let result_set = conn.query(Query::from(sql.as_str())).await.map_err(|e| Error::new(format!("cannot read migration history: {}", e)))?;
+        result_set.into_iter().map(|row| {
+            let column = |name: &str| row.get(name).and_then(|v| v.to_string()).ok_or_else(|| {
+                Error::new(format!("migration history has no readable `{name}' column"))
+            });
+            Ok((column("version")?, column("applied_at")?))
+        }).collect()
+    }
+
+    async fn applied_name(dialect: SQLDialect, conn: &dyn Queryable, version: &str) -> Result<String> {
+        let mut params = SQLParams::new(dialect);
+        let r#where = format!("{} = {}", "version".escape(dialect), params.push(&Value::String(version.to_owned()), &Type::String));
+        let (sql, values) = SQL::select(Some(&vec!["name"]), HISTORY_TABLE).r#where(r#where).params(params).to_statement(dialect);
+        let result_set = conn.query_raw(&sql, &values).await.map_err(|e| Error::new(format!("cannot read migration history: {}", e)))?;
+        Ok(result_set.into_iter().next().and_then(|row| row.get("name").and_then(|v| v.to_string())).unwrap_or_default())
+    }
+
+    async fn record(dialect: SQLDialect, conn: &dyn Queryable, file: &MigrationFile) -> Result<()> {
+        let mut params = SQLParams::new(dialect);
+        let version = params.push(&Value::String(file.version.clone()), &Type::String);
+        let name = params.push(&Value::String(file.name.clone()), &Type::String);
+        let applied_at = params.push(&Value::String(Utc::now().to_rfc3339()), &Type::String);
+        let (stmt, values) = SQL::insert_into(HISTORY_TABLE).values(vec![("version", version.as_str()), ("name", name.as_str()), ("applied_at", applied_at.as_str())]).params(params).to_statement(dialect);
+        conn.execute_raw(&stmt, &values).await.map_err(|e| Error::new(format!("cannot record migration `{}': {}", file.id(), e)))?;
+        Ok(())
+    }
+
+    async fn forget(dialect: SQLDialect, conn: &dyn Queryable, file: &MigrationFile) -> Result<()> {
+        let mut params = SQLParams::new(dialect);
+        let version = params.push(&Value::String(file.version.clone()), &Type::String);
+        let (stmt, values) = SQL::delete_from(HISTORY_TABLE).r#where(format!("{} = {version}", "version".escape(dialect))).params(params).to_statement(dialect);
+        conn.execute_raw(&stmt, &values).await.map_err(|e| Error::new(format!("cannot forget migration `{}': {}", file.id(), e)))?;
+        Ok(())
+    }
+}
+
+// a transaction or a savepoint around one version; MySQL commits DDL implicitly, so a version
+// failing there halfway has to be repaired by hand
+struct Unit {
+    commit: Option<String>,
+    rollback: String,
+}
+
+impl Unit {
+
+    async fn begin(dialect: SQLDialect, conn: &dyn Queryable, in_transaction: bool, file: &MigrationFile) -> Result<Self> {
+        let (begin, commit, rollback) = match (in_transaction, dialect) {
+            (true, SQLDialect::MSSQL) => ("SAVE TRANSACTION teo_migration", None, "ROLLBACK TRANSACTION teo_migration"),
+            (true, _) => ("SAVEPOINT teo_migration", Some("RELEASE SAVEPOINT teo_migration"), "ROLLBACK TO SAVEPOINT teo_migration"),
+            (false, SQLDialect::MSSQL) => ("BEGIN TRANSACTION", Some("COMMIT TRANSACTION"), "ROLLBACK TRANSACTION"),
+            (false, SQLDialect::MySQL) => ("START TRANSACTION", Some("COMMIT"), "ROLLBACK"),
+            (false, _) => ("BEGIN", Some("COMMIT"), "ROLLBACK"),
+        };
+        execute(conn, begin, file).await?;
+        Ok(Self { commit: commit.map(ToOwned::to_owned), rollback: rollback.to_owned() })
+    }
+
+    async fn end(self, conn: &dyn Queryable, result: Result<()>, file: &MigrationFile) -> Result<()> {
+        match result {
+            Ok(()) => match &self.commit {
+                Some(commit) => execute(conn, commit, file).await,
+                None => Ok(()),
+            },
+            Err(err) => {
+                // the statement error is the one worth reporting
+                let _ = conn.raw_cmd(&self.rollback).await;
+                Err(err)
+            }
+        }
+    }
+}
+
+async fn execute(conn: &dyn Queryable, stmt: &str, file: &MigrationFile) -> Result<()> {
+    conn.raw_cmd(stmt).await.map_err(|e| Error::new(format!("migration `{}' failed on `{}': {}", file.id(), stmt, e)))
+}
+
+fn down_script(plan: &MigrationPlan) -> String {
+    let mut result = String::new();
+    for step in plan.steps.iter().rev() {
+        if step.is_reversible() {
+            result += &format!("-- revert {}\n", step.summary());
+            for statement in &step.down {
+                result += &format!("{};\n", statement.trim_end_matches(';'));
+            }
+        } else {
+            result += &format!("{IRREVERSIBLE}: {}\n", step.summary());
+        }
+    }
+    result
+}
+
+fn sanitize_name(name: &str) -> String {
+    let name: String = name.trim().chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' }).collect();
+    if name.is_empty() { "migration".to_owned() } else { name }
+}
+
+fn write_file(path: &Path, content: &str) -> Result<()> {
+    fs::write(path, content).map_err(|e| Error::new(format!("cannot write migration file `{}': {}", path.display(), e)))
+}
+
+// versions carry milliseconds and always sort after the existing ones
+fn next_version(files: &[MigrationFile]) -> String {
+    let version = Utc::now().format("%Y%m%d%H%M%S%3f").to_string();
+    match files.last() {
+        Some(last) if last.version.len() == version.len() && last.version >= version => {
+            last.version.parse::<u64>().map(|v| (v + 1).to_string()).unwrap_or(version)
+        }
+        _ => version,
+    }
+}
+
+fn read_file(path: &Path) -> Result<String> {
+    fs::read_to_string(path).map_err(|e| Error::new(format!("cannot read migration file `{}': {}", path.display(), e)))
+}
+
+fn read_statements(path: &Path) -> Result<Vec<String>> {
+    Ok(split_statements(&read_file(path)?))
+}
+
+// splits on semicolons outside of quotes, comments and the BEGIN ... END bodies of triggers
+fn split_statements(content: &str) -> Vec<String> {
+    let mut result = vec![];
+    let mut current = String::new();
+    let mut word = String::new();
+    let mut depth = 0usize;
+    let mut chars = content.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch.is_ascii_alphanumeric() || ch == '_' {
+            word.push(ch);
+            current.push(ch);
+            continue;
+        }
+        if !word.is_empty() {
+            if current.trim_start().get(..6).is_some_and(|s| s.eq_ignore_ascii_case("CREATE")) {
+                match word.to_ascii_uppercase().as_str() {
+                    "BEGIN" | "CASE" => depth += 1,
+                    "END" => depth = depth.saturating_sub(1),
+                    _ => (),
+                }
+            }
+            word.clear();
+        }
+        match ch {
+            '\'' | '"' | '`' | '[' => {
+                let close = if ch == '[' { ']' } else { ch };
+                current.push(ch);
+                while let Some(next) = chars.next() {
+                    current.push(next);
+                    if next == close {
+                        // a doubled quote is an escaped one
+                        if chars.peek() == Some(&close) && close != ']' {
+                            current.push(chars.next().unwrap());
+                            continue;
+                        }
+                        break;
+                    }
+                }
+            }
+            '-' if chars.peek() == Some(&'-') => {
+                for next in chars.by_ref() {
+                    if next == '\n' {
+                        current.push('\n');
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                for next in chars.by_ref() {
+                    if last == '*' && next == '/' {
+                        break;
+                    }
+                    last = next;
+                }
+                current.push(' ');
+            }
+            ';' if depth == 0 => {
+                let statement = current.trim();
+                if !statement.is_empty() {
+                    result.push(statement.to_owned());
+                }
+                current.clear();
+            }
+            _ => current.push(ch),
+        }
+    }
+    let statement = current.trim();
+    if !statement.is_empty() {
+        result.push(statement.to_owned());
+    }
+    result
+}
+
+fn read_migration_files(dir: &Path) -> Result<Vec<MigrationFile>> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let entries = fs::read_dir(dir).map_err(|e| Error::new(format!("cannot read migration directory `{}': {}", dir.display(), e)))?;
+    let mut result = vec![];
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !path.is_dir() || !path.join(UP_FILE).exists() {
+            continue;
+        }
+        let Some(dir_name) = path.file_name().and_then(|n| n.to_str()) else { continue };
+        let Some((version, name)) = dir_name.split_once('_') else { continue };
+        if version.is_empty() || !version.chars().all(|c| c.is_ascii_digit()) {
+            continue;
+        }
+        result.push(MigrationFile { version: version.to_owned(), name: name.to_owned(), path: path.clone() });
+    }
+    result.sort_by(|a, b| a.version.cmp(&b.version));
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split_statements;
+
+    #[test]
+    fn splits_outside_strings_comments_and_trigger_bodies() {
+        let content = "-- revert add posts\nINSERT INTO `a` VALUES ('x;\ny', 'it''s;');\n/* ; */CREATE TRIGGER `t_ai` AFTER INSERT ON `t` BEGIN INSERT INTO `f` VALUES (CASE WHEN 1 THEN 'a;' END); DELETE FROM `g`; END;\nDROP TABLE \"b;c\"";
+        assert_eq!(split_statements(content), vec![
+            "INSERT INTO `a` VALUES ('x;\ny', 'it''s;')".to_owned(),
+            "CREATE TRIGGER `t_ai` AFTER INSERT ON `t` BEGIN INSERT INTO `f` VALUES (CASE WHEN 1 THEN 'a;' END); DELETE FROM `g`; END".to_owned(),
+            "DROP TABLE \"b;c\"".to_owned(),
+        ]);
+    }
+}
@@ -0,0 +1,29 @@
+use crate::schema::dialect::SQLDialect;
+use crate::schema::value::encode::SQLEscape;
+
+const INSENSITIVE_INDEX_SUFFIX: &str = "_ci_idx";
+
+pub(crate) fn insensitive_index_name(table_name: &str, column_name: &str) -> String {
+    format!("{table_name}_{column_name}{INSENSITIVE_INDEX_SUFFIX}")
+}
+
+pub(crate) fn is_insensitive_index_name(index_name: &str) -> bool {
+    index_name.ends_with(INSENSITIVE_INDEX_SUFFIX)
+}
+
+// the indexed expression matches what `mode: insensitive` filters compare, MySQL and SQL Server
+// collations are case insensitive and the plain indices serve them
+pub(crate) fn create_insensitive_index_statement(dialect: SQLDialect, table_name: &str, column_name: &str) -> Option<String> {
+    let index_name = insensitive_index_name(table_name, column_name).escape(dialect);
+    let table = table_name.escape(dialect);
+    let column = column_name.escape(dialect);
+    match dialect {
+        SQLDialect::PostgreSQL => Some(format!("CREATE INDEX {index_name} ON {table}(LOWER({column}))")),
+        SQLDialect::SQLite => Some(format!("CREATE INDEX {index_name} ON {table}({column} COLLATE NOCASE)")),
+        _ => None,
+    }
+}
+
+pub(crate) fn drop_insensitive_index_statement(dialect: SQLDialect, index_name: &str) -> String {
+    format!("DROP INDEX {}", index_name.escape(dialect))
+}
@@ -6,7 +6,13 @@ use maplit::hashset;
 use quaint_forked::pooled::{Quaint};
 use quaint_forked::prelude::Queryable;
 use quaint_forked::ast::Query;
-use crate::migration::sql::{sqlite_auto_increment_query, sqlite_list_indices_query};
+use quaint_forked::prelude::{ResultRow, ResultSet};
+use crate::error::{SQLError, SQLErrorKind};
+use crate::migration::full_text::{create_index_statement, drop_index_statement, full_text_index_name, full_text_table_name, is_full_text_index_name, sqlite_create_statements, sqlite_create_table_statement, sqlite_drop_statements, FULL_TEXT_TABLE_PREFIX};
+use crate::migration::history::HISTORY_TABLE;
+use crate::migration::insensitive::{create_insensitive_index_statement, drop_insensitive_index_statement, insensitive_index_name, is_insensitive_index_name};
+use crate::migration::plan::{MigrationPlan, MigrationStep, MigrationStepKind};
+use crate::migration::sql::{mssql_columns_query, mssql_list_indices_query, sqlite_auto_increment_query, sqlite_list_indices_query};
 use super::super::url::url_utils;
 use crate::schema::column::decoder::{ColumnDecoder, ColumnManipulation};
 use crate::stmts::create::table::SQLCreateTableStatement;
@@ -14,9 +20,8 @@ use crate::stmts::SQL;
 use crate::schema::column::SQLColumn;
 use crate::schema::dialect::SQLDialect;
 use teo_runtime::model::Model;
-use crate::schema::value::encode::ToSQLString;
+use crate::schema::value::encode::{SQLEscape, ToSQLString};
 use teo_runtime::connection::connection::Connection;
-use teo_runtime::connection::transaction::Transaction;
 use teo_runtime::sort::Sort;
 use teo_runtime::model::{Index, index::Item};
 use teo_runtime::index::Type;
@@ -24,25 +29,31 @@ use teo_teon::value::Value;
 use teo_result::{Result};
 use crate::exts::database_type::DatabaseTypeToSQLString;
 use crate::exts::index::IndexExt;
+use crate::exts::model::ModelExt;
 use crate::exts::sort::SortExt;
 
+const FOREIGN_KEYS_OFF: &str = "PRAGMA foreign_keys = OFF";
+const FOREIGN_KEYS_ON: &str = "PRAGMA foreign_keys = ON";
+const FOREIGN_KEY_CHECK: &str = "PRAGMA foreign_key_check";
+const REBUILD_SAVEPOINT: &str = "teo_rebuild";
+
 pub(crate) struct SQLMigration { }
 
 impl SQLMigration {
 
     // Create database
 
-    pub(crate) async fn create_database_if_needed(dialect: SQLDialect, url: &str, reset: bool) {
+    pub(crate) async fn create_database_if_needed(dialect: SQLDialect, url: &str, reset: bool) -> Result<()> {
         match dialect {
             SQLDialect::SQLite => Self::create_sqlite_database_if_needed(url, reset).await,
             _ => Self::create_server_database_if_needed(dialect, url, reset).await,
         }
     }
 
-    pub(crate) async fn create_sqlite_database_if_needed(url: &str, reset: bool) {
+    pub(crate) async fn create_sqlite_database_if_needed(url: &str, reset: bool) -> Result<()> {
         let url = url_utils::remove_scheme(url);
         if url_utils::is_memory_url(url) {
-            return
+            return Ok(())
         }
         let absolutized_url = url_utils::absolutized(url);
         if absolutized_url.exists() && reset {
@@ -51,120 +62,231 @@ impl SQLMigration {
         }
         if !absolutized_url.exists() || reset {
             // create a new one
-            fs::File::create(absolutized_url).expect("SQLite database file create failed.");
+            fs::File::create(&absolutized_url).map_err(|e| {
+                SQLError::new(SQLErrorKind::Connection, SQLDialect::SQLite, format!("SQLite database file `{}' create failed: {}", absolutized_url.display(), e))
+            })?;
         }
+        Ok(())
     }
 
-    pub(crate) async fn create_server_database_if_needed(dialect: SQLDialect, url: &str, reset: bool) {
-        let url = url_utils::normalized_url(dialect, url);
+    pub(crate) async fn create_server_database_if_needed(dialect: SQLDialect, url: &str, reset: bool) -> Result<()> {
+        let url = url_utils::normalized_url(dialect, url)?;
         let db_name = &url.path()[1..];
         let url_without_db = url_utils::remove_db_path(dialect, &url);
-        let pool = Quaint::builder(url_without_db.as_str()).unwrap().build();
-        let conn = pool.check_out().await.unwrap();
+        let pool = Quaint::builder(&url_utils::connection_string(dialect, &url_without_db)?).map_err(|e| {
+            SQLError::new(SQLErrorKind::InvalidURL, dialect, e.to_string())
+        })?.build();
+        let conn = pool.check_out().await.map_err(|e| {
+            SQLError::new(SQLErrorKind::Connection, dialect, format!("cannot create pooled connection: {}", e))
+        })?;
         // drop database if needed
         if reset {
             let stmt = SQL::drop().database(db_name).if_exists().to_string(dialect);
-            conn.execute(Query::from(stmt)).await.unwrap();
+            Self::execute(dialect, &conn, None, &stmt).await?;
         }
         // create database if needed
         if dialect == SQLDialect::PostgreSQL {
             let stmt = format!("select from pg_database where datname = '{}'", db_name);
-            let result = conn.query(Query::from(stmt)).await.unwrap();
+            let result = Self::query(dialect, &conn, None, &stmt).await?;
             if result.is_empty() {
                 let stmt = SQL::create().database(db_name).to_string(dialect);
-                conn.execute(Query::from(stmt)).await.unwrap();
+                Self::execute(dialect, &conn, None, &stmt).await?;
             }
         } else {
             let stmt = SQL::create().database(db_name).if_not_exists().to_string(dialect);
-            conn.execute(Query::from(stmt)).await.unwrap();
+            Self::execute(dialect, &conn, None, &stmt).await?;
         }
         // use database
         if dialect == SQLDialect::PostgreSQL {
             let stmt = format!("SET search_path TO {db_name}");
-            conn.execute(Query::from(stmt)).await.unwrap();
+            Self::execute(dialect, &conn, None, &stmt).await?;
         } else {
             let stmt = SQL::r#use().database(db_name).to_string(dialect);
-            conn.raw_cmd(&stmt).await.unwrap();
+            conn.raw_cmd(&stmt).await.map_err(|e| SQLError::statement(dialect, None, &stmt, e))?;
         }
+        Ok(())
+    }
+
+    async fn query(dialect: SQLDialect, conn: &dyn Queryable, table: Option<&str>, stmt: &str) -> Result<ResultSet> {
+        Ok(conn.query(Query::from(stmt)).await.map_err(|e| SQLError::statement(dialect, table, stmt, e))?)
+    }
+
+    async fn execute(dialect: SQLDialect, conn: &dyn Queryable, table: Option<&str>, stmt: &str) -> Result<()> {
+        conn.execute(Query::from(stmt)).await.map_err(|e| SQLError::statement(dialect, table, stmt, e))?;
+        Ok(())
+    }
+
+    // introspection columns are read through these, a missing or mistyped value is a migration error
+    fn row_string(dialect: SQLDialect, table: &str, row: &ResultRow, column: &str) -> Result<String> {
+        Ok(row.get(column).and_then(|v| v.to_string()).ok_or_else(|| Self::introspection_error(dialect, table, column))?)
+    }
+
+    fn row_bool(dialect: SQLDialect, table: &str, row: &ResultRow, column: &str) -> Result<bool> {
+        Ok(row.get(column).and_then(|v| v.as_bool()).ok_or_else(|| Self::introspection_error(dialect, table, column))?)
+    }
+
+    fn introspection_error(dialect: SQLDialect, table: &str, column: &str) -> SQLError {
+        SQLError::new(SQLErrorKind::Migration, dialect, format!("cannot read `{}' of index information", column)).with_table(Some(table))
     }
 
     // Migrate
 
-    pub(crate) async fn db_columns(conn: &dyn Queryable, dialect: SQLDialect, table_name: &str) -> HashSet<SQLColumn> {
+    pub(crate) async fn db_columns(conn: &dyn Queryable, dialect: SQLDialect, table_name: &str) -> Result<HashSet<SQLColumn>> {
+        let table = Some(table_name);
         match dialect {
             SQLDialect::SQLite => {
-                let columns_result = conn.query(Query::from(format!("pragma table_info('{}')", table_name))).await.unwrap();
-                let indices_result = conn.query(Query::from(sqlite_list_indices_query(table_name))).await.unwrap();
-                let auto_increment_result = conn.query(Query::from(sqlite_auto_increment_query(table_name))).await.unwrap();
-                let db_columns = ColumnDecoder::decode_sqlite_columns(columns_result, indices_result, auto_increment_result);
-                db_columns
+                let columns_result = Self::query(dialect, conn, table, &format!("pragma table_info('{}')", table_name)).await?;
+                let indices_result = Self::query(dialect, conn, table, &sqlite_list_indices_query(table_name)).await?;
+                let auto_increment_result = Self::query(dialect, conn, table, &sqlite_auto_increment_query(table_name)).await?;
+                ColumnDecoder::decode_sqlite_columns(table_name, columns_result, indices_result, auto_increment_result)
             }
             _ => {
                 let mut results = hashset! {};
-                let db_table_columns = conn.query(if dialect == SQLDialect::PostgreSQL {
-                    let desc = format!("SELECT * FROM information_schema.columns where table_name = '{}'", table_name);
-                    Query::from(desc)
+                let desc = if dialect == SQLDialect::PostgreSQL {
+                    format!("SELECT * FROM information_schema.columns where table_name = '{}'", table_name)
+                } else if dialect == SQLDialect::MSSQL {
+                    mssql_columns_query(table_name)
                 } else {
-                    let desc = SQL::describe(table_name).to_string(dialect);
-                    Query::from(desc)
-                }).await.unwrap();
+                    SQL::describe(table_name).to_string(dialect)
+                };
+                let db_table_columns = Self::query(dialect, conn, table, &desc).await?;
                 for db_table_column in db_table_columns {
-                    let db_column = ColumnDecoder::decode(db_table_column, dialect, conn, table_name).await;
+                    let db_column = ColumnDecoder::decode(db_table_column, dialect, conn, table_name).await?;
                     results.insert(db_column);
                 }
-                results
+                Ok(results)
             }
         }
     }
 
-    pub(crate) async fn get_db_user_tables(dialect: SQLDialect, conn: &dyn Queryable) -> Vec<String> {
-        match dialect {
-            SQLDialect::MySQL => {
-                let sql = "SHOW TABLES";
-                let db_result = conn.query(Query::from(sql)).await.unwrap();
-                db_result.into_iter().map(|result| { result.into_single().unwrap().to_string().unwrap() }).collect()
+    pub(crate) async fn get_db_user_tables(dialect: SQLDialect, conn: &dyn Queryable) -> Result<Vec<String>> {
+        let sql = match dialect {
+            SQLDialect::MySQL => "SHOW TABLES",
+            SQLDialect::PostgreSQL => "SELECT tablename FROM pg_catalog.pg_tables WHERE schemaname != 'pg_catalog' AND schemaname != 'information_schema'",
+            SQLDialect::SQLite => "SELECT name FROM sqlite_master WHERE type in ('table') AND name not like 'sqlite?_%' escape '?'",
+            SQLDialect::MSSQL => "SELECT TABLE_NAME FROM INFORMATION_SCHEMA.TABLES WHERE TABLE_TYPE = 'BASE TABLE'",
+        };
+        let db_result = Self::query(dialect, conn, None, sql).await?;
+        let tables = db_result.into_iter().filter_map(|result| result.into_single().ok().and_then(|v| v.to_string()));
+        // the migration history is maintained by `MigrationHistory`, never diffed against models,
+        // FTS5 tables and their shadow tables belong to the table they index
+        Ok(tables.filter(|t| t != HISTORY_TABLE && !t.starts_with(FULL_TEXT_TABLE_PREFIX)).collect())
+    }
+
+    pub(crate) fn rename_table_statement(dialect: SQLDialect, old_name: &str, new_name: &str) -> String {
+        if dialect == SQLDialect::MSSQL {
+            return format!("EXEC sp_rename N'{old_name}', N'{new_name}'");
+        }
+        format!("ALTER TABLE {} RENAME TO {}", old_name.escape(dialect), new_name.escape(dialect))
+    }
+
+    pub(crate) async fn table_has_records(dialect: SQLDialect, conn: &dyn Queryable, table_name: &str) -> Result<bool> {
+        let sql = SQL::select(None, table_name).limit(1, 0).to_string(dialect);
+        Ok(!Self::query(dialect, conn, Some(table_name), &sql).await?.is_empty())
+    }
+
+    // the plan is returned whether applied or not, printing it is left to the caller
+    pub(crate) async fn migrate(dialect: SQLDialect, conn: &dyn Queryable, models: Vec<&Model>, dry_run: bool, reset_database: bool) -> Result<MigrationPlan> {
+        let plan = Self::plan(dialect, conn, models, reset_database).await?;
+        if !dry_run {
+            Self::apply(dialect, conn, &plan).await?;
+        }
+        Ok(plan)
+    }
+
+    pub(crate) async fn apply(dialect: SQLDialect, conn: &dyn Queryable, plan: &MigrationPlan) -> Result<()> {
+        for step in &plan.steps {
+            if step.kind == MigrationStepKind::RebuildTable {
+                Self::apply_rebuild(dialect, conn, step).await?;
+                continue;
             }
-            SQLDialect::PostgreSQL => {
-                let sql = "SELECT tablename FROM pg_catalog.pg_tables WHERE schemaname != 'pg_catalog' AND schemaname != 'information_schema'";
-                let db_result = conn.query(Query::from(sql)).await.unwrap();
-                db_result.into_iter().map(|result| { result.into_single().unwrap().to_string().unwrap() }).collect()
+            for stmt in &step.statements {
+                Self::execute(dialect, conn, Some(&step.table), stmt).await?;
             }
-            SQLDialect::SQLite => {
-                let sql = "SELECT name FROM sqlite_master WHERE type in ('table') AND name not like 'sqlite?_%' escape '?'";
-                let db_result = conn.query(Query::from(sql)).await.unwrap();
-                db_result.into_iter().map(|result| { result.into_single().unwrap().to_string().unwrap() }).collect()
+        }
+        Ok(())
+    }
+
+    // the foreign key pragmas run around the rebuild, a failed statement or foreign key check
+    // rolls the rebuild back to its savepoint
+    async fn apply_rebuild(dialect: SQLDialect, conn: &dyn Queryable, step: &MigrationStep) -> Result<()> {
+        Self::disable_foreign_keys(dialect, conn, Some(&step.table)).await?;
+        let mut result = Ok(());
+        for stmt in step.statements.iter().filter(|s| !Self::is_foreign_keys_pragma(s)) {
+            result = if Self::is_foreign_key_check(stmt) {
+                Self::check_foreign_keys(dialect, conn, Some(&step.table)).await
+            } else {
+                Self::execute(dialect, conn, Some(&step.table), stmt).await
+            };
+            if result.is_err() {
+                let _ = conn.raw_cmd(&format!("ROLLBACK TO {REBUILD_SAVEPOINT}")).await;
+                let _ = conn.raw_cmd(&format!("RELEASE {REBUILD_SAVEPOINT}")).await;
+                break;
             }
-            _ => unreachable!()
         }
+        let enabled = Self::enable_foreign_keys(dialect, conn, Some(&step.table)).await;
+        result.and(enabled)
+    }
+
+    pub(crate) fn is_foreign_keys_pragma(stmt: &str) -> bool {
+        stmt == FOREIGN_KEYS_OFF || stmt == FOREIGN_KEYS_ON
+    }
+
+    pub(crate) fn is_foreign_key_check(stmt: &str) -> bool {
+        stmt == FOREIGN_KEY_CHECK
+    }
+
+    // the pragma is a no-op inside a transaction, which would let dropping the old table cascade
+    pub(crate) async fn disable_foreign_keys(dialect: SQLDialect, conn: &dyn Queryable, table: Option<&str>) -> Result<()> {
+        Self::execute(dialect, conn, table, FOREIGN_KEYS_OFF).await?;
+        let result = Self::query(dialect, conn, table, "PRAGMA foreign_keys").await?;
+        let enforced = result.into_iter().next().and_then(|row| row.get("foreign_keys").and_then(|v| v.as_i64())).unwrap_or(0) != 0;
+        if enforced {
+            return Err(SQLError::new(SQLErrorKind::Migration, dialect, "Cannot rebuild tables inside a transaction, foreign keys cannot be disabled there.".to_owned()).with_table(table).into());
+        }
+        Ok(())
     }
 
-    pub(crate) async fn rename_table(dialect: SQLDialect, conn: &dyn Queryable, old_name: &str, new_name: &str) {
-        let escape = dialect.escape();
-        let sql = format!("ALTER TABLE {escape}{old_name}{escape} RENAME TO {escape}{new_name}{escape}");
-        conn.execute(Query::from(sql)).await.unwrap();
+    pub(crate) async fn enable_foreign_keys(dialect: SQLDialect, conn: &dyn Queryable, table: Option<&str>) -> Result<()> {
+        Self::execute(dialect, conn, table, FOREIGN_KEYS_ON).await
     }
 
-    pub(crate) async fn table_has_records(dialect: SQLDialect, conn: &dyn Queryable, table_name: &str) -> bool {
-        let escape = dialect.escape();
-        let sql = format!("select * from {escape}{table_name}{escape} limit 1");
-        !conn.query(Query::from(sql)).await.unwrap().is_empty()
+    pub(crate) async fn check_foreign_keys(dialect: SQLDialect, conn: &dyn Queryable, table: Option<&str>) -> Result<()> {
+        let violations = Self::query(dialect, conn, table, FOREIGN_KEY_CHECK).await?;
+        if !violations.is_empty() {
+            return Err(SQLError::new(SQLErrorKind::Migration, dialect, format!("Rebuilding leaves {} rows with broken foreign keys.", violations.len())).with_table(table).into());
+        }
+        Ok(())
     }
 
-    pub(crate) async fn migrate(dialect: SQLDialect, conn: &dyn Queryable, models: Vec<&Model>, pconn: &dyn Transaction) -> Result<()> {
-        let mut db_tables = Self::get_db_user_tables(dialect, conn).await;
-        // compare each table and do migration
+    pub(crate) async fn plan(dialect: SQLDialect, conn: &dyn Queryable, models: Vec<&Model>, reset_database: bool) -> Result<MigrationPlan> {
+        let mut plan = MigrationPlan::default();
+        let mut db_tables = Self::get_db_user_tables(dialect, conn).await?;
+        if reset_database {
+            for table in db_tables.drain(..) {
+                plan.push(Self::drop_table_step(dialect, &table));
+            }
+        }
+        // compare each table and plan migration
         for model in models {
             let table_name = &model.table_name;
+            // columns and indices are read from the current table name until the rename is applied
+            let mut db_table_name = table_name.to_string();
             if let Some(migration) = Some(&model.migration) {
                 if !db_tables.iter().any(|x| x == table_name) {
                     if let Some(old_name) = &migration.renamed {
                         for old_name in old_name {
                             if db_tables.contains(old_name) {
                                 // rename
-                                Self::rename_table(dialect, conn, old_name.as_str(), table_name).await;
+                                plan.push(MigrationStep::new(MigrationStepKind::RenameTable, table_name, None, Some(old_name.clone()), vec![
+                                    Self::rename_table_statement(dialect, old_name.as_str(), table_name)
+                                ]).with_down(vec![
+                                    Self::rename_table_statement(dialect, table_name, old_name.as_str())
+                                ]));
                                 let index = db_tables.clone().iter().find_position(|v| *v == old_name).unwrap().0;
                                 db_tables.remove(index);
                                 db_tables.push(table_name.to_string());
+                                db_table_name = old_name.clone();
                                 break;
                             }
                         }
@@ -175,120 +297,312 @@ impl SQLMigration {
             let is_table_exist = db_tables.iter().any(|x| x == table_name);
             if !is_table_exist {
                 // table not exist, create table
-                Self::create_table(dialect, conn, model).await;
+                plan.push(Self::create_table_step(dialect, model));
             } else {
                 // remove from list
                 let index = db_tables.clone().iter().find_position(|x| *x == table_name).unwrap().0;
                 db_tables.remove(index);
                 // start migrate for this table
-                let model_columns = ColumnDecoder::decode_model_columns(model);
-                let db_columns = Self::db_columns(conn, dialect, table_name).await;
+                let model_columns = ColumnDecoder::decode_model_columns(model, dialect);
+                let db_columns = Self::db_columns(conn, dialect, &db_table_name).await?;
                 let need_to_alter_any_column = ColumnDecoder::need_to_alter_any_columns(&db_columns, &model_columns);
-                if need_to_alter_any_column && dialect == SQLDialect::SQLite {
-                    panic!("SQLite doesn't support column altering");
-                }
-                let table_has_records = Self::table_has_records(dialect, conn, table_name).await;
-                let db_indices = Self::db_indices(dialect, conn, model).await;
+                let table_has_records = Self::table_has_records(dialect, conn, &db_table_name).await?;
+                let db_indices = Self::db_indices(dialect, conn, &db_table_name).await?;
                 let model_indices = Self::normalized_model_indices(model.indexes(), dialect, table_name);
                 // here update columns and indices
                 let manipulations = ColumnDecoder::manipulations(&db_columns, &model_columns, &db_indices, &model_indices, model);
                 if table_has_records && manipulations.iter().find(|m| m.is_add_column_non_null()).is_some() && model.allows_drop_when_migrate() {
-                    Self::drop_table(dialect, conn, table_name).await;
-                    Self::create_table(dialect, conn, model).await;
+                    plan.push(Self::drop_table_step(dialect, table_name));
+                    plan.push(Self::create_table_step(dialect, model));
+                } else if need_to_alter_any_column && dialect == SQLDialect::SQLite {
+                    // SQLite cannot alter columns in place, the table is rebuilt instead
+                    plan.push(Self::sqlite_rebuild_table_step(model, &db_columns, &model_columns, &manipulations, table_has_records)?);
                 } else {
                     for m in manipulations.iter() {
-                        match m {
-                            ColumnManipulation::CreateIndex(index) => {
-                                let create = index.to_sql_create(dialect, table_name);
-                                conn.execute(Query::from(create)).await.unwrap();
-                            }
-                            ColumnManipulation::DropIndex(index) => {
-                                let drop = index.to_sql_drop(dialect, table_name);
-                                conn.execute(Query::from(drop)).await.unwrap();
-                            }
-                            ColumnManipulation::AddColumn(column, default) => {
-                                if column.not_null() && default.is_none() {
-                                    // if any records, just raise here
-                                    let has_records = Self::table_has_records(dialect, conn, table_name).await;
-                                    if has_records {
-                                        panic!("Cannot add new non null column `{}', table `{}' has records. Consider add a default value or drop the table.", column.name(), table_name)
-                                    }
-                                }
-                                let mut c = column.clone().clone();
-                                if default.is_some() {
-                                    c.set_default(Some(ToSQLString::to_string(&default.as_ref().unwrap(), dialect)));
-                                }
-                                let stmt = SQL::alter_table(table_name).add(c).to_string(dialect);
-                                conn.execute(Query::from(stmt)).await.unwrap();
-                            }
-                            ColumnManipulation::AlterColumn(old_column, new_column) => {
-                                if dialect != SQLDialect::PostgreSQL {
-                                    let alter = SQL::alter_table(table_name).modify(new_column.clone().clone()).to_string(dialect);
-                                    conn.execute(Query::from(alter)).await.unwrap();
-                                } else {
-                                    let clauses = Self::psql_alter_clauses(table_name, *old_column, *new_column);
-                                    for clause in clauses {
-                                        conn.execute(Query::from(clause)).await.unwrap();
-                                    }
-                                }
-                            }
-                            ColumnManipulation::RemoveColumn(name) => {
-                                let stmt = SQL::alter_table(table_name).drop_column(name).to_string(dialect);
-                                conn.execute(Query::from(stmt)).await.unwrap();
-                            }
-                            ColumnManipulation::RenameColumn { old, new } => {
-                                let stmt = if dialect == SQLDialect::PostgreSQL {
-                                    format!("ALTER TABLE {} RENAME COLUMN '{}' TO '{}'", table_name, old, new)
-                                } else {
-                                    format!("ALTER TABLE {} RENAME COLUMN `{}` TO `{}`", table_name, old, new)
-                                };
-                                conn.execute(Query::from(stmt)).await.unwrap();
-                            }
-                        }
+                        plan.push(Self::manipulation_step(dialect, table_name, m, &db_columns, table_has_records)?);
+                    }
+                    for step in Self::full_text_steps(dialect, conn, model, &db_table_name).await? {
+                        plan.push(step);
+                    }
+                    for step in Self::insensitive_index_steps(dialect, conn, model, &db_table_name).await? {
+                        plan.push(step);
                     }
                 }
             }
         }
         // drop tables
         for table in db_tables {
-            Self::drop_table(dialect, conn, &table).await;
+            plan.push(Self::drop_table_step(dialect, &table));
+        }
+        Ok(plan)
+    }
+
+    fn manipulation_step(dialect: SQLDialect, table_name: &str, m: &ColumnManipulation, db_columns: &HashSet<SQLColumn>, table_has_records: bool) -> Result<MigrationStep> {
+        Ok(match m {
+            ColumnManipulation::CreateIndex(index) => {
+                let create = index.to_sql_create(dialect, table_name);
+                let drop = index.to_sql_drop(dialect, table_name);
+                MigrationStep::new(MigrationStepKind::CreateIndex, table_name, Some(index.sql_name(table_name, dialect).to_string()), None, vec![create]).with_down(vec![drop])
+            }
+            ColumnManipulation::DropIndex(index) => {
+                let drop = index.to_sql_drop(dialect, table_name);
+                let create = index.to_sql_create(dialect, table_name);
+                MigrationStep::new(MigrationStepKind::DropIndex, table_name, Some(index.sql_name(table_name, dialect).to_string()), None, vec![drop]).with_down(vec![create])
+            }
+            ColumnManipulation::AddColumn(column, default) => {
+                if column.not_null() && default.is_none() {
+                    // if any records, just raise here
+                    if table_has_records {
+                        return Err(Self::non_null_column_error(dialect, table_name, column.name()).into());
+                    }
+                }
+                let mut c = column.clone().clone();
+                if default.is_some() {
+                    c.set_default(Some(ToSQLString::to_string(&default.as_ref().unwrap(), dialect)));
+                }
+                let stmt = SQL::alter_table(table_name).add(c).to_string(dialect);
+                let down = SQL::alter_table(table_name).drop_column(column.name()).to_string(dialect);
+                MigrationStep::new(MigrationStepKind::AddColumn, table_name, Some(column.name().to_owned()), None, vec![stmt]).with_down(vec![down])
+            }
+            ColumnManipulation::AlterColumn(old_column, new_column) => {
+                let (stmts, down) = if dialect != SQLDialect::PostgreSQL {
+                    (vec![SQL::alter_table(table_name).modify(new_column.clone().clone()).to_string(dialect)],
+                     vec![SQL::alter_table(table_name).modify(old_column.clone().clone()).to_string(dialect)])
+                } else {
+                    (Self::psql_alter_clauses(table_name, *old_column, *new_column),
+                     Self::psql_alter_clauses(table_name, *new_column, *old_column))
+                };
+                MigrationStep::new(MigrationStepKind::AlterColumn, table_name, Some(new_column.name().to_owned()), None, stmts).with_down(down)
+            }
+            ColumnManipulation::RemoveColumn(name) => {
+                let stmt = SQL::alter_table(table_name).drop_column(name).to_string(dialect);
+                let down = db_columns.iter().find(|c| c.name() == name.as_str()).map(|c| {
+                    SQL::alter_table(table_name).add(c.clone()).to_string(dialect)
+                }).into_iter().collect();
+                MigrationStep::new(MigrationStepKind::DropColumn, table_name, Some(name.clone()), None, vec![stmt]).with_down(down)
+            }
+            ColumnManipulation::RenameColumn { old, new } => {
+                let stmt = Self::rename_column_statement(dialect, table_name, old, new);
+                let down = Self::rename_column_statement(dialect, table_name, new, old);
+                MigrationStep::new(MigrationStepKind::RenameColumn, table_name, Some(new.clone()), Some(old.clone()), vec![stmt]).with_down(vec![down])
+            }
+        })
+    }
+
+    fn non_null_column_error(dialect: SQLDialect, table_name: &str, column_name: &str) -> SQLError {
+        SQLError::new(SQLErrorKind::Migration, dialect, format!("Cannot add new non null column `{}', table `{}' has records. Consider add a default value or drop the table.", column_name, table_name)).with_table(Some(table_name))
+    }
+
+    fn rename_column_statement(dialect: SQLDialect, table_name: &str, old: &str, new: &str) -> String {
+        if dialect == SQLDialect::MSSQL {
+            // sp_rename takes the qualified old name and the bare new name as string literals
+            let object = format!("{}.{}", table_name.escape(dialect), old.escape(dialect)).replace('\'', "''");
+            format!("EXEC sp_rename N'{}', N'{}', N'COLUMN'", object, new.replace('\'', "''"))
+        } else {
+            format!("ALTER TABLE {} RENAME COLUMN {} TO {}", table_name.escape(dialect), old.escape(dialect), new.escape(dialect))
         }
-        Ok(())
     }
 
-    async fn drop_table(dialect: SQLDialect, conn: &dyn Queryable, table: &str) {
-        let escape = dialect.escape();
-        let sql = format!("DROP TABLE {escape}{table}{escape}");
-        conn.execute(Query::from(sql)).await.unwrap();
+    fn drop_table_step(dialect: SQLDialect, table: &str) -> MigrationStep {
+        let mut stmts = if dialect == SQLDialect::SQLite { sqlite_drop_statements(table) } else { vec![] };
+        stmts.push(format!("DROP TABLE {}", table.escape(dialect)));
+        MigrationStep::new(MigrationStepKind::DropTable, table, None, None, stmts)
     }
 
-    async fn create_table(dialect: SQLDialect, conn: &dyn Queryable, model: &Model) {
+    fn full_text_create_statements(dialect: SQLDialect, model: &Model) -> Vec<String> {
+        let columns: Vec<&str> = model.full_text_fields().iter().map(|f| f.column_name()).collect();
+        if columns.is_empty() {
+            return vec![];
+        }
+        match dialect {
+            SQLDialect::SQLite => sqlite_create_statements(&model.table_name, &columns),
+            // SQL Server full text catalogs are managed outside of migrations
+            SQLDialect::MSSQL => vec![],
+            _ => columns.iter().map(|c| create_index_statement(dialect, &model.table_name, c)).collect(),
+        }
+    }
+
+    async fn full_text_steps(dialect: SQLDialect, conn: &dyn Queryable, model: &Model, db_table_name: &str) -> Result<Vec<MigrationStep>> {
+        let table_name = &model.table_name;
+        let columns: Vec<&str> = model.full_text_fields().iter().map(|f| f.column_name()).collect();
+        match dialect {
+            SQLDialect::SQLite => {
+                let sql = format!("SELECT sql FROM sqlite_master WHERE type = 'table' AND name = '{}'", full_text_table_name(db_table_name));
+                let existing = Self::query(dialect, conn, Some(table_name), &sql).await?.into_iter().next().and_then(|row| row.get("sql").and_then(|v| v.to_string()));
+                let desired = if columns.is_empty() { None } else { Some(sqlite_create_table_statement(table_name, &columns)) };
+                if existing == desired {
+                    return Ok(vec![]);
+                }
+                let mut stmts = sqlite_drop_statements(db_table_name);
+                stmts.extend(Self::full_text_create_statements(dialect, model));
+                let kind = if desired.is_some() { MigrationStepKind::CreateIndex } else { MigrationStepKind::DropIndex };
+                Ok(vec![MigrationStep::new(kind, table_name, Some(full_text_table_name(table_name)), None, stmts)])
+            }
+            SQLDialect::MSSQL => Ok(vec![]),
+            _ => {
+                let existing = Self::db_full_text_indices(dialect, conn, db_table_name).await?;
+                let mut steps = vec![];
+                for column in &columns {
+                    let name = full_text_index_name(table_name, column);
+                    if !existing.contains(&name) {
+                        let create = create_index_statement(dialect, table_name, column);
+                        let drop = drop_index_statement(dialect, table_name, &name);
+                        steps.push(MigrationStep::new(MigrationStepKind::CreateIndex, table_name, Some(name), None, vec![create]).with_down(vec![drop]));
+                    }
+                }
+                for name in existing {
+                    if !columns.iter().any(|c| full_text_index_name(table_name, c) == name) {
+                        let drop = drop_index_statement(dialect, table_name, &name);
+                        steps.push(MigrationStep::new(MigrationStepKind::DropIndex, table_name, Some(name), None, vec![drop]));
+                    }
+                }
+                Ok(steps)
+            }
+        }
+    }
+
+    fn insensitive_index_create_statements(dialect: SQLDialect, model: &Model) -> Vec<String> {
+        model.insensitive_index_fields().iter().filter_map(|f| create_insensitive_index_statement(dialect, &model.table_name, f.column_name())).collect()
+    }
+
+    async fn insensitive_index_steps(dialect: SQLDialect, conn: &dyn Queryable, model: &Model, db_table_name: &str) -> Result<Vec<MigrationStep>> {
+        let sql = match dialect {
+            SQLDialect::PostgreSQL => format!("SELECT indexname AS name FROM pg_indexes WHERE schemaname = 'public' AND tablename = '{}'", db_table_name),
+            SQLDialect::SQLite => format!("SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = '{}'", db_table_name),
+            _ => return Ok(vec![]),
+        };
+        let table_name = &model.table_name;
+        let result_set = Self::query(dialect, conn, Some(table_name), &sql).await?;
+        let existing: Vec<String> = result_set.into_iter().map(|row| Self::row_string(dialect, table_name, &row, "name")).collect::<Result<Vec<String>>>()?.into_iter().filter(|name| is_insensitive_index_name(name)).collect();
+        let columns: Vec<&str> = model.insensitive_index_fields().iter().map(|f| f.column_name()).collect();
+        let mut steps = vec![];
+        let missing = columns.iter().filter_map(|column| {
+            let name = insensitive_index_name(table_name, column);
+            if existing.contains(&name) { return None }
+            create_insensitive_index_statement(dialect, table_name, column).map(|create| (name, create))
+        }).collect::<Vec<(String, String)>>();
+        for (name, create) in missing {
+            let drop = drop_insensitive_index_statement(dialect, &name);
+            steps.push(MigrationStep::new(MigrationStepKind::CreateIndex, table_name, Some(name), None, vec![create]).with_down(vec![drop]));
+        }
+        for name in existing {
+            if !columns.iter().any(|c| insensitive_index_name(table_name, c) == name) {
+                let drop = drop_insensitive_index_statement(dialect, &name);
+                steps.push(MigrationStep::new(MigrationStepKind::DropIndex, table_name, Some(name), None, vec![drop]));
+            }
+        }
+        Ok(steps)
+    }
+
+    async fn db_full_text_indices(dialect: SQLDialect, conn: &dyn Queryable, table_name: &str) -> Result<Vec<String>> {
+        if dialect == SQLDialect::MySQL {
+            let sql = format!("SHOW INDEX FROM `{}` WHERE Index_type = 'FULLTEXT'", table_name);
+            let result_set = Self::query(dialect, conn, Some(table_name), &sql).await?;
+            Ok(result_set.into_iter().map(|row| Self::row_string(dialect, table_name, &row, "Key_name")).collect::<Result<Vec<String>>>()?.into_iter().unique().collect())
+        } else {
+            let sql = format!("SELECT indexname FROM pg_indexes WHERE schemaname = 'public' AND tablename = '{}'", table_name);
+            let result_set = Self::query(dialect, conn, Some(table_name), &sql).await?;
+            Ok(result_set.into_iter().map(|row| Self::row_string(dialect, table_name, &row, "indexname")).collect::<Result<Vec<String>>>()?.into_iter().filter(|name| is_full_text_index_name(name)).collect())
+        }
+    }
+
+    fn create_table_step(dialect: SQLDialect, model: &Model) -> MigrationStep {
         // create table
-        let stmt = SQLCreateTableStatement::from(model).to_string(dialect);
-        conn.execute(Query::from(stmt)).await.unwrap();
+        let mut stmts = vec![SQLCreateTableStatement::from(model).to_string(dialect)];
         // create indices
         for index in model.indexes() {
             // primary is created when creating table
             if index.r#type().is_primary() { continue }
-            let stmt = index.to_sql_create(dialect, &model.table_name);
-            conn.execute(Query::from(stmt)).await.unwrap();
+            stmts.push(index.to_sql_create(dialect, &model.table_name));
         }
+        stmts.extend(Self::full_text_create_statements(dialect, model));
+        stmts.extend(Self::insensitive_index_create_statements(dialect, model));
+        let table = &model.table_name;
+        MigrationStep::new(MigrationStepKind::CreateTable, table, None, None, stmts).with_down(vec![format!("DROP TABLE {}", table.escape(dialect))])
+    }
+
+    // https://www.sqlite.org/lang_altertable.html#otheralter
+    fn sqlite_rebuild_table_step(model: &Model, db_columns: &HashSet<SQLColumn>, model_columns: &HashSet<SQLColumn>, manipulations: &Vec<ColumnManipulation>, table_has_records: bool) -> Result<MigrationStep> {
+        let dialect = SQLDialect::SQLite;
+        let table_name = &model.table_name;
+        let temp_table_name = format!("_teo_rebuild_{}", table_name);
+        let mut columns: Vec<String> = vec![];
+        let mut sources: Vec<String> = vec![];
+        for column in model_columns.iter().sorted_by(|a, b| a.name().cmp(b.name())) {
+            let default = manipulations.iter().find_map(|m| match m {
+                ColumnManipulation::AddColumn(c, Some(default)) if c.name() == column.name() => Some(ToSQLString::to_string(default, dialect)),
+                _ => None,
+            }).or(column.default().map(|d| d.to_owned()));
+            let renamed_from = manipulations.iter().find_map(|m| match m {
+                ColumnManipulation::RenameColumn { old, new } if new == column.name() => Some(old.as_str()),
+                _ => None,
+            });
+            let existing = db_columns.iter().find(|c| c.name() == renamed_from.unwrap_or(column.name()));
+            let source = if let Some(existing) = existing {
+                let name = existing.name();
+                match (column.not_null() && existing.null(), &default) {
+                    (true, Some(default)) => format!("COALESCE(`{name}`, {default})"),
+                    _ => format!("`{name}`"),
+                }
+            } else if let Some(default) = default {
+                default
+            } else if column.not_null() && !column.auto_increment() && table_has_records {
+                return Err(Self::non_null_column_error(dialect, table_name, column.name()).into());
+            } else {
+                // let the column default apply
+                continue
+            };
+            columns.push(format!("`{}`", column.name()));
+            sources.push(source);
+        }
+        let mut create = SQLCreateTableStatement::from(model);
+        create.table = temp_table_name.clone();
+        let mut recreate: Vec<String> = vec![];
+        for index in model.indexes() {
+            // primary is created when creating table
+            if index.r#type().is_primary() { continue }
+            recreate.push(index.to_sql_create(dialect, table_name));
+        }
+        // rows may get new rowids, the FTS5 table is rebuilt with the triggers dropped along with the old table
+        recreate.extend(sqlite_drop_statements(table_name));
+        recreate.extend(Self::full_text_create_statements(dialect, model));
+        recreate.extend(Self::insensitive_index_create_statements(dialect, model));
+        let stmts = Self::sqlite_rebuild_statements(table_name, &temp_table_name, create.to_string(dialect), &columns, &sources, recreate);
+        Ok(MigrationStep::new(MigrationStepKind::RebuildTable, table_name, None, None, stmts))
+    }
+
+    // the copy runs inside a savepoint with foreign keys off, `apply_rebuild` relies on this order
+    fn sqlite_rebuild_statements(table_name: &str, temp_table_name: &str, create: String, columns: &Vec<String>, sources: &Vec<String>, recreate: Vec<String>) -> Vec<String> {
+        let mut stmts = vec![
+            FOREIGN_KEYS_OFF.to_owned(),
+            format!("SAVEPOINT {REBUILD_SAVEPOINT}"),
+            create,
+            format!("INSERT INTO `{temp_table_name}`({}) SELECT {} FROM `{table_name}`", columns.join(","), sources.join(",")),
+            format!("DROP TABLE `{table_name}`"),
+            Self::rename_table_statement(SQLDialect::SQLite, temp_table_name, table_name),
+        ];
+        stmts.extend(recreate);
+        stmts.push(FOREIGN_KEY_CHECK.to_owned());
+        stmts.push(format!("RELEASE {REBUILD_SAVEPOINT}"));
+        stmts.push(FOREIGN_KEYS_ON.to_owned());
+        stmts
     }
 
     fn psql_alter_clauses(table: &str, old_column: &SQLColumn, new_column: &SQLColumn) -> Vec<String> {
         let mut result = vec![];
         let name = new_column.name();
-        let escape = SQLDialect::PostgreSQL.escape();
+        let dialect = SQLDialect::PostgreSQL;
+        let (table, name) = (table.escape(dialect), name.escape(dialect));
         if old_column.r#type() != new_column.r#type() {
-            result.push(format!("ALTER TABLE {escape}{table}{escape} ALTER COLUMN {escape}{name}{escape} TYPE {}", new_column.r#type().to_sql_string()));
+            result.push(format!("ALTER TABLE {table} ALTER COLUMN {name} TYPE {}", new_column.r#type().to_sql_string()));
         }
         if old_column.default().is_none() && new_column.default().is_some() {
-            result.push(format!("ALTER TABLE {escape}{table}{escape} ALTER COLUMN {escape}{name}{escape} SET DEFAULT {}", new_column.default().unwrap()));
+            result.push(format!("ALTER TABLE {table} ALTER COLUMN {name} SET DEFAULT {}", new_column.default().unwrap()));
         } else if old_column.default().is_some() && new_column.default().is_none() {
-            result.push(format!("ALTER TABLE {escape}{table}{escape} ALTER COLUMN {escape}{name}{escape} DROP DEFAULT"));
+            result.push(format!("ALTER TABLE {table} ALTER COLUMN {name} DROP DEFAULT"));
         } else if old_column.default().is_some() && new_column.default().is_some() {
             if old_column.default() == new_column.default() {
-                result.push(format!("ALTER TABLE {escape}{table}{escape} ALTER COLUMN {escape}{name}{escape} SET DEFAULT {}", new_column.default().unwrap()));
+                result.push(format!("ALTER TABLE {table} ALTER COLUMN {name} SET DEFAULT {}", new_column.default().unwrap()));
             }
         }
         result
@@ -311,30 +625,65 @@ impl SQLMigration {
         results.into_iter().collect()
     }
 
-    async fn db_indices(dialect: SQLDialect, conn: &dyn Queryable, model: &Model) -> HashSet<Index> {
+    async fn db_indices(dialect: SQLDialect, conn: &dyn Queryable, table_name: &str) -> Result<HashSet<Index>> {
         match dialect {
-            SQLDialect::PostgreSQL => Self::psql_db_indices(conn, model).await,
-            SQLDialect::MySQL => Self::mysql_db_indices(conn, model).await,
-            SQLDialect::SQLite => Self::sqlite_db_indices(conn, model).await,
-            _ => unreachable!(),
+            SQLDialect::PostgreSQL => Self::psql_db_indices(conn, table_name).await,
+            SQLDialect::MySQL => Self::mysql_db_indices(conn, table_name).await,
+            SQLDialect::SQLite => Self::sqlite_db_indices(conn, table_name).await,
+            SQLDialect::MSSQL => Self::mssql_db_indices(conn, table_name).await,
         }
     }
 
-    async fn mysql_db_indices(conn: &dyn Queryable, model: &Model) -> HashSet<Index> {
-        let table_name = &model.table_name;
+    async fn mssql_db_indices(conn: &dyn Queryable, table_name: &str) -> Result<HashSet<Index>> {
+        let sql = mssql_list_indices_query(table_name);
+        let result_set = Self::query(SQLDialect::MSSQL, conn, Some(table_name), &sql).await?;
+        let mut indices = vec![];
+        for row in result_set {
+            let is_primary = row.get("is_primary").and_then(|v| v.as_bool()).unwrap_or(false);
+            // SQL Server generates primary key names, normalize them like model indices
+            let index_name = if is_primary {
+                format!("PK_{}", table_name)
+            } else {
+                Self::row_string(SQLDialect::MSSQL, table_name, &row, "index_name")?
+            };
+            let column_name = Self::row_string(SQLDialect::MSSQL, table_name, &row, "column_name")?;
+            let order = Sort::from_desc_bool(row.get("is_descending").and_then(|v| v.as_bool()).unwrap_or(false));
+            if let Some(position) = indices.iter().position(|m: &Index| m.name() == &index_name) {
+                let model_index = indices.get_mut(position).unwrap();
+                let item = Item::new(column_name, order, None);
+                model_index.items.push(item);
+            } else {
+                let is_unique = row.get("is_unique").and_then(|v| v.as_bool()).unwrap_or(false);
+                let item = Item::new(column_name, order, None);
+                indices.push(Index::new(
+                    if is_primary { Type::Primary } else if is_unique { Type::Unique } else { Type::Index },
+                    index_name.clone(),
+                    vec![item],
+                ))
+            }
+        }
+        Ok(indices.into_iter().collect())
+    }
+
+    async fn mysql_db_indices(conn: &dyn Queryable, table_name: &str) -> Result<HashSet<Index>> {
         let sql = format!("SHOW INDEX FROM `{}`", table_name);
-        let result_set = conn.query(Query::from(sql)).await.unwrap();
+        let result_set = Self::query(SQLDialect::MySQL, conn, Some(table_name), &sql).await?;
         let mut indices = vec![];
         for row in result_set {
-            let index_name = row.get("Key_name").unwrap().to_string().unwrap();
-            let column_name = row.get("Column_name").unwrap().to_string().unwrap();
-            let order = Sort::from_mysql_str(row.get("Collation").unwrap().as_str().unwrap()).unwrap();
+            // full text indices are planned apart from model indices
+            if row.get("Index_type").and_then(|t| t.as_str()) == Some("FULLTEXT") {
+                continue;
+            }
+            let index_name = Self::row_string(SQLDialect::MySQL, table_name, &row, "Key_name")?;
+            let column_name = Self::row_string(SQLDialect::MySQL, table_name, &row, "Column_name")?;
+            // hashed and spatial indices have no collation
+            let order = row.get("Collation").and_then(|c| c.as_str()).and_then(Sort::from_mysql_str).unwrap_or(Sort::Asc);
             if let Some(position) = indices.iter().position(|m: &Index| m.name() == &index_name) {
                 let model_index = indices.get_mut(position).unwrap();
                 let item = Item::new(column_name, order, None);
                 model_index.items.push(item);
             } else {
-                let is_unique = !row.get("Non_unique").unwrap().as_bool().unwrap();
+                let is_unique = !Self::row_bool(SQLDialect::MySQL, table_name, &row, "Non_unique")?;
                 let item = Item::new(column_name, order, None);
                 indices.push(Index::new(
                     if &index_name == "PRIMARY" { Type::Primary } else if is_unique { Type::Unique } else { Type::Index },
@@ -343,11 +692,10 @@ impl SQLMigration {
                 ))
             }
         }
-        indices.into_iter().collect()
+        Ok(indices.into_iter().collect())
     }
 
-    async fn psql_db_indices(conn: &dyn Queryable, model: &Model) -> HashSet<Index> {
-        let table_name = &model.table_name;
+    async fn psql_db_indices(conn: &dyn Queryable, table_name: &str) -> Result<HashSet<Index>> {
         let sql = format!(r#"SELECT     irel.relname                           AS index_name,
            a.attname                              AS column_name,
            i.indisunique                          AS is_unique,
@@ -382,19 +730,21 @@ GROUP BY   tnsp.nspname,
            array_position(i.indkey, a.attnum),
            o.OPTION ORDER BY column_position
 "#);
-        let result_set = conn.query(Query::from(sql)).await.unwrap();
+        let result_set = Self::query(SQLDialect::PostgreSQL, conn, Some(table_name), &sql).await?;
         let mut indices = vec![];
         for row in result_set {
-            let index_name = row.get("index_name").unwrap().to_string().unwrap();
-            let column_name = row.get("column_name").unwrap().to_string().unwrap();
-            let order = Sort::from_str(row.get("order").unwrap().as_str().unwrap()).unwrap();
+            let index_name = Self::row_string(SQLDialect::PostgreSQL, table_name, &row, "index_name")?;
+            let column_name = Self::row_string(SQLDialect::PostgreSQL, table_name, &row, "column_name")?;
+            let order = Sort::from_str(&Self::row_string(SQLDialect::PostgreSQL, table_name, &row, "order")?).ok_or_else(|| {
+                Self::introspection_error(SQLDialect::PostgreSQL, table_name, "order")
+            })?;
             if let Some(position) = indices.iter().position(|m: &Index| m.name() == index_name) {
                 let model_index = indices.get_mut(position).unwrap();
                 let item = Item::new(column_name, order, None);
                 model_index.items.push(item);
             } else {
-                let is_unique = row.get("is_unique").unwrap().as_bool().unwrap();
-                let is_primary = row.get("is_primary").unwrap().as_bool().unwrap();
+                let is_unique = Self::row_bool(SQLDialect::PostgreSQL, table_name, &row, "is_unique")?;
+                let is_primary = Self::row_bool(SQLDialect::PostgreSQL, table_name, &row, "is_primary")?;
                 let item = Item::new(column_name, order, None);
                 indices.push(Index::new(
                     if is_primary { Type::Primary } else if is_unique { Type::Unique} else { Type::Index },
@@ -403,11 +753,10 @@ GROUP BY   tnsp.nspname,
                 ))
             }
         }
-        indices.into_iter().collect()
+        Ok(indices.into_iter().collect())
     }
 
-    async fn sqlite_db_indices(conn: &dyn Queryable, model: &Model) -> HashSet<Index> {
-        let table_name = &model.table_name;
+    async fn sqlite_db_indices(conn: &dyn Queryable, table_name: &str) -> Result<HashSet<Index>> {
         let sql = format!(r#"SELECT
     il.name as index_name,
     ii.name as column_name,
@@ -433,19 +782,23 @@ GROUP BY
     il.seq,
     ii.`desc`
 ORDER BY 1,6"#, table_name);
-        let result_set = conn.query(Query::from(sql)).await.unwrap();
+        let result_set = Self::query(SQLDialect::SQLite, conn, Some(table_name), &sql).await?;
         let mut indices = vec![];
         for row in result_set {
-            let index_name = row.get("index_name").unwrap().to_string().unwrap();
-            let column_name = row.get("column_name").unwrap().to_string().unwrap();
-            let order = Sort::from_desc_bool(row.get("desc").unwrap().as_bool().unwrap());
+            let index_name = Self::row_string(SQLDialect::SQLite, table_name, &row, "index_name")?;
+            // case insensitive indices are planned apart from model indices
+            if is_insensitive_index_name(&index_name) {
+                continue;
+            }
+            let column_name = Self::row_string(SQLDialect::SQLite, table_name, &row, "column_name")?;
+            let order = Sort::from_desc_bool(Self::row_bool(SQLDialect::SQLite, table_name, &row, "desc")?);
             if let Some(position) = indices.iter().position(|m: &Index| m.name() == &index_name) {
                 let model_index = indices.get_mut(position).unwrap();
                 let item = Item::new(column_name, order, None);
                 model_index.items.push(item);
             } else {
-                let is_unique = row.get("is_unique").unwrap().as_bool().unwrap();
-                let is_primary = row.get("is_primary").unwrap().as_bool().unwrap();
+                let is_unique = Self::row_bool(SQLDialect::SQLite, table_name, &row, "is_unique")?;
+                let is_primary = Self::row_bool(SQLDialect::SQLite, table_name, &row, "is_primary")?;
                 let item = Item::new(column_name, order, None);
                 indices.push(Index::new(
                     if is_primary { Type::Primary } else if is_unique { Type::Unique} else { Type::Index },
@@ -460,15 +813,45 @@ ORDER BY 1,6"#, table_name);
         }).is_some();
         if !includes_primary {
             let sql = format!("SELECT * FROM pragma_table_info(\"{table_name}\") WHERE pk = 1");
-            let result_set = conn.query(Query::from(sql)).await.unwrap();
-            let row = result_set.into_single().unwrap();
-            let column_name = row.get("name").unwrap().to_string().unwrap();
-            let leaked = column_name;
-            let index = Index::new(Type::Primary, format!("sqlite_autoindex_{table_name}_1"), vec![
-                Item::new(leaked, Sort::Asc, None)
-            ]);
-            results.push(index);
+            let result_set = Self::query(SQLDialect::SQLite, conn, Some(table_name), &sql).await?;
+            if let Ok(row) = result_set.into_single() {
+                let column_name = Self::row_string(SQLDialect::SQLite, table_name, &row, "name")?;
+                let leaked = column_name;
+                let index = Index::new(Type::Primary, format!("sqlite_autoindex_{table_name}_1"), vec![
+                    Item::new(leaked, Sort::Asc, None)
+                ]);
+                results.push(index);
+            }
         }
-        results.into_iter().collect()
+        Ok(results.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SQLMigration;
+
+    #[test]
+    fn sqlite_rebuild_copies_inside_a_savepoint_with_foreign_keys_off() {
+        let stmts = SQLMigration::sqlite_rebuild_statements(
+            "users",
+            "_teo_rebuild_users",
+            "CREATE TABLE `_teo_rebuild_users`( `id` INTEGER PRIMARY KEY, `name` TEXT NOT NULL )".to_owned(),
+            &vec!["`id`".to_owned(), "`name`".to_owned()],
+            &vec!["`id`".to_owned(), "COALESCE(`name`, '')".to_owned()],
+            vec!["CREATE UNIQUE INDEX `users_name` ON `users`(`name`)".to_owned()],
+        );
+        assert_eq!(stmts, vec![
+            "PRAGMA foreign_keys = OFF".to_owned(),
+            "SAVEPOINT teo_rebuild".to_owned(),
+            "CREATE TABLE `_teo_rebuild_users`( `id` INTEGER PRIMARY KEY, `name` TEXT NOT NULL )".to_owned(),
+            "INSERT INTO `_teo_rebuild_users`(`id`,`name`) SELECT `id`,COALESCE(`name`, '') FROM `users`".to_owned(),
+            "DROP TABLE `users`".to_owned(),
+            "ALTER TABLE `_teo_rebuild_users` RENAME TO `users`".to_owned(),
+            "CREATE UNIQUE INDEX `users_name` ON `users`(`name`)".to_owned(),
+            "PRAGMA foreign_key_check".to_owned(),
+            "RELEASE teo_rebuild".to_owned(),
+            "PRAGMA foreign_keys = ON".to_owned(),
+        ]);
     }
 }
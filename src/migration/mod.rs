@@ -1,2 +1,6 @@
 pub(crate) mod migrate;
 pub(crate) mod sql;
+pub(crate) mod full_text;
+pub(crate) mod insensitive;
+pub mod plan;
+pub mod history;
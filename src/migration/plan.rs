@@ -0,0 +1,114 @@
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationStepKind {
+    CreateTable,
+    DropTable,
+    RenameTable,
+    RebuildTable,
+    AddColumn,
+    AlterColumn,
+    RenameColumn,
+    DropColumn,
+    CreateIndex,
+    DropIndex,
+}
+
+impl MigrationStepKind {
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MigrationStepKind::CreateTable => "create table",
+            MigrationStepKind::DropTable => "drop table",
+            MigrationStepKind::RenameTable => "rename table",
+            MigrationStepKind::RebuildTable => "rebuild table",
+            MigrationStepKind::AddColumn => "add column",
+            MigrationStepKind::AlterColumn => "alter column",
+            MigrationStepKind::RenameColumn => "rename column",
+            MigrationStepKind::DropColumn => "drop column",
+            MigrationStepKind::CreateIndex => "create index",
+            MigrationStepKind::DropIndex => "drop index",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MigrationStep {
+    pub kind: MigrationStepKind,
+    pub table: String,
+    pub target: Option<String>,
+    pub previous: Option<String>,
+    pub statements: Vec<String>,
+    pub down: Vec<String>,
+}
+
+impl MigrationStep {
+
+    pub(crate) fn new(kind: MigrationStepKind, table: impl Into<String>, target: Option<String>, previous: Option<String>, statements: Vec<String>) -> Self {
+        Self { kind, table: table.into(), target, previous, statements, down: vec![] }
+    }
+
+    pub(crate) fn with_down(mut self, down: Vec<String>) -> Self {
+        self.down = down;
+        self
+    }
+
+    pub fn is_reversible(&self) -> bool {
+        !self.down.is_empty()
+    }
+
+    pub fn summary(&self) -> String {
+        let kind = self.kind.as_str();
+        let table = &self.table;
+        match (&self.target, &self.previous) {
+            (None, Some(previous)) => format!("{kind} `{previous}' to `{table}'"),
+            (Some(target), Some(previous)) => format!("{kind} `{previous}' to `{target}' on `{table}'"),
+            (Some(target), None) => format!("{kind} `{target}' on `{table}'"),
+            (None, None) => format!("{kind} `{table}'"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MigrationPlan {
+    pub steps: Vec<MigrationStep>,
+}
+
+impl MigrationPlan {
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn statements(&self) -> Vec<&str> {
+        self.steps.iter().flat_map(|s| s.statements.iter().map(|s| s.as_str())).collect()
+    }
+
+    pub fn down_statements(&self) -> Vec<&str> {
+        self.steps.iter().rev().flat_map(|s| s.down.iter().map(|s| s.as_str())).collect()
+    }
+
+    pub fn summary(&self) -> String {
+        if self.is_empty() {
+            return "no migration needed".to_owned();
+        }
+        self.steps.iter().map(|s| format!("- {}", s.summary())).collect::<Vec<String>>().join("\n")
+    }
+
+    pub(crate) fn push(&mut self, step: MigrationStep) {
+        self.steps.push(step);
+    }
+}
+
+impl Display for MigrationPlan {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for step in &self.steps {
+            writeln!(f, "-- {}", step.summary())?;
+            for statement in &step.statements {
+                writeln!(f, "{};", statement.trim_end_matches(';'))?;
+            }
+        }
+        Ok(())
+    }
+}
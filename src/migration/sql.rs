@@ -22,3 +22,38 @@ pub(crate) fn sqlite_auto_increment_query(table_name: &str) -> String {
 pub(crate) fn psql_is_auto_increment(table_name: &str, column_name: &str) -> String {
     format!("select relname from pg_class where relname = '{}_{}_seq'", table_name, column_name)
 }
+
+pub(crate) fn mssql_columns_query(table_name: &str) -> String {
+    format!("SELECT
+  c.COLUMN_NAME AS column_name,
+  c.DATA_TYPE AS data_type,
+  c.CHARACTER_MAXIMUM_LENGTH AS character_maximum_length,
+  c.NUMERIC_PRECISION AS numeric_precision,
+  c.NUMERIC_SCALE AS numeric_scale,
+  c.DATETIME_PRECISION AS datetime_precision,
+  c.IS_NULLABLE AS is_nullable,
+  COLUMNPROPERTY(OBJECT_ID(c.TABLE_SCHEMA + '.' + c.TABLE_NAME), c.COLUMN_NAME, 'IsIdentity') AS is_identity,
+  CASE WHEN EXISTS (
+    SELECT 1 FROM INFORMATION_SCHEMA.TABLE_CONSTRAINTS AS tc
+    JOIN INFORMATION_SCHEMA.KEY_COLUMN_USAGE AS k ON k.CONSTRAINT_NAME = tc.CONSTRAINT_NAME AND k.TABLE_NAME = tc.TABLE_NAME
+    WHERE tc.CONSTRAINT_TYPE = 'PRIMARY KEY' AND tc.TABLE_NAME = c.TABLE_NAME AND k.COLUMN_NAME = c.COLUMN_NAME
+  ) THEN 1 ELSE 0 END AS is_primary
+FROM INFORMATION_SCHEMA.COLUMNS AS c
+WHERE c.TABLE_NAME = '{}'
+ORDER BY c.ORDINAL_POSITION", table_name)
+}
+
+pub(crate) fn mssql_list_indices_query(table_name: &str) -> String {
+    format!("SELECT
+  i.name AS index_name,
+  col.name AS column_name,
+  i.is_unique AS is_unique,
+  i.is_primary_key AS is_primary,
+  ic.is_descending_key AS is_descending
+FROM sys.indexes AS i
+JOIN sys.index_columns AS ic ON ic.object_id = i.object_id AND ic.index_id = i.index_id
+JOIN sys.columns AS col ON col.object_id = ic.object_id AND col.column_id = ic.column_id
+JOIN sys.tables AS t ON t.object_id = i.object_id
+WHERE t.name = '{}' AND i.name IS NOT NULL AND ic.is_included_column = 0
+ORDER BY i.name, ic.key_ordinal", table_name)
+}
@@ -0,0 +1,286 @@
+use teo_result::{Error, Result};
+use teo_runtime::model::field::column_named::ColumnNamed;
+use teo_runtime::model::Model;
+use teo_runtime::model::relation::Relation;
+use teo_runtime::namespace::Namespace;
+use teo_teon::{teon, Value};
+use crate::query::{Query, DISTINCT_NUMBER_COLUMN, ROW_NUMBER_COLUMN};
+use crate::schema::dialect::SQLDialect;
+use crate::schema::value::encode::{SQLEscape, ToSQLString};
+use crate::stmts::params::SQLParams;
+use crate::stmts::select::r#where::WhereClause::And;
+use crate::stmts::SQL;
+
+pub(crate) const RELATION_LOAD_STRATEGY: &str = "relationLoadStrategy";
+
+impl Query {
+
+    // `{ "relationLoadStrategy": "join" }` loads includes inside the main statement instead of one query per level
+    pub(crate) fn loads_relations_with_join(value: &Value) -> bool {
+        value.get(RELATION_LOAD_STRATEGY).and_then(|v| v.as_str()) == Some("join")
+    }
+
+    // one JSON array column named after each included relation, correlated to the rows named by `qualifier`,
+    // these are correlated subqueries in the select list rather than lateral joins: SQLite has no LATERAL,
+    // MySQL only from 8.0.14 and SQL Server spells it OUTER APPLY, while an aggregating subquery returns
+    // exactly one row per parent everywhere and is evaluated per row just like a lateral join
+    pub(crate) fn relation_json_columns(namespace: &Namespace, model: &Model, value: &Value, dialect: SQLDialect, qualifier: &str, depth: usize, params: &mut SQLParams) -> Result<Vec<String>> {
+        let Some(include) = value.get("include").and_then(|i| i.as_dictionary()) else {
+            return Ok(vec![]);
+        };
+        let mut retval = vec![];
+        for (name, nested) in include {
+            let Some(relation) = model.relation(name) else { continue };
+            let nested = if nested.is_dictionary() { nested.clone() } else { teon!({}) };
+            let expr = Self::relation_json(namespace, model, relation, &nested, dialect, qualifier, depth, params)?;
+            // nested arrays are kept as JSON instead of being escaped into strings
+            let expr = if dialect == SQLDialect::MSSQL { format!("JSON_QUERY({expr})") } else { expr };
+            retval.push(format!("{} AS {}", expr, name.escape(dialect)));
+        }
+        Ok(retval)
+    }
+
+    fn relation_json(namespace: &Namespace, model: &Model, relation: &Relation, value: &Value, dialect: SQLDialect, qualifier: &str, depth: usize, params: &mut SQLParams) -> Result<String> {
+        let (opposite_model, _) = namespace.opposite_relation(relation);
+        let alias = format!("r{depth}");
+        let link = if relation.has_join_table() {
+            let (through_model, through_relation) = namespace.through_relation(relation);
+            let (_, through_opposite_relation) = namespace.through_opposite_relation(relation);
+            let parent_pairs: Vec<(&str, &str)> = through_relation.iter().map(|(f, r)| {
+                (through_model.field(f).unwrap().column_name(), model.field(r).unwrap().column_name())
+            }).collect();
+            let child_pairs: Vec<(&str, &str)> = through_opposite_relation.iter().map(|(f, r)| {
+                (through_model.field(f).unwrap().column_name(), opposite_model.field(r).unwrap().column_name())
+            }).collect();
+            Self::join_table_link(dialect, &through_model.table_name, &format!("j{depth}"), &alias, qualifier, &parent_pairs, &child_pairs)
+        } else {
+            let pairs: Vec<(&str, &str)> = relation.iter().map(|(f, r)| {
+                (opposite_model.field(r).unwrap().column_name(), model.field(f).unwrap().column_name())
+            }).collect();
+            Self::relation_link(dialect, &alias, qualifier, &pairs)
+        };
+        let mut names: Vec<String> = match Self::projection(namespace, opposite_model, value)? {
+            Some(projection) => projection.iter().map(|c| c.to_string()).collect(),
+            None => opposite_model.cache.save_keys.clone(),
+        };
+        let mut columns: Vec<String> = names.iter().map(|c| format!("{alias}.{} AS {}", c.escape(dialect), c.escape(dialect))).collect();
+        let nested_columns = Self::relation_json_columns(namespace, opposite_model, value, dialect, &alias, depth + 1, params)?;
+        let relation_names: Vec<String> = value.get("include").and_then(|i| i.as_dictionary()).map(|i| {
+            i.keys().filter(|k| opposite_model.relation(k).is_some()).cloned().collect()
+        }).unwrap_or_default();
+        columns.extend(nested_columns);
+        names.extend(relation_names.iter().cloned());
+        names.push(ROW_NUMBER_COLUMN.to_owned());
+        let distinct = Self::distinct_columns(opposite_model, value, dialect, Some(&alias))?;
+        let mut conditions = vec![link];
+        if let Some(nested_where) = value.get("where") {
+            if nested_where.as_dictionary().map_or(true, |w| !w.is_empty()) {
+                conditions.push(Self::r#where(namespace, opposite_model, nested_where, dialect, Some(&alias), params)?);
+            }
+        }
+        let negative_take = value.get("take").and_then(|t| t.as_int64()).is_some_and(|t| t.is_negative());
+        // JSON aggregates don't keep row order reliably, the decoder sorts by the row number
+        let natural_order_value = value.get("orderBy").cloned().unwrap_or_else(|| Self::default_asc_order(opposite_model));
+        let natural_order = Self::order_by(namespace, opposite_model, &natural_order_value, dialect, &alias, false, params)?;
+        let order = if value.get("orderBy").is_some() || negative_take {
+            Some(Self::order_by(namespace, opposite_model, &natural_order_value, dialect, &alias, negative_take, params)?)
+        } else {
+            None
+        };
+        let from = format!("{} AS {alias}", opposite_model.table_name.escape(dialect));
+        let inner = Self::relation_rows_statement(dialect, &from, columns, conditions, &natural_order, order.as_deref(), &distinct, Self::window_range(value), negative_take);
+        let pairs = |wrap_json: bool| names.iter().map(|name| {
+            let column = format!("o.{}", name.escape(dialect));
+            let column = if wrap_json && relation_names.contains(name) { format!("json({column})") } else { column };
+            format!("'{}', {}", name, column)
+        }).collect::<Vec<String>>().join(", ");
+        Ok(match dialect {
+            SQLDialect::PostgreSQL => format!("(SELECT COALESCE(json_agg(row_to_json(o)), '[]'::json) FROM ({inner}) AS o)"),
+            SQLDialect::MySQL => format!("(SELECT COALESCE(JSON_ARRAYAGG(JSON_OBJECT({})), JSON_ARRAY()) FROM ({inner}) AS o)", pairs(false)),
+            SQLDialect::SQLite => format!("(SELECT COALESCE(json_group_array(json_object({})), json_array()) FROM ({inner}) AS o)", pairs(true)),
+            SQLDialect::MSSQL => format!("COALESCE(({inner} FOR JSON PATH, INCLUDE_NULL_VALUES), '[]')"),
+        })
+    }
+
+    // `pairs` are columns of the included rows `alias` and of the parent rows `qualifier`
+    pub(crate) fn relation_link(dialect: SQLDialect, alias: &str, qualifier: &str, pairs: &Vec<(&str, &str)>) -> String {
+        And(pairs.iter().map(|(child, parent)| {
+            format!("{alias}.{} = {qualifier}.{}", child.escape(dialect), parent.escape(dialect))
+        }).collect()).to_string(dialect)
+    }
+
+    // `parent_pairs` and `child_pairs` are columns of the join table and of the rows it links
+    pub(crate) fn join_table_link(dialect: SQLDialect, join_table: &str, join_alias: &str, alias: &str, qualifier: &str, parent_pairs: &Vec<(&str, &str)>, child_pairs: &Vec<(&str, &str)>) -> String {
+        let mut conditions: Vec<String> = parent_pairs.iter().map(|(join, parent)| {
+            format!("{join_alias}.{} = {qualifier}.{}", join.escape(dialect), parent.escape(dialect))
+        }).collect();
+        conditions.extend(child_pairs.iter().map(|(join, child)| {
+            format!("{join_alias}.{} = {alias}.{}", join.escape(dialect), child.escape(dialect))
+        }));
+        format!("EXISTS (SELECT 1 FROM {} AS {join_alias} WHERE {})", join_table.escape(dialect), And(conditions).to_string(dialect))
+    }
+
+    // escaped columns of the `distinct` field names, qualified when given a table alias
+    pub(crate) fn distinct_columns(model: &Model, value: &Value, dialect: SQLDialect, qualifier: Option<&str>) -> Result<Vec<String>> {
+        let Some(distinct) = value.get("distinct") else {
+            return Ok(vec![]);
+        };
+        let Some(keys) = distinct.as_array() else {
+            return Err(Error::new("distinct should be an array of field names"));
+        };
+        keys.iter().map(|k| {
+            let Some(name) = k.as_str() else {
+                return Err(Error::new("distinct should be an array of field names"));
+            };
+            let Some(field) = model.field(name) else {
+                return Err(Error::new(format!("distinct field `{}' is not defined", name)));
+            };
+            let column = field.column_name().escape(dialect);
+            Ok(match qualifier {
+                Some(qualifier) => format!("{qualifier}.{column}"),
+                None => column,
+            })
+        }).collect()
+    }
+
+    // the rows of one included relation, numbered in `natural_order` for the decoder, with `distinct`
+    // the first row of each group is kept before paging
+    pub(crate) fn relation_rows_statement(
+        dialect: SQLDialect,
+        from: &str,
+        mut columns: Vec<String>,
+        conditions: Vec<String>,
+        natural_order: &str,
+        order: Option<&str>,
+        distinct: &Vec<String>,
+        (skip, take): (u64, Option<u64>),
+        negative_take: bool,
+    ) -> String {
+        columns.push(format!("ROW_NUMBER() OVER (ORDER BY {natural_order}) AS {}", ROW_NUMBER_COLUMN.escape(dialect)));
+        if !distinct.is_empty() {
+            columns.push(format!("ROW_NUMBER() OVER (PARTITION BY {} ORDER BY {natural_order}) AS {}", distinct.join(","), DISTINCT_NUMBER_COLUMN.escape(dialect)));
+        }
+        let column_refs: Vec<&str> = columns.iter().map(|c| c.as_str()).collect();
+        let mut stmt = SQL::select(Some(&column_refs), from);
+        stmt.r#where(And(conditions).to_string(dialect));
+        let deduplicated = if distinct.is_empty() {
+            None
+        } else {
+            Some(format!("({}) AS d", stmt.to_string(dialect)))
+        };
+        let mut stmt = if let Some(from) = &deduplicated {
+            let mut outer = SQL::select(None, from);
+            outer.r#where(format!("d.{} = 1", DISTINCT_NUMBER_COLUMN.escape(dialect)));
+            if order.is_some() {
+                outer.order_by(format!("d.{} {}", ROW_NUMBER_COLUMN.escape(dialect), if negative_take { "DESC" } else { "ASC" }));
+            }
+            outer
+        } else {
+            if let Some(order) = order {
+                stmt.order_by(order.to_owned());
+            }
+            stmt
+        };
+        if skip > 0 || take.is_some() {
+            stmt.limit(take.unwrap_or(if dialect == SQLDialect::MySQL { u64::MAX } else { i64::MAX as u64 }), skip);
+        }
+        stmt.to_string(dialect)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use teo_parser::r#type::Type;
+    use teo_teon::Value;
+    use crate::query::Query;
+    use crate::schema::dialect::SQLDialect;
+    use crate::schema::value::encode::SQLEscape;
+    use crate::stmts::params::SQLParams;
+
+    const DIALECTS: [SQLDialect; 4] = [SQLDialect::PostgreSQL, SQLDialect::MySQL, SQLDialect::SQLite, SQLDialect::MSSQL];
+
+    const RELATION_KINDS: [fn(SQLDialect) -> String; 3] = [one_to_many_link, many_to_one_link, many_to_many_link];
+
+    // user has many posts
+    fn one_to_many_link(dialect: SQLDialect) -> String {
+        Query::relation_link(dialect, "r1", &"users".escape(dialect), &vec![("userId", "id")])
+    }
+
+    // post belongs to a user
+    fn many_to_one_link(dialect: SQLDialect) -> String {
+        Query::relation_link(dialect, "r1", &"posts".escape(dialect), &vec![("id", "userId")])
+    }
+
+    // tag has many posts through `_PostToTag`
+    fn many_to_many_link(dialect: SQLDialect) -> String {
+        Query::join_table_link(dialect, "_PostToTag", "j1", "r1", &"tags".escape(dialect), &vec![("tagId", "id")], &vec![("postId", "id")])
+    }
+
+    // `{ "where": { "published": true }, "orderBy": [{ "createdAt": "desc" }], "take": 2 }` with an optional
+    // `"distinct": ["authorId"]` on the included rows of `posts`
+    fn included_rows(dialect: SQLDialect, link: String, distinct: bool) -> (String, usize) {
+        let mut params = SQLParams::new(dialect);
+        let published = Query::where_entry("r1.published", &Type::Bool, false, &Value::Bool(true), dialect, &mut params).unwrap();
+        let order = format!("r1.{} DESC", "createdAt".escape(dialect));
+        let distinct = if distinct { vec![format!("r1.{}", "authorId".escape(dialect))] } else { vec![] };
+        let sql = Query::relation_rows_statement(
+            dialect,
+            &format!("{} AS r1", "posts".escape(dialect)),
+            vec![format!("r1.{} AS {}", "id".escape(dialect), "id".escape(dialect))],
+            vec![link, published],
+            &order,
+            Some(&order),
+            &distinct,
+            (0, Some(2)),
+            false,
+        );
+        let (sql, values) = params.finish(sql);
+        (sql, values.len())
+    }
+
+    #[test]
+    fn links_every_relation_kind() {
+        assert_eq!(one_to_many_link(SQLDialect::PostgreSQL), r#"r1."userId" = "users"."id""#);
+        assert_eq!(one_to_many_link(SQLDialect::MySQL), "r1.`userId` = `users`.`id`");
+        assert_eq!(one_to_many_link(SQLDialect::SQLite), "r1.`userId` = `users`.`id`");
+        assert_eq!(one_to_many_link(SQLDialect::MSSQL), "r1.[userId] = [users].[id]");
+        assert_eq!(many_to_one_link(SQLDialect::PostgreSQL), r#"r1."id" = "posts"."userId""#);
+        assert_eq!(many_to_one_link(SQLDialect::MySQL), "r1.`id` = `posts`.`userId`");
+        assert_eq!(many_to_one_link(SQLDialect::SQLite), "r1.`id` = `posts`.`userId`");
+        assert_eq!(many_to_one_link(SQLDialect::MSSQL), "r1.[id] = [posts].[userId]");
+        assert_eq!(many_to_many_link(SQLDialect::PostgreSQL), r#"EXISTS (SELECT 1 FROM "_PostToTag" AS j1 WHERE j1."tagId" = "tags"."id" AND j1."postId" = r1."id")"#);
+        assert_eq!(many_to_many_link(SQLDialect::MySQL), "EXISTS (SELECT 1 FROM `_PostToTag` AS j1 WHERE j1.`tagId` = `tags`.`id` AND j1.`postId` = r1.`id`)");
+        assert_eq!(many_to_many_link(SQLDialect::SQLite), "EXISTS (SELECT 1 FROM `_PostToTag` AS j1 WHERE j1.`tagId` = `tags`.`id` AND j1.`postId` = r1.`id`)");
+        assert_eq!(many_to_many_link(SQLDialect::MSSQL), "EXISTS (SELECT 1 FROM [_PostToTag] AS j1 WHERE j1.[tagId] = [tags].[id] AND j1.[postId] = r1.[id])");
+    }
+
+    #[test]
+    fn where_and_order_by_on_every_relation_kind() {
+        for link in RELATION_KINDS {
+            for dialect in DIALECTS {
+                let expected = match dialect {
+                    SQLDialect::PostgreSQL => format!(r#"SELECT r1."id" AS "id", ROW_NUMBER() OVER (ORDER BY r1."createdAt" DESC) AS "_teo_rn" from "posts" AS r1 WHERE {} AND "r1"."published" = $1 ORDER BY r1."createdAt" DESC LIMIT 2 OFFSET 0"#, link(dialect)),
+                    SQLDialect::MySQL => format!("SELECT r1.`id` AS `id`, ROW_NUMBER() OVER (ORDER BY r1.`createdAt` DESC) AS `_teo_rn` from `posts` AS r1 WHERE {} AND `r1`.`published` = ? ORDER BY r1.`createdAt` DESC LIMIT 0,2", link(dialect)),
+                    SQLDialect::SQLite => format!("SELECT r1.`id` AS `id`, ROW_NUMBER() OVER (ORDER BY r1.`createdAt` DESC) AS `_teo_rn` from `posts` AS r1 WHERE {} AND `r1`.`published` = ?1 ORDER BY r1.`createdAt` DESC LIMIT 0,2", link(dialect)),
+                    SQLDialect::MSSQL => format!("SELECT r1.[id] AS [id], ROW_NUMBER() OVER (ORDER BY r1.[createdAt] DESC) AS [_teo_rn] from [posts] AS r1 WHERE {} AND [r1].[published] = @P1 ORDER BY r1.[createdAt] DESC OFFSET 0 ROWS FETCH NEXT 2 ROWS ONLY", link(dialect)),
+                };
+                assert_eq!(included_rows(dialect, link(dialect), false), (expected, 1));
+            }
+        }
+    }
+
+    #[test]
+    fn distinct_on_every_relation_kind() {
+        for link in RELATION_KINDS {
+            for dialect in DIALECTS {
+                let expected = match dialect {
+                    SQLDialect::PostgreSQL => format!(r#"SELECT * from (SELECT r1."id" AS "id", ROW_NUMBER() OVER (ORDER BY r1."createdAt" DESC) AS "_teo_rn", ROW_NUMBER() OVER (PARTITION BY r1."authorId" ORDER BY r1."createdAt" DESC) AS "_teo_dn" from "posts" AS r1 WHERE {} AND "r1"."published" = $1) AS d WHERE d."_teo_dn" = 1 ORDER BY d."_teo_rn" ASC LIMIT 2 OFFSET 0"#, link(dialect)),
+                    SQLDialect::MySQL => format!("SELECT * from (SELECT r1.`id` AS `id`, ROW_NUMBER() OVER (ORDER BY r1.`createdAt` DESC) AS `_teo_rn`, ROW_NUMBER() OVER (PARTITION BY r1.`authorId` ORDER BY r1.`createdAt` DESC) AS `_teo_dn` from `posts` AS r1 WHERE {} AND `r1`.`published` = ?) AS d WHERE d.`_teo_dn` = 1 ORDER BY d.`_teo_rn` ASC LIMIT 0,2", link(dialect)),
+                    SQLDialect::SQLite => format!("SELECT * from (SELECT r1.`id` AS `id`, ROW_NUMBER() OVER (ORDER BY r1.`createdAt` DESC) AS `_teo_rn`, ROW_NUMBER() OVER (PARTITION BY r1.`authorId` ORDER BY r1.`createdAt` DESC) AS `_teo_dn` from `posts` AS r1 WHERE {} AND `r1`.`published` = ?1) AS d WHERE d.`_teo_dn` = 1 ORDER BY d.`_teo_rn` ASC LIMIT 0,2", link(dialect)),
+                    SQLDialect::MSSQL => format!("SELECT * from (SELECT r1.[id] AS [id], ROW_NUMBER() OVER (ORDER BY r1.[createdAt] DESC) AS [_teo_rn], ROW_NUMBER() OVER (PARTITION BY r1.[authorId] ORDER BY r1.[createdAt] DESC) AS [_teo_dn] from [posts] AS r1 WHERE {} AND [r1].[published] = @P1) AS d WHERE d.[_teo_dn] = 1 ORDER BY d.[_teo_rn] ASC OFFSET 0 ROWS FETCH NEXT 2 ROWS ONLY", link(dialect)),
+                };
+                assert_eq!(included_rows(dialect, link(dialect), true), (expected, 1));
+            }
+        }
+    }
+}
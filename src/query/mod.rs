@@ -7,16 +7,27 @@ use once_cell::sync::Lazy;
 use teo_parser::r#type::Type;
 use teo_result::{Error, Result};
 use crate::schema::dialect::SQLDialect;
-use crate::schema::value::encode::{IfIMode, SQLEscape, ToLike, ToSQLString, ToWrapped, ValueToSQLString, WrapInArray};
+use crate::schema::value::encode::{is_json_type, json_path_segments, SQLEscape, ToSQLString, ToWrapped};
+use crate::migration::full_text::{full_text_table_name, psql_document, FULL_TEXT_CONFIG};
 use crate::stmts::select::r#where::{ToWrappedSQLString, WhereClause};
-use crate::stmts::select::r#where::WhereClause::{And, Not};
+use crate::stmts::select::r#where::WhereClause::{And, Not, Or};
+use crate::stmts::select::lock::{SQLLock, SQLLockMode, SQLLockWait};
 use crate::stmts::SQL;
+use crate::stmts::params::SQLParams;
+use crate::exts::model::ModelExt;
 use teo_runtime::model::{Model, object::Object, object::input::Input};
+use teo_runtime::model::relation::Relation;
 use teo_runtime::model::field::column_named::ColumnNamed;
 use teo_runtime::model::field::typed::Typed;
 use teo_runtime::namespace::Namespace;
 use teo_teon::Value;
 
+pub(crate) mod include;
+
+pub(crate) const ROW_NUMBER_COLUMN: &str = "_teo_rn";
+pub(crate) const DISTINCT_NUMBER_COLUMN: &str = "_teo_dn";
+pub(crate) const RELATION_COUNT_PREFIX: &str = "_count";
+
 pub(crate) struct Query { }
 
 impl Query {
@@ -25,26 +36,27 @@ impl Query {
         format!("{} {} {}", lhs.as_ref(), op, rhs)
     }
 
-    pub(crate) fn where_from_identifier(object: &Object, dialect: SQLDialect) -> String {
-        Self::where_from_value(object.model(), &object.identifier(), dialect)
+    pub(crate) fn where_from_identifier(object: &Object, dialect: SQLDialect, params: &mut SQLParams) -> String {
+        Self::where_from_value(object.model(), &object.identifier(), dialect, params)
     }
 
-    pub(crate) fn where_from_previous_identifier(object: &Object, dialect: SQLDialect) -> String {
-        Self::where_from_value(object.model(), &object.previous_identifier(), dialect)
+    pub(crate) fn where_from_previous_identifier(object: &Object, dialect: SQLDialect, params: &mut SQLParams) -> String {
+        Self::where_from_value(object.model(), &object.previous_identifier(), dialect, params)
     }
 
     fn where_entry_array(
         column_name: &str,
         r#type: &Type,
-        optional: bool,
         value: &Value,
         op: &str,
-        dialect: SQLDialect,
+        i_mode: bool,
+        params: &mut SQLParams,
     ) -> String {
         let arr_val = value.as_array().unwrap();
         let mut arr: Vec<String> = Vec::new();
         for val in arr_val {
-            arr.push(val.to_sql_string(r#type, optional, dialect));
+            let placeholder = params.push(val, r#type);
+            arr.push(Self::insensitive_value(placeholder, i_mode, params.dialect()));
         }
         Query::where_item(column_name, op, &arr.join(", ").to_wrapped())
     }
@@ -55,96 +67,230 @@ impl Query {
         optional: bool,
         value: &Value,
         dialect: SQLDialect,
-    ) -> String {
+        params: &mut SQLParams,
+    ) -> Result<String> {
         let column_name = escape_wisdom(column_name, dialect);
+        if is_json_type(r#type) {
+            return Self::where_entry_json(&column_name, value, dialect, params);
+        }
         if let Some(map) = value.as_dictionary() {
+            let i_mode = Input::has_i_mode(map) && r#type.unwrap_optional().is_string();
+            let insensitive_column = Self::insensitive_column(&column_name, i_mode, dialect);
+            let like = if i_mode && dialect == SQLDialect::PostgreSQL { "ILIKE" } else { "LIKE" };
             let mut result: Vec<String> = vec![];
             for (key, value) in map {
                 match key.as_str() {
                     "equals" => if value.is_null() {
                         result.push(Self::where_item(&column_name, "IS", "NULL"));
                     } else {
-                        result.push(Self::where_item(&column_name, "=", &value.to_sql_string(r#type, optional, dialect)));
+                        let rhs = Self::insensitive_value(params.push(value, r#type), i_mode, dialect);
+                        result.push(Self::where_item(&insensitive_column, "=", &rhs));
                     }
                     "not" => if value.is_null() {
                         result.push(Self::where_item(&column_name, "IS NOT", "NULL"));
                     } else {
-                        result.push(Self::where_item(&column_name, "<>", &value.to_sql_string(r#type, optional, dialect)));
+                        let rhs = Self::insensitive_value(params.push(value, r#type), i_mode, dialect);
+                        result.push(Self::where_item(&insensitive_column, "<>", &rhs));
                     }
                     "gt" => {
-                        result.push(Self::where_item(&column_name, ">", &value.to_sql_string(r#type, false, dialect)));
+                        result.push(Self::where_item(&column_name, ">", &params.push(value, r#type)));
                     }
                     "gte" => {
-                        result.push(Self::where_item(&column_name, ">=", &value.to_sql_string(r#type, false, dialect)));
+                        result.push(Self::where_item(&column_name, ">=", &params.push(value, r#type)));
                     }
                     "lt" => {
-                        result.push(Self::where_item(&column_name, "<", &value.to_sql_string(r#type, false, dialect)));
+                        result.push(Self::where_item(&column_name, "<", &params.push(value, r#type)));
                     }
                     "lte" => {
-                        result.push(Self::where_item(&column_name, "<=", &value.to_sql_string(r#type, false, dialect)));
+                        result.push(Self::where_item(&column_name, "<=", &params.push(value, r#type)));
                     }
                     "in" => {
                         if !value.as_array().unwrap().is_empty() {
-                            result.push(Self::where_entry_array(&column_name, r#type, optional, value, "IN", dialect));
+                            result.push(Self::where_entry_array(&insensitive_column, r#type, value, "IN", i_mode, params));
                         } else {
-                            result.push("FALSE".to_owned())
+                            result.push(Self::constant_condition(false, dialect))
                         }
                     }
                     "notIn" => {
                         if !value.as_array().unwrap().is_empty() {
-                            result.push(Self::where_entry_array(&column_name, r#type, optional, value, "NOT IN", dialect));
+                            result.push(Self::where_entry_array(&insensitive_column, r#type, value, "NOT IN", i_mode, params));
                         } else {
-                            result.push("TRUE".to_owned())
+                            result.push(Self::constant_condition(true, dialect))
                         }
                     }
                     "contains" => {
-                        let i_mode = Input::has_i_mode(map);
-                        result.push(Self::where_item(&column_name.to_i_mode(i_mode), "LIKE", &value.to_sql_string(r#type, false, dialect).to_like(true, true).to_i_mode(i_mode)));
+                        result.push(Self::where_item(&column_name, like, &params.push_like(value, true, true)?));
                     }
                     "startsWith" => {
-                        let i_mode = Input::has_i_mode(map);
-                        result.push(Self::where_item(&column_name.to_i_mode(i_mode), "LIKE", &value.to_sql_string(r#type, false, dialect).to_like(false, true).to_i_mode(i_mode)));
+                        result.push(Self::where_item(&column_name, like, &params.push_like(value, false, true)?));
                     }
                     "endsWith" => {
-                        let i_mode = Input::has_i_mode(map);
-                        result.push(Self::where_item(&column_name.to_i_mode(i_mode), "LIKE", &value.to_sql_string(r#type, false, dialect).to_like(true, false).to_i_mode(i_mode)));
+                        result.push(Self::where_item(&column_name, like, &params.push_like(value, true, false)?));
                     }
                     "matches" => {
-                        let i_mode = Input::has_i_mode(map);
-                        result.push(Self::where_item(&column_name.to_i_mode(i_mode), "REGEXP", &value.to_sql_string(r#type, false, dialect).to_i_mode(i_mode)));
+                        result.push(Self::where_entry_regex(&column_name, value, i_mode, dialect, params)?);
                     }
                     "mode" => { }
                     "has" => {
-                        let element_type = r#type.as_array().unwrap();
-                        result.push(Self::where_item(&column_name, "@>", &value.to_sql_string_array_arg(element_type.unwrap_optional(), element_type.is_optional(), dialect).wrap_in_array()));
+                        result.push(Self::where_item(&column_name, "@>", &params.push(&Value::Array(vec![value.clone()]), r#type)));
                     }
                     "hasEvery" => {
-                        result.push(Self::where_item(&column_name, "@>", &value.to_sql_string_array_arg(r#type, false, dialect)));
+                        result.push(Self::where_item(&column_name, "@>", &params.push(value, r#type)));
                     }
                     "hasSome" => {
-                        result.push(Self::where_item(&column_name, "&&", &value.to_sql_string_array_arg(r#type, false, dialect)));
+                        result.push(Self::where_item(&column_name, "&&", &params.push(value, r#type)));
                     }
                     "isEmpty" => {
                         result.push(Self::where_item(&format!("ARRAY_LENGTH({})", &column_name), "=", "0"));
                     }
                     "length" => {
-                        result.push(Self::where_item(&format!("ARRAY_LENGTH({})", &column_name), "=", &value.to_sql_string(&Type::Int64, false, dialect)));
+                        result.push(Self::where_item(&format!("ARRAY_LENGTH({})", &column_name), "=", &params.push(value, &Type::Int64)));
                     }
                     "_count" => {
-                        result.push(Self::where_entry_item(&format!("COUNT({})", &column_name), &Type::Int64, false, value, dialect));
+                        result.push(Self::where_entry_item(&format!("COUNT({})", &column_name), &Type::Int64, false, value, dialect, params)?);
                     }
                     "_avg" | "_sum" => {
-                        result.push(Self::where_entry_item(&format!("{}({})", key[1..].to_uppercase(), &column_name), &Type::Float, true, value, dialect));
+                        result.push(Self::where_entry_item(&format!("{}({})", key[1..].to_uppercase(), &column_name), &Type::Float, true, value, dialect, params)?);
                     }
                     "_min" | "_max" => {
-                        result.push(Self::where_entry_item(&format!("{}({})", key[1..].to_uppercase(), &column_name), r#type, optional, value, dialect));
+                        result.push(Self::where_entry_item(&format!("{}({})", key[1..].to_uppercase(), &column_name), r#type, optional, value, dialect, params)?);
                     }
-                    _ => panic!("Unhandled key."),
+                    _ => return Err(Error::new(format!("unknown filter `{}'", key))),
                 }
             }
-            And(result).to_wrapped_string(dialect)
+            Ok(And(result).to_wrapped_string(dialect))
+        } else {
+            Ok(Query::where_item(column_name, "=", &params.push(value, r#type)))
+        }
+    }
+
+    // T-SQL has no boolean literals in conditions
+    fn constant_condition(value: bool, dialect: SQLDialect) -> String {
+        match (dialect, value) {
+            (SQLDialect::MSSQL, true) => "1=1".to_owned(),
+            (SQLDialect::MSSQL, false) => "1=0".to_owned(),
+            (_, true) => "TRUE".to_owned(),
+            (_, false) => "FALSE".to_owned(),
+        }
+    }
+
+    // PostgreSQL compares lowered values, which `LOWER(col)` indices serve, SQLite compares with
+    // the NOCASE collation, MySQL and SQL Server collations are case insensitive already
+    fn insensitive_column(column_name: &str, i_mode: bool, dialect: SQLDialect) -> String {
+        match dialect {
+            SQLDialect::PostgreSQL if i_mode => format!("LOWER({column_name})"),
+            SQLDialect::SQLite if i_mode => format!("{column_name} COLLATE NOCASE"),
+            _ => column_name.to_owned(),
+        }
+    }
+
+    // SQLite evaluates REGEXP with the `regexp` function every pooled connection registers, flags are passed inline
+    fn where_entry_regex(column_name: &str, value: &Value, i_mode: bool, dialect: SQLDialect, params: &mut SQLParams) -> Result<String> {
+        Ok(match dialect {
+            SQLDialect::PostgreSQL => Self::where_item(column_name, if i_mode { "~*" } else { "~" }, &params.push(value, &Type::String)),
+            SQLDialect::MySQL => if i_mode {
+                format!("REGEXP_LIKE({column_name}, {}, 'i')", params.push(value, &Type::String))
+            } else {
+                Self::where_item(column_name, "REGEXP", &params.push(value, &Type::String))
+            },
+            SQLDialect::SQLite => {
+                let Some(pattern) = value.as_str() else {
+                    return Err(Error::new("matches should be a string"));
+                };
+                let pattern = format!("{}{}", if i_mode { "(?i)" } else { "" }, pattern);
+                Self::where_item(column_name, "REGEXP", &params.push(&Value::String(pattern), &Type::String))
+            }
+            SQLDialect::MSSQL => return Err(Error::new("regular expression filters are not supported on SQL Server")),
+        })
+    }
+
+    fn insensitive_value(placeholder: String, i_mode: bool, dialect: SQLDialect) -> String {
+        if i_mode && dialect == SQLDialect::PostgreSQL {
+            format!("LOWER({placeholder})")
         } else {
-            Query::where_item(column_name, "=", &value.to_sql_string(r#type, optional, dialect))
+            placeholder
+        }
+    }
+
+    // JSON filters look like `{ "path": ["a", "b"], "equals": 1 }`, without `path` the whole document is matched
+    fn where_entry_json(column_name: &str, value: &Value, dialect: SQLDialect, params: &mut SQLParams) -> Result<String> {
+        let Some(map) = value.as_dictionary() else {
+            return Self::where_entry_json(column_name, &Value::Dictionary(indexmap!{"equals".to_owned() => value.clone()}), dialect, params);
+        };
+        let segments = map.get("path").map(json_path_segments).unwrap_or_default();
+        let path = if segments.is_empty() && dialect != SQLDialect::PostgreSQL {
+            None
+        } else {
+            Some(params.push_json_path(&segments))
+        };
+        let document = match (dialect, &path) {
+            (SQLDialect::PostgreSQL, Some(path)) => format!("({column_name}::jsonb #> {path})"),
+            (SQLDialect::PostgreSQL, None) => format!("{column_name}::jsonb"),
+            (SQLDialect::MySQL, Some(path)) => format!("JSON_EXTRACT({column_name}, {path})"),
+            (SQLDialect::MySQL, None) => column_name.to_owned(),
+            (SQLDialect::SQLite, Some(path)) => format!("json_extract({column_name}, {path})"),
+            (SQLDialect::SQLite, None) => format!("json_extract({column_name}, '$')"),
+            (SQLDialect::MSSQL, Some(path)) => format!("JSON_QUERY({column_name}, {path})"),
+            (SQLDialect::MSSQL, None) => column_name.to_owned(),
+        };
+        let text = match (dialect, &path) {
+            (SQLDialect::PostgreSQL, Some(path)) => format!("({column_name}::jsonb #>> {path})"),
+            (SQLDialect::PostgreSQL, None) => format!("({column_name}::jsonb #>> '{{}}')"),
+            (SQLDialect::MySQL, _) => format!("JSON_UNQUOTE({document})"),
+            (SQLDialect::SQLite, _) => document.clone(),
+            (SQLDialect::MSSQL, Some(path)) => format!("JSON_VALUE({column_name}, {path})"),
+            (SQLDialect::MSSQL, None) => format!("JSON_VALUE({column_name}, '$')"),
+        };
+        // SQL Server keeps scalars and documents apart, scalars are compared as text
+        let compares_text = |value: &Value| dialect == SQLDialect::MSSQL && !value.is_dictionary() && !value.is_array();
+        let mut result: Vec<String> = vec![];
+        for (key, value) in map {
+            match key.as_str() {
+                "path" | "mode" => { }
+                "equals" => if value.is_null() {
+                    result.push(Self::where_item(&document, "IS", "NULL"));
+                } else {
+                    let rhs = Self::json_operand(params.push_json(value), dialect);
+                    result.push(Self::where_item(if compares_text(value) { &text } else { &document }, "=", &rhs));
+                }
+                "not" => if value.is_null() {
+                    result.push(Self::where_item(&document, "IS NOT", "NULL"));
+                } else {
+                    let rhs = Self::json_operand(params.push_json(value), dialect);
+                    result.push(Self::where_item(if compares_text(value) { &text } else { &document }, "<>", &rhs));
+                }
+                "stringContains" => result.push(Self::where_item(&text, "LIKE", &params.push_like(value, true, true)?)),
+                "stringStartsWith" => result.push(Self::where_item(&text, "LIKE", &params.push_like(value, false, true)?)),
+                "stringEndsWith" => result.push(Self::where_item(&text, "LIKE", &params.push_like(value, true, false)?)),
+                "arrayContains" => {
+                    let candidates = if let Some(array) = value.as_array() { array.clone() } else { vec![value.clone()] };
+                    match dialect {
+                        SQLDialect::PostgreSQL => result.push(Self::where_item(&document, "@>", &params.push_json(&Value::Array(candidates)))),
+                        SQLDialect::MySQL => result.push(format!("JSON_CONTAINS({document}, {})", params.push_json(&Value::Array(candidates)))),
+                        SQLDialect::SQLite => for candidate in candidates {
+                            let each = if let Some(path) = &path { format!("json_each({column_name}, {path})") } else { format!("json_each({column_name})") };
+                            let rhs = Self::json_operand(params.push_json(&candidate), dialect);
+                            result.push(format!("EXISTS (SELECT 1 FROM {each} AS e WHERE e.value = {rhs})"));
+                        }
+                        SQLDialect::MSSQL => for candidate in candidates {
+                            let each = if let Some(path) = &path { format!("OPENJSON({column_name}, {path})") } else { format!("OPENJSON({column_name})") };
+                            let rhs = params.push_json(&candidate);
+                            result.push(format!("EXISTS (SELECT 1 FROM {each} AS e WHERE e.[value] = {rhs})"));
+                        }
+                    }
+                }
+                _ => return Err(Error::new(format!("unknown JSON filter `{}'", key))),
+            }
+        }
+        Ok(And(result).to_wrapped_string(dialect))
+    }
+
+    // SQLite's `json_extract` yields SQL values, the bound document is extracted the same way to compare alike
+    fn json_operand(placeholder: String, dialect: SQLDialect) -> String {
+        if dialect == SQLDialect::SQLite {
+            format!("json_extract({placeholder}, '$')")
+        } else {
+            placeholder
         }
     }
 
@@ -154,35 +300,37 @@ impl Query {
         optional: bool,
         value: &Value,
         dialect: SQLDialect,
-    ) -> String {
-        Self::where_entry_item(column_name, field_type, optional, value, dialect)
+        params: &mut SQLParams,
+    ) -> Result<String> {
+        Self::where_entry_item(column_name, field_type, optional, value, dialect, params)
     }
 
-    pub(crate) fn where_from_value(model: &Model, identifier: &Value, dialect: SQLDialect) -> String {
+    pub(crate) fn where_from_value(model: &Model, identifier: &Value, dialect: SQLDialect, params: &mut SQLParams) -> String {
         let mut retval: Vec<String> = vec![];
         for (key, value) in identifier.as_dictionary().unwrap() {
             let field = model.field(key).unwrap();
             let column_name = field.column_name();
-            let escape = dialect.escape();
-            retval.push(format!("{}{}{} = {}", escape, column_name, escape, ToSQLString::to_string(&value, dialect)));
+            retval.push(format!("{} = {}", column_name.escape(dialect), params.push(value, field.r#type())));
         }
         And(retval).to_string(dialect)
     }
 
-    pub(crate) fn r#where(namespace: &Namespace, model: &Model, r#where: &Value, dialect: SQLDialect, table_alias: Option<&str>) -> String {
-        let r#where = r#where.as_dictionary().unwrap();
+    pub(crate) fn r#where(namespace: &Namespace, model: &Model, r#where: &Value, dialect: SQLDialect, table_alias: Option<&str>, params: &mut SQLParams) -> Result<String> {
+        let Some(r#where) = r#where.as_dictionary() else {
+            return Err(Error::new("where should be a dictionary"));
+        };
         let mut retval: Vec<String> = vec![];
         for (key, value) in r#where.iter() {
             if key == "AND" {
-                let inner = WhereClause::And(value.as_array().unwrap().iter().map(|w| Self::r#where(namespace, model, w, dialect, table_alias)).collect()).to_string(dialect);
+                let inner = WhereClause::And(value.as_array().unwrap().iter().map(|w| Self::r#where(namespace, model, w, dialect, table_alias, params)).collect::<Result<Vec<String>>>()?).to_string(dialect);
                 let val = "(".to_owned() + &inner + ")";
                 retval.push(val);
             } else if key == "OR" {
-                let inner = WhereClause::Or(value.as_array().unwrap().iter().map(|w| Self::r#where(namespace, model, w, dialect, table_alias)).collect()).to_string(dialect);
+                let inner = WhereClause::Or(value.as_array().unwrap().iter().map(|w| Self::r#where(namespace, model, w, dialect, table_alias, params)).collect::<Result<Vec<String>>>()?).to_string(dialect);
                 let val = "(".to_owned() + &inner + ")";
                 retval.push(val);
             } else if key == "NOT" {
-                let inner = WhereClause::Not(Self::r#where(namespace, model, value, dialect, table_alias)).to_string(dialect);
+                let inner = WhereClause::Not(Self::r#where(namespace, model, value, dialect, table_alias, params)?).to_string(dialect);
                 let val = "(".to_owned() + &inner + ")";
                 retval.push(val);
             } else {
@@ -195,12 +343,26 @@ impl Query {
                     } else {
                         Cow::Borrowed(column_name)
                     };
-                    let where_entry = Query::where_entry(&entry_column_name, field.r#type(), optional, value, dialect);
-                    retval.push(where_entry);
+                    if let Some(search) = value.as_dictionary().and_then(|d| d.get("search")) {
+                        retval.push(Self::full_text_match(model, column_name, table_alias, search, dialect, params)?);
+                        // other operators on the same field still apply
+                        let mut rest = value.as_dictionary().unwrap().clone();
+                        rest.shift_remove("search");
+                        if rest.keys().any(|k| k.as_str() != "mode") {
+                            retval.push(Query::where_entry(&entry_column_name, field.r#type(), optional, &Value::Dictionary(rest), dialect, params)?);
+                        }
+                    } else {
+                        let where_entry = Query::where_entry(&entry_column_name, field.r#type(), optional, value, dialect, params)?;
+                        retval.push(where_entry);
+                    }
                 } else if let Some(relation) = model.relation(key) {
                     let has_join_table = relation.has_join_table();
                     let id_columns: Vec<&str> = model.primary_index().unwrap().keys().iter().map(|k| model.field(k).unwrap().column_name()).collect();
-                    let id_columns_string = id_columns.iter().map(|k| k.escape(dialect)).collect::<Vec<String>>().join(",").to_wrapped();
+                    // the outer rows may be joined with a join table sharing column names
+                    let id_columns_string = id_columns.iter().map(|k| match table_alias {
+                        Some(alias) => format!("{}.{}", alias, k.escape(dialect)),
+                        None => k.escape(dialect),
+                    }).collect::<Vec<String>>().join(",").to_wrapped();
                     let id_columns_prefixed_string = id_columns.iter().map(|s| format!("t.{}", s)).collect::<Vec<String>>();
                     let id_columns_prefixed = id_columns_prefixed_string.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
                     let _join_columns = if has_join_table {
@@ -215,12 +377,11 @@ impl Query {
                         through_columns_string.iter().map(|k| k.as_str()).collect::<Vec<&str>>()
                     } else { vec![] };
                     for (key, value) in value.as_dictionary().unwrap() {
-                        let escape = dialect.escape();
                         let from = if !has_join_table {
-                            format!("{escape}{}{escape} AS t", &model.table_name)
+                            format!("{} AS t", model.table_name.escape(dialect))
                         } else {
                             let through_table_name = namespace.model_at_path(&relation.through_path().unwrap()).unwrap().table_name();
-                            format!("{escape}{}{escape} AS t", through_table_name)
+                            format!("{} AS t", through_table_name.escape(dialect))
                         };
                         let opposite_model = namespace.model_at_path(&relation.model_path()).unwrap();
                         let relation_table_name = &opposite_model.table_name;
@@ -252,7 +413,7 @@ impl Query {
                                 format!("t.{} IS NOT NULL", f.escape(dialect))
                             }).collect::<Vec<String>>().join(" AND ")
                         };
-                        let mut inner_where = Query::r#where(namespace, opposite_model, value, dialect, Some("j"));
+                        let mut inner_where = Query::r#where(namespace, opposite_model, value, dialect, Some("j"), params)?;
                         if key.as_str() == "every" {
                             inner_where = Not(inner_where.to_wrapped()).to_string(dialect).to_wrapped();
                         }
@@ -271,40 +432,280 @@ impl Query {
                             "none" | "isNot" | "every" => {
                                 retval.push(format!("{} NOT IN {}", id_columns_string, inner_stmt))
                             }
-                            _ => panic!("Unhandled key.")
+                            _ => return Err(Error::new(format!("unknown relation filter `{}'", key))),
                         }
                     }
 
                 }
             }
         }
-        And(retval).to_string(dialect)
+        Ok(And(retval).to_string(dialect))
+    }
+
+    // `search` takes the query syntax of each engine, tsquery on PostgreSQL, boolean mode on MySQL and FTS5 on SQLite
+    fn full_text_match(model: &Model, column_name: &str, table_alias: Option<&str>, search: &Value, dialect: SQLDialect, params: &mut SQLParams) -> Result<String> {
+        let qualifier = table_alias.map(|a| a.to_owned()).unwrap_or_else(|| model.table_name.escape(dialect));
+        let column = format!("{qualifier}.{}", column_name.escape(dialect));
+        Ok(match dialect {
+            SQLDialect::PostgreSQL => format!("{} @@ to_tsquery('{FULL_TEXT_CONFIG}', {})", psql_document(&column), params.push(search, &Type::String)),
+            SQLDialect::MySQL => format!("MATCH ({column}) AGAINST ({} IN BOOLEAN MODE)", params.push(search, &Type::String)),
+            SQLDialect::SQLite => {
+                let fts = full_text_table_name(&model.table_name).escape(dialect);
+                format!("{qualifier}.rowid IN (SELECT rowid FROM {fts} WHERE {} MATCH {})", column_name.escape(dialect), params.push(search, &Type::String))
+            }
+            // full text catalogs of SQL Server are not managed here
+            SQLDialect::MSSQL => return Err(Error::new("full text search is not supported on SQL Server")),
+        })
     }
 
+    // `{ "_relevance": { "fields": ["title"], "search": "cat", "sort": "desc" } }`
+    fn relevance(model: &Model, value: &Value, dialect: SQLDialect, qualifier: &str, params: &mut SQLParams) -> Result<Option<String>> {
+        let Some(search) = value.get("search").filter(|s| s.is_string()) else {
+            return Ok(None);
+        };
+        let columns: Vec<&str> = value.get("fields").and_then(|f| f.as_array()).map(|fields| {
+            fields.iter().filter_map(|f| model.field(f.as_str()?)).map(|f| f.column_name()).collect()
+        }).unwrap_or_default();
+        if columns.is_empty() {
+            return Ok(None);
+        }
+        let qualified = |c: &&str| format!("{qualifier}.{}", c.escape(dialect));
+        Ok(Some(match dialect {
+            SQLDialect::PostgreSQL => {
+                let document = columns.iter().map(|c| format!("COALESCE({}, '')", qualified(c))).join(" || ' ' || ");
+                format!("ts_rank({}, to_tsquery('{FULL_TEXT_CONFIG}', {}))", psql_document(&document), params.push(search, &Type::String))
+            }
+            SQLDialect::MySQL => columns.iter().map(|c| {
+                format!("MATCH ({}) AGAINST ({} IN BOOLEAN MODE)", qualified(c), params.push(search, &Type::String))
+            }).join(" + ").to_wrapped(),
+            SQLDialect::SQLite => {
+                let fts = full_text_table_name(&model.table_name).escape(dialect);
+                // the FTS5 column filter and the search are bound apart and joined into `{a b} : (search)`
+                let filter = params.push(&Value::String(format!("{{{}}}", columns.join(" "))), &Type::String);
+                let search = params.push(search, &Type::String);
+                format!("(SELECT -bm25({fts}) FROM {fts} WHERE {fts}.rowid = {qualifier}.rowid AND {fts} MATCH ({filter} || ' : (' || {search} || ')'))")
+            }
+            SQLDialect::MSSQL => return Err(Error::new("ordering by relevance is not supported on SQL Server")),
+        }))
+    }
+
+    // `qualifier` names the ordered rows inside correlated subqueries for relation keys
     pub(crate) fn order_by(
+        namespace: &Namespace,
         model: &Model,
         order_by: &Value,
-        _dialect: SQLDialect,
+        dialect: SQLDialect,
+        qualifier: &str,
         negative_take: bool,
-    ) -> String {
+        params: &mut SQLParams,
+    ) -> Result<String> {
         let asc = if negative_take { "DESC" } else { "ASC" };
         let desc = if negative_take { "ASC" } else { "DESC" };
-        let order_by = order_by.as_array().unwrap();
+        let direction = |value: &Value| match value.as_str() {
+            Some("asc") => Ok(Some(asc)),
+            Some("desc") => Ok(Some(desc)),
+            Some(other) => Err(Error::new(format!("unknown sort order `{}'", other))),
+            None => Ok(None),
+        };
+        let Some(order_by) = order_by.as_array() else {
+            return Err(Error::new("orderBy should be an array"));
+        };
         let mut retval: Vec<String> = vec![];
         for item in order_by.iter() {
-            let (key, value) = Input::key_value(item.as_dictionary().unwrap());
+            let Some(item) = item.as_dictionary().filter(|d| !d.is_empty()) else {
+                return Err(Error::new("orderBy item should be a dictionary"));
+            };
+            let (key, value) = Input::key_value(item);
             if let Some(field) = model.field(key) {
-                let column_name = field.column_name();
-                if let Some(str) = value.as_str() {
-                    match str {
-                        "asc" => retval.push(format!("{} {}", column_name, asc)),
-                        "desc" => retval.push(format!("{} {}", column_name, desc)),
-                        _ => panic!("Unhandled."),
-                    }
+                if let Some(direction) = direction(value)? {
+                    retval.push(format!("{}.{} {}", qualifier, field.column_name().escape(dialect), direction));
+                }
+            } else if key == "_relevance" {
+                let Some(direction) = value.get("sort").map(|s| direction(s)).transpose()?.flatten() else { continue };
+                if let Some(relevance) = Self::relevance(model, value, dialect, qualifier, params)? {
+                    retval.push(format!("{} {}", relevance, direction));
+                }
+            } else if let Some(relation) = model.relation(key) {
+                let Some(relation_order) = value.as_dictionary() else {
+                    return Err(Error::new(format!("orderBy of relation `{}' should be a dictionary", key)));
+                };
+                let (relation_key, value) = Input::key_value(relation_order);
+                let Some(direction) = direction(value)? else { continue };
+                if relation_key == "_count" && relation.is_vec {
+                    retval.push(format!("{} {}", Self::relation_count(namespace, model, relation, dialect, qualifier), direction));
+                } else if !relation.is_vec {
+                    let (opposite_model, _) = namespace.opposite_relation(relation);
+                    let Some(opposite_field) = opposite_model.field(relation_key) else { continue };
+                    let conditions: Vec<String> = relation.iter().map(|(f, r)| {
+                        format!("o.{} = {}.{}", opposite_model.field(r).unwrap().column_name().escape(dialect), qualifier, model.field(f).unwrap().column_name().escape(dialect))
+                    }).collect();
+                    retval.push(format!("(SELECT o.{} FROM {} AS o WHERE {}) {}", opposite_field.column_name().escape(dialect), opposite_model.table_name.escape(dialect), And(conditions).to_string(dialect), direction));
                 }
             }
         }
-        retval.join(",")
+        Ok(retval.join(","))
+    }
+
+    // column names narrowed by `select`, identity, version, relation, ordering and distinct keys are always kept
+    fn projection<'a>(namespace: &'a Namespace, model: &'a Model, value: &'a Value) -> Result<Option<Vec<&'a str>>> {
+        let Some(select) = value.get("select").and_then(|s| s.as_dictionary()) else {
+            return Ok(None);
+        };
+        let explicit: Vec<(&str, bool)> = select.iter().filter(|(k, _)| {
+            model.field(k).is_some() || model.property(k).is_some_and(|p| p.cached)
+        }).map(|(k, v)| (k.as_str(), v.as_bool().unwrap_or(false))).collect();
+        if explicit.is_empty() {
+            return Ok(None);
+        }
+        let mut keys: Vec<&str> = if explicit.iter().any(|(_, selected)| *selected) {
+            explicit.iter().filter(|(_, selected)| *selected).map(|(k, _)| *k).collect()
+        } else {
+            model.cache.save_keys.iter().map(|k| k.as_str()).filter(|k| !explicit.iter().any(|(e, _)| e == k)).collect()
+        };
+        keys.extend(model.primary_index().unwrap().keys().iter().map(|k| k.as_str()));
+        if let Some(field) = model.version_field() {
+            keys.push(field.name());
+        }
+        let mut relations: Vec<&str> = vec![];
+        if let Some(include) = value.get("include").and_then(|i| i.as_dictionary()) {
+            relations.extend(include.keys().map(|k| k.as_str()));
+        }
+        if let Some(order_by) = value.get("orderBy").and_then(|o| o.as_array()) {
+            for item in order_by {
+                let Some(key) = item.as_dictionary().and_then(|d| d.keys().next()) else {
+                    return Err(Error::new("orderBy item should be a dictionary"));
+                };
+                let key = key.as_str();
+                if model.field(key).is_some() {
+                    keys.push(key);
+                } else {
+                    relations.push(key);
+                }
+            }
+        }
+        for relation in relations.iter().filter_map(|name| model.relation(name)) {
+            if relation.has_join_table() {
+                let (_, through_relation) = namespace.through_relation(relation);
+                keys.extend(through_relation.references());
+            } else {
+                keys.extend(relation.fields());
+            }
+        }
+        for key in ["distinct", "cursor"] {
+            if let Some(array) = value.get(key).and_then(|d| d.as_array()) {
+                keys.extend(array.iter().filter_map(|v| v.as_str()));
+            } else if let Some(dictionary) = value.get(key).and_then(|d| d.as_dictionary()) {
+                keys.extend(dictionary.keys().map(|k| k.as_str()));
+            }
+        }
+        // only fields and cached properties have a column, other keys are dropped
+        Ok(Some(keys.into_iter().unique().filter_map(|k| {
+            if let Some(field) = model.field(k) {
+                Some(field.column_name())
+            } else {
+                model.property(k).filter(|p| p.cached).map(|p| p.column_name())
+            }
+        }).collect()))
+    }
+
+    // `_count` is requested in include or select as `{ "_count": { "select": { "comments": true } } }`,
+    // each count comes back in a `_count.<relation>` column
+    fn relation_counts(namespace: &Namespace, model: &Model, value: &Value, dialect: SQLDialect, qualifier: &str) -> Vec<String> {
+        let spec = value.get("include").and_then(|i| i.get("_count")).or_else(|| value.get("select").and_then(|s| s.get("_count")));
+        let Some(spec) = spec.map(|s| s.get("select").unwrap_or(s)).and_then(|s| s.as_dictionary()) else {
+            return vec![];
+        };
+        spec.iter().filter(|(_, v)| v.as_bool().unwrap_or(false)).filter_map(|(name, _)| {
+            let relation = model.relation(name).filter(|r| r.is_vec)?;
+            Some(format!("{} AS {}", Self::relation_count(namespace, model, relation, dialect, qualifier), format!("{RELATION_COUNT_PREFIX}.{name}").escape(dialect)))
+        }).collect()
+    }
+
+    // correlated COUNT(*) of the rows related to the row named by `qualifier`
+    pub(crate) fn relation_count(namespace: &Namespace, model: &Model, relation: &Relation, dialect: SQLDialect, qualifier: &str) -> String {
+        let (table, conditions) = if relation.has_join_table() {
+            let (through_model, through_relation) = namespace.through_relation(relation);
+            (&through_model.table_name, through_relation.iter().map(|(f, r)| {
+                format!("o.{} = {}.{}", through_model.field(f).unwrap().column_name().escape(dialect), qualifier, model.field(r).unwrap().column_name().escape(dialect))
+            }).collect::<Vec<String>>())
+        } else {
+            let (opposite_model, _) = namespace.opposite_relation(relation);
+            (&opposite_model.table_name, relation.iter().map(|(f, r)| {
+                format!("o.{} = {}.{}", opposite_model.field(r).unwrap().column_name().escape(dialect), qualifier, model.field(f).unwrap().column_name().escape(dialect))
+            }).collect::<Vec<String>>())
+        };
+        format!("(SELECT COUNT(*) FROM {} AS o WHERE {})", table.escape(dialect), And(conditions).to_string(dialect))
+    }
+
+    fn order_by_with_primary_keys(model: &Model, order_by: &Value) -> Value {
+        let mut items = order_by.as_array().unwrap().clone();
+        for key in model.primary_index().unwrap().keys() {
+            if !items.iter().any(|item| item.as_dictionary().unwrap().contains_key(key)) {
+                items.push(Value::Dictionary(indexmap!{key.clone() => Value::String("asc".to_string())}));
+            }
+        }
+        Value::Array(items)
+    }
+
+    // keyset condition selecting the cursor row and every row sorted after it,
+    // the cursor row's values are read with scalar subqueries
+    fn cursor_where(
+        namespace: &Namespace,
+        model: &Model,
+        cursor: &Value,
+        order_by: &Value,
+        dialect: SQLDialect,
+        table_alias: Option<&str>,
+        negative_take: bool,
+        params: &mut SQLParams,
+    ) -> Result<String> {
+        let table_name = model.table_name.as_str().escape(dialect);
+        let mut columns: Vec<(String, String, bool)> = vec![];
+        for item in order_by.as_array().unwrap() {
+            let (key, direction) = Input::key_value(item.as_dictionary().unwrap());
+            let Some(field) = model.field(key) else { continue };
+            let column_name = field.column_name().escape(dialect);
+            let lhs = if let Some(alias) = table_alias {
+                format!("{}.{}", alias, column_name)
+            } else {
+                column_name.clone()
+            };
+            let cursor_where = Query::r#where(namespace, model, cursor, dialect, None, params)?;
+            let rhs = format!("(SELECT {} FROM {} WHERE {})", column_name, table_name, cursor_where);
+            let ascending = (direction.as_str() == Some("asc")) != negative_take;
+            columns.push((lhs, rhs, ascending));
+        }
+        Ok(Self::keyset_condition(&columns, dialect))
+    }
+
+    // a row value comparison when every column sorts the same way, otherwise one alternative
+    // per column with the columns before it equal; SQL Server has no row values
+    fn keyset_condition(columns: &Vec<(String, String, bool)>, dialect: SQLDialect) -> String {
+        let uniform = columns.iter().all(|(_, _, ascending)| *ascending == columns[0].2);
+        if uniform && dialect != SQLDialect::MSSQL {
+            let op = if columns[0].2 { ">=" } else { "<=" };
+            let lhs = columns.iter().map(|(lhs, _, _)| lhs.as_str()).join(",");
+            let rhs = columns.iter().map(|(_, rhs, _)| rhs.as_str()).join(",");
+            return if columns.len() == 1 {
+                Query::where_item(lhs, op, &rhs)
+            } else {
+                Query::where_item(format!("({lhs})"), op, &format!("({rhs})"))
+            };
+        }
+        let mut alternatives: Vec<String> = vec![];
+        for (index, (lhs, rhs, ascending)) in columns.iter().enumerate() {
+            let last = index == columns.len() - 1;
+            let op = match (*ascending, last) {
+                (true, false) => ">",
+                (true, true) => ">=",
+                (false, false) => "<",
+                (false, true) => "<=",
+            };
+            let mut items: Vec<String> = columns[0..index].iter().map(|(lhs, rhs, _)| Query::where_item(lhs, "=", rhs)).collect();
+            items.push(Query::where_item(lhs, op, rhs));
+            alternatives.push(And(items).to_wrapped_string(dialect));
+        }
+        Or(alternatives).to_wrapped_string(dialect)
     }
 
     pub(crate) fn build_for_count(
@@ -316,8 +717,9 @@ impl Query {
         additional_left_join: Option<String>,
         join_table_results: Option<Vec<String>>,
         force_negative_take: bool,
+        params: &mut SQLParams,
     ) -> Result<String> {
-        Ok(format!("SELECT COUNT(*) FROM ({}) AS _", Self::build(namespace, model, value, dialect, additional_where, additional_left_join, join_table_results, force_negative_take)?))
+        Ok(format!("SELECT COUNT(*) FROM ({}) AS _", Self::build(namespace, model, value, dialect, additional_where, additional_left_join, join_table_results, force_negative_take, params)?))
     }
 
     pub(crate) fn build_for_group_by(
@@ -325,15 +727,16 @@ impl Query {
         model: &Model,
         value: &Value,
         dialect: SQLDialect,
+        params: &mut SQLParams,
     ) -> Result<String> {
-        let aggregate = Self::build_for_aggregate(namespace, model, value, dialect)?;
+        let aggregate = Self::build_for_aggregate(namespace, model, value, dialect, params)?;
         let map = value.as_dictionary().unwrap();
         let by = map.get("by").unwrap().as_array().unwrap().iter().map(|v| {
             let field_name = v.as_str().unwrap();
             model.field(field_name).unwrap().column_name()
         }).collect::<Vec<&str>>().join(",");
         let having = if let Some(having) = map.get("having") {
-            let inner = Query::r#where(namespace, model, having, dialect, None);
+            let inner = Query::r#where(namespace, model, having, dialect, None, params)?;
             " HAVING (".to_owned() + &inner + ")"
         } else {
             "".to_owned()
@@ -346,9 +749,9 @@ impl Query {
         model: &Model,
         value: &Value,
         dialect: SQLDialect,
+        params: &mut SQLParams,
     ) -> Result<String> {
         let map = value.as_dictionary().unwrap();
-        let escape = dialect.escape();
         let mut results: Vec<String> = vec![];
         for (key, value) in map {
             match key.as_str() {
@@ -357,7 +760,7 @@ impl Query {
                         let k = k.as_str();
                         if v.as_bool().unwrap() {
                             match k {
-                                "_all" => results.push(format!("COUNT(*) as {escape}_count._all{escape}")),
+                                "_all" => results.push(format!("COUNT(*) as {}", "_count._all".escape(dialect))),
                                 _ => {
                                     let column_name = model.field(k).unwrap().column_name();
                                     let func = SQL_AGGREGATE_MAP.get(key.as_str()).unwrap();
@@ -367,7 +770,7 @@ impl Query {
                                         "_avg" | "_sum" => left = format!("CAST({} AS DOUBLE)", left),
                                         _ => ()
                                     }
-                                    results.push(format!("{} as {escape}{}.{}{escape}", left, key, k));
+                                    results.push(format!("{} as {}", left, format!("{}.{}", key, k).escape(dialect)));
                                 }
                             }
                         }
@@ -382,7 +785,7 @@ impl Query {
                 results.push(model.field(field_name).unwrap().column_name().to_string());
             }
         }
-        Ok(format!("SELECT {} FROM ({}) AS _", results.join(","), Self::build(namespace, model, value, dialect, None, None, None, false)?))
+        Ok(format!("SELECT {} FROM ({}) AS _", results.join(","), Self::build(namespace, model, value, dialect, None, None, None, false, params)?))
     }
 
     pub(crate) fn build(
@@ -394,6 +797,7 @@ impl Query {
         additional_left_join: Option<String>,
         join_table_results: Option<Vec<String>>,
         force_negative_take: bool,
+        params: &mut SQLParams,
     ) -> Result<String> {
         let r#where = value.get("where");
         let order_by = value.get("orderBy");
@@ -414,42 +818,49 @@ impl Query {
         } else {
             model.table_name.to_string().escape(dialect)
         };
+        let qualifier = if additional_left_join.is_some() { "t".to_owned() } else { model.table_name.escape(dialect) };
         let mut columns: Vec<String> = vec![];
+        let projection = Self::projection(namespace, model, value)?;
         if additional_left_join.is_some() {
-            columns = model.cache.save_keys.iter().map(|k| format!("t.{} AS {}", k.escape(dialect), k.escape(dialect))).collect::<Vec<String>>();
+            columns = match &projection {
+                Some(projection) => projection.iter().map(|k| format!("t.{} AS {}", k.escape(dialect), k.escape(dialect))).collect::<Vec<String>>(),
+                None => model.cache.save_keys.iter().map(|k| format!("t.{} AS {}", k.escape(dialect), k.escape(dialect))).collect::<Vec<String>>(),
+            };
+        } else if let Some(projection) = &projection {
+            columns = projection.iter().map(|k| format!("{}.{}", qualifier, k.escape(dialect))).collect::<Vec<String>>();
         }
         if let Some(join_table_results) = join_table_results {
             for result_key in join_table_results {
                 columns.push(result_key);
             }
         }
+        let mut computed_columns = Self::relation_counts(namespace, model, value, dialect, &qualifier);
+        if Self::loads_relations_with_join(value) {
+            computed_columns.extend(Self::relation_json_columns(namespace, model, value, dialect, &qualifier, 1, params)?);
+        }
+        if !computed_columns.is_empty() {
+            if columns.is_empty() {
+                columns.push(format!("{qualifier}.*"));
+            }
+            columns.extend(computed_columns);
+        }
         let column_refs = columns.iter().map(|c| c.as_str()).collect::<Vec<&str>>();
-        let from = if let Some(cursor) = cursor {
-            if order_by.is_none() {
+        // pages are only stable when the sort ends on a unique key
+        let cursor_order_by = if cursor.is_some() {
+            let Some(order_by) = order_by else {
                 return Err(Error::new("cursor is invalid without order by argument"));
-            }
-            let order_by = order_by.unwrap().as_array().unwrap().get(0).unwrap().as_dictionary().unwrap();
-            let key = order_by.keys().next().unwrap();
-            let column_key = model.field(key).unwrap().column_name();
-            let columns = cursor.as_dictionary().unwrap().keys().map(|_k| {
-                if dialect == SQLDialect::PostgreSQL {
-                    format!("{} AS \"c.{}\"", column_key, column_key)
-                } else {
-                    format!("{} AS `c.{}`", column_key, column_key)
-                }
-            }).collect::<Vec<String>>();
-            let column_refs: Vec<&str> = columns.iter().map(|k| k.as_str()).collect();
-            let sub_where = Query::r#where(namespace, model, cursor, dialect, None);
-            let mut query = SQL::select(Some(&column_refs), &table_name);
-            query.r#where(sub_where);
-            format!("{}, ({}) AS c", &table_name, &query.to_string(dialect))
+            };
+            Some(Self::order_by_with_primary_keys(model, order_by))
         } else {
-            table_name.clone()
+            None
         };
-        let mut stmt = SQL::select(if columns.is_empty() { None } else { Some(&column_refs) }, from.as_ref());
+        let order_by = cursor_order_by.as_ref().or(order_by);
+        let lock = Self::lock(value, dialect)?;
+        let mut stmt = SQL::select(if columns.is_empty() { None } else { Some(&column_refs) }, table_name.as_ref());
+        let table_alias = if additional_left_join.is_some() { Some("t") } else { None };
         if let Some(r#where) = r#where {
             if !r#where.as_dictionary().unwrap().is_empty() {
-                stmt.r#where(Query::r#where(namespace, model, r#where, dialect, None));
+                stmt.r#where(Query::r#where(namespace, model, r#where, dialect, table_alias, params)?);
             }
         }
         if let Some(additional_where) = additional_where {
@@ -459,26 +870,23 @@ impl Query {
                 stmt.r#where(additional_where.to_string());
             }
         }
-        if cursor.is_some() {
-            let order_by = order_by.unwrap().as_array().unwrap().get(0).unwrap().as_dictionary().unwrap();
-            let key = order_by.keys().next().unwrap();
-            let order = if order_by.values().next().unwrap().as_str().unwrap() == if negative_take { "desc" } else { "asc" }
-                { ">=" } else { "<=" };
-            let cursor_where = Query::where_item(&key, order, &format!("`c.{}`", key));
+        if let Some(cursor) = cursor {
+            let cursor_where = Self::cursor_where(namespace, model, cursor, order_by.unwrap(), dialect, table_alias, negative_take, params)?;
             if stmt.r#where.is_some() {
                 stmt.r#where(And(vec![stmt.r#where.as_ref().unwrap().clone(), cursor_where]).to_string(dialect));
             } else {
                 stmt.r#where(cursor_where);
             }
         }
+        let additional_left_join_present = additional_left_join.is_some();
         if let Some(additional_left_join) = additional_left_join {
             stmt.left_join(additional_left_join);
         }
         if let Some(order_bys) = order_by {
-            stmt.order_by(Query::order_by(model, order_bys, dialect, negative_take));
+            stmt.order_by(Query::order_by(namespace, model, order_bys, dialect, &qualifier, negative_take, params)?);
         } else if negative_take {
             let val = Self::default_desc_order(model);
-            stmt.order_by(Query::order_by(model, &val, dialect, false));
+            stmt.order_by(Query::order_by(namespace, model, &val, dialect, &qualifier, false, params)?);
         }
         if page_size.is_some() && page_number.is_some() {
             let skip: u64 = ((page_number.unwrap().as_int64().unwrap() - 1) * page_size.unwrap().as_int64().unwrap()) as u64;
@@ -494,10 +902,119 @@ impl Query {
                 stmt.limit(limit, skip);
             }
         }
+        if let Some(lock) = lock {
+            // the joined side may be null and cannot be locked
+            stmt.lock(if additional_left_join_present { lock.of("t") } else { lock });
+        }
         let result = stmt.to_string(dialect);
         Ok(result)
     }
 
+    // `lock` is either "update" or "share", or `{ "mode": "update", "noWait": true }` and `{ "mode": "update", "skipLocked": true }`
+    fn lock(value: &Value, dialect: SQLDialect) -> Result<Option<SQLLock>> {
+        let Some(lock) = value.get("lock") else {
+            return Ok(None);
+        };
+        let (mode, options) = if let Some(mode) = lock.as_str() {
+            (mode, None)
+        } else if let Some(options) = lock.as_dictionary() {
+            (options.get("mode").and_then(|m| m.as_str()).unwrap_or("update"), Some(options))
+        } else {
+            return Err(Error::new("lock should be a string or a dictionary"));
+        };
+        let mode = match mode {
+            "update" => SQLLockMode::Update,
+            "share" => SQLLockMode::Share,
+            _ => return Err(Error::new(format!("unknown lock mode `{}'", mode))),
+        };
+        let flag = |key: &str| options.and_then(|o| o.get(key)).and_then(|v| v.as_bool()).unwrap_or(false);
+        let wait = match (flag("noWait"), flag("skipLocked")) {
+            (true, true) => return Err(Error::new("lock cannot be both noWait and skipLocked")),
+            (true, false) => SQLLockWait::NoWait,
+            (false, true) => SQLLockWait::SkipLocked,
+            (false, false) => SQLLockWait::Wait,
+        };
+        match dialect {
+            SQLDialect::SQLite => Err(Error::new("row level locking is not supported on SQLite, start the transaction with an isolation level instead")),
+            SQLDialect::MSSQL => Err(Error::new("row level locking is not supported on SQL Server")),
+            _ => Ok(Some(SQLLock::new(mode, wait))),
+        }
+    }
+
+    // paging and distinct are applied inside each partition with window functions, `partition`
+    // and `distinct` are escaped names of columns returned by the inner select
+    pub(crate) fn build_windowed(
+        namespace: &Namespace,
+        model: &Model,
+        value: &Value,
+        dialect: SQLDialect,
+        additional_where: Option<String>,
+        additional_left_join: Option<String>,
+        join_table_results: Option<Vec<String>>,
+        partition: &Vec<String>,
+        distinct: &Vec<String>,
+        params: &mut SQLParams,
+    ) -> Result<String> {
+        // rows picked by window functions cannot be locked
+        if value.get("lock").is_some() {
+            return Err(Error::new("lock cannot be combined with distinct or paged includes"));
+        }
+        let negative_take = Input::has_negative_take(value);
+        let mut inner = value.as_dictionary().unwrap().clone();
+        for key in ["take", "skip", "pageSize", "pageNumber", "distinct"] {
+            inner.remove(key);
+        }
+        let has_cursor = inner.contains_key("cursor");
+        if !has_cursor {
+            inner.remove("orderBy");
+        }
+        let base = Self::build(namespace, model, &Value::Dictionary(inner), dialect, additional_where, additional_left_join, join_table_results, negative_take && has_cursor, params)?;
+        let order_value = value.get("orderBy").cloned().unwrap_or_else(|| Self::default_asc_order(model));
+        let over = |columns: &Vec<String>, order: &str| if columns.is_empty() {
+            format!("ORDER BY {}", order)
+        } else {
+            format!("PARTITION BY {} ORDER BY {}", columns.join(","), order)
+        };
+        let source = if distinct.is_empty() {
+            format!("({base}) AS d")
+        } else {
+            let keys: Vec<String> = partition.iter().chain(distinct.iter()).cloned().collect();
+            let order = Self::order_by(namespace, model, &order_value, dialect, "b", negative_take, params)?;
+            if dialect == SQLDialect::PostgreSQL {
+                format!("(SELECT DISTINCT ON ({}) b.* FROM ({base}) AS b ORDER BY {},{}) AS d", keys.join(","), keys.join(","), order)
+            } else {
+                format!("(SELECT * FROM (SELECT b.*, ROW_NUMBER() OVER ({}) AS {DISTINCT_NUMBER_COLUMN} FROM ({base}) AS b) AS n WHERE n.{DISTINCT_NUMBER_COLUMN} = 1) AS d", over(&keys, &order))
+            }
+        };
+        let order = Self::order_by(namespace, model, &order_value, dialect, "d", negative_take, params)?;
+        let (skip, take) = Self::window_range(value);
+        let mut range = vec![format!("w.{ROW_NUMBER_COLUMN} > {skip}")];
+        if let Some(take) = take {
+            range.push(format!("w.{ROW_NUMBER_COLUMN} <= {}", skip + take));
+        }
+        let outer_order = partition.iter().cloned().chain([format!("w.{ROW_NUMBER_COLUMN}")]).join(",");
+        Ok(format!("SELECT * FROM (SELECT d.*, ROW_NUMBER() OVER ({}) AS {ROW_NUMBER_COLUMN} FROM {source}) AS w WHERE {} ORDER BY {outer_order}", over(partition, &order), And(range).to_string(dialect)))
+    }
+
+    fn window_range(value: &Value) -> (u64, Option<u64>) {
+        if let (Some(page_size), Some(page_number)) = (value.get("pageSize"), value.get("pageNumber")) {
+            let page_size = page_size.as_int64().unwrap();
+            (((page_number.as_int64().unwrap() - 1) * page_size) as u64, Some(page_size as u64))
+        } else {
+            let skip = value.get("skip").map(|s| s.as_int64().unwrap() as u64).unwrap_or(0);
+            let take = value.get("take").map(|t| t.as_int64().unwrap().unsigned_abs());
+            (skip, take)
+        }
+    }
+
+    fn default_asc_order(model: &Model) -> Value {
+        let mut vec: Vec<Value> = vec![];
+        for item in &model.primary_index().unwrap().items {
+            vec.push(Value::Dictionary(indexmap!{item.field.clone() => Value::String("asc".to_string())}));
+        }
+        Value::Array(vec)
+    }
+
     fn default_desc_order(model: &Model) -> Value {
         let mut vec: Vec<Value> = vec![];
         for item in &model.primary_index().unwrap().items {
@@ -519,10 +1036,50 @@ static SQL_AGGREGATE_MAP: Lazy<BTreeMap<&str, &str>> = Lazy::new(|| {
 
 pub(crate) fn escape_wisdom(s: impl AsRef<str>, dialect: SQLDialect) -> String {
     let s = s.as_ref();
-    let escape = dialect.escape();
-    if s.contains(escape) {
+    if s.contains(['"', '`', '[']) {
         s.to_owned()
     } else {
-        s.split(".").map(|s| format!("{escape}{s}{escape}")).join(".")
+        s.split(".").map(|s| s.escape(dialect)).join(".")
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use teo_teon::teon;
+    use crate::schema::dialect::SQLDialect;
+    use crate::schema::value::encode::ToSQLString;
+    use crate::stmts::SQL;
+    use super::Query;
+
+    const DIALECTS: [SQLDialect; 4] = [SQLDialect::PostgreSQL, SQLDialect::MySQL, SQLDialect::SQLite, SQLDialect::MSSQL];
+
+    #[test]
+    fn lock_renders_on_postgres_and_mysql_and_is_rejected_elsewhere() {
+        let value = teon!({"lock": {"mode": "update", "skipLocked": true}});
+        for dialect in DIALECTS {
+            let lock = Query::lock(&value, dialect);
+            match dialect {
+                SQLDialect::PostgreSQL => assert_eq!(SQL::select(None, "jobs").lock(lock.unwrap().unwrap()).to_string(dialect), r#"SELECT * from "jobs" FOR UPDATE SKIP LOCKED"#),
+                SQLDialect::MySQL => assert_eq!(SQL::select(None, "jobs").lock(lock.unwrap().unwrap()).to_string(dialect), "SELECT * from `jobs` FOR UPDATE SKIP LOCKED"),
+                SQLDialect::SQLite | SQLDialect::MSSQL => assert!(lock.is_err()),
+            }
+        }
+    }
+
+    #[test]
+    fn keyset_cursor_expands_mixed_directions() {
+        for dialect in DIALECTS {
+            let columns = vec![
+                ("a".to_owned(), "(SELECT a)".to_owned(), true),
+                ("b".to_owned(), "(SELECT b)".to_owned(), false),
+                ("id".to_owned(), "(SELECT id)".to_owned(), true),
+            ];
+            assert_eq!(Query::keyset_condition(&columns, dialect), "((a > (SELECT a)) OR (a = (SELECT a) AND b < (SELECT b)) OR (a = (SELECT a) AND b = (SELECT b) AND id >= (SELECT id)))");
+            let uniform: Vec<(String, String, bool)> = columns.into_iter().map(|(lhs, rhs, _)| (lhs, rhs, true)).collect();
+            let expected = match dialect {
+                SQLDialect::MSSQL => "((a > (SELECT a)) OR (a = (SELECT a) AND b > (SELECT b)) OR (a = (SELECT a) AND b = (SELECT b) AND id >= (SELECT id)))",
+                _ => "(a,b,id) >= ((SELECT a),(SELECT b),(SELECT id))",
+            };
+            assert_eq!(Query::keyset_condition(&uniform, dialect), expected);
+        }
+    }
+}
@@ -4,6 +4,9 @@ use itertools::Itertools;
 use maplit::{hashset};
 use quaint_forked::pooled::PooledConnection;
 use quaint_forked::prelude::{Query, Queryable, ResultRow, ResultSet};
+use teo_result::Result;
+use teo_runtime::database::r#type::DatabaseType;
+use crate::error::{SQLError, SQLErrorKind};
 use crate::migration::sql::psql_is_auto_increment;
 use crate::schema::column::SQLColumn;
 use crate::schema::dialect::SQLDialect;
@@ -148,70 +151,99 @@ impl ColumnDecoder {
         false
     }
 
-    pub(crate) fn decode_model_columns(model: &Model) -> HashSet<SQLColumn> {
-        let mut result = hashset!{};
+    pub(crate) fn decode_model_columns(model: &Model, dialect: SQLDialect) -> HashSet<SQLColumn> {
+        let mut columns: Vec<SQLColumn> = vec![];
         for field in model.fields() {
             if field.r#virtual { continue }
-            result.insert(field.into());
+            columns.push(field.into());
         }
         for property in model.properties() {
             if property.cached {
-                result.insert(property.into());
+                columns.push(property.into());
             }
         }
-        result
+        columns.into_iter().map(|mut column| {
+            column.r#type = SQLTypeDecoder::normalize(&column.r#type, dialect);
+            column
+        }).collect()
     }
 
-    pub(crate) fn decode_sqlite_columns(columns: ResultSet, indices: ResultSet, auto_increment: ResultSet) -> HashSet<SQLColumn> {
+    pub(crate) fn decode_sqlite_columns(table_name: &str, columns: ResultSet, indices: ResultSet, auto_increment: ResultSet) -> Result<HashSet<SQLColumn>> {
         let columns_iter: Vec<ResultRow> = columns.into_iter().collect();
         let indices_iter: Vec<ResultRow> = indices.into_iter().collect();
-        let primary_is_single = columns_iter.iter().filter(|r| {
-            r.get("pk").unwrap().as_i64().unwrap() > 0
-        }).count() == 1;
+        let dialect = SQLDialect::SQLite;
+        let mut primary_count = 0;
+        for column in &columns_iter {
+            if Self::row_i64(dialect, table_name, column, "pk")? > 0 {
+                primary_count += 1;
+            }
+        }
+        let primary_is_single = primary_count == 1;
         let mut result = hashset!{};
         for column in &columns_iter {
-            let name = column.get("name").unwrap().as_str().unwrap();
-            let r#type = column.get("type").unwrap().as_str().unwrap();
-            let not_null = column.get("notnull").unwrap().as_bool().unwrap();
+            let name = Self::row_string(dialect, table_name, column, "name")?;
+            let r#type = Self::row_string(dialect, table_name, column, "type")?;
+            let not_null = Self::row_bool(dialect, table_name, column, "notnull")?;
             let pk = if primary_is_single {
-                column.get("pk").unwrap().as_bool().unwrap_or(false)
+                column.get("pk").and_then(|v| v.as_bool()).unwrap_or(false)
             } else {
                 false
             };
             let unique_row = indices_iter.iter().find(|i| {
-                i.get("column_name").unwrap().as_str().unwrap() == name
+                i.get("column_name").and_then(|v| v.as_str()) == Some(name.as_str())
             });
-            let _unique_key = if unique_row.is_some() {
-                unique_row.unwrap().get("unique").unwrap().as_bool().unwrap()
-            } else {
-                false
-            };
+            let _unique_key = unique_row.and_then(|r| r.get("unique")).and_then(|v| v.as_bool()).unwrap_or(false);
             result.insert(SQLColumn {
-                name: name.to_string(),
-                r#type: SQLTypeDecoder::decode(&r#type, SQLDialect::SQLite),
+                name,
+                r#type: Self::decode_type(&r#type, dialect, table_name)?,
                 not_null,
                 auto_increment: pk && !auto_increment.is_empty(),
                 default: None,
                 primary_key: pk,
             });
         }
-        result
+        Ok(result)
+    }
+
+    // introspection columns are read through these, a missing or mistyped value is a migration error
+    fn row_string(dialect: SQLDialect, table_name: &str, row: &ResultRow, column: &str) -> Result<String> {
+        row.get(column).and_then(|v| v.to_string()).ok_or_else(|| Self::introspection_error(dialect, table_name, column))
+    }
+
+    fn row_i64(dialect: SQLDialect, table_name: &str, row: &ResultRow, column: &str) -> Result<i64> {
+        row.get(column).and_then(|v| v.as_i64()).ok_or_else(|| Self::introspection_error(dialect, table_name, column))
     }
 
-    async fn psql_primary_field_name(conn: &dyn Queryable, table_name: &str) -> Vec<String> {
+    fn row_bool(dialect: SQLDialect, table_name: &str, row: &ResultRow, column: &str) -> Result<bool> {
+        row.get(column).and_then(|v| v.as_bool()).ok_or_else(|| Self::introspection_error(dialect, table_name, column))
+    }
+
+    fn introspection_error(dialect: SQLDialect, table_name: &str, column: &str) -> teo_result::Error {
+        SQLError::new(SQLErrorKind::Migration, dialect, format!("cannot read `{}' of column information", column)).with_table(Some(table_name)).into()
+    }
+
+    fn decode_type(r#type: &str, dialect: SQLDialect, table_name: &str) -> Result<DatabaseType> {
+        SQLTypeDecoder::decode(r#type, dialect).ok_or_else(|| {
+            SQLError::new(SQLErrorKind::UnsupportedType, dialect, format!("unhandled database type '{}'", r#type)).with_table(Some(table_name)).into()
+        })
+    }
+
+    async fn query(conn: &dyn Queryable, dialect: SQLDialect, table_name: &str, sql: String) -> Result<ResultSet> {
+        conn.query(Query::from(sql.as_str())).await.map_err(|e| SQLError::statement(dialect, Some(table_name), &sql, e).into())
+    }
+
+    async fn psql_primary_field_name(conn: &dyn Queryable, table_name: &str) -> Result<Vec<String>> {
         let sql = format!("SELECT a.attname
 FROM   pg_index i
 JOIN   pg_attribute a ON a.attrelid = i.indrelid
                      AND a.attnum = ANY(i.indkey)
 WHERE  i.indrelid = '\"{}\"'::regclass
 AND    i.indisprimary", table_name);
-        let result = conn.query(Query::from(sql)).await.unwrap();
-        result.into_iter().map(|r| {
-            r.get("attname").unwrap().to_string().unwrap()
-        }).collect()
+        let result = Self::query(conn, SQLDialect::PostgreSQL, table_name, sql).await?;
+        result.into_iter().map(|r| Self::row_string(SQLDialect::PostgreSQL, table_name, &r, "attname")).collect()
     }
 
-    async fn psql_is_unique(conn: &dyn Queryable, table_name: &str, column_name: &str) -> bool {
+    async fn psql_is_unique(conn: &dyn Queryable, table_name: &str, column_name: &str) -> Result<bool> {
         let sql = format!("SELECT *
             FROM INFORMATION_SCHEMA.TABLE_CONSTRAINTS tc
         inner join INFORMATION_SCHEMA.CONSTRAINT_COLUMN_USAGE cu
@@ -220,52 +252,74 @@ AND    i.indisprimary", table_name);
         tc.CONSTRAINT_TYPE = 'UNIQUE'
         and tc.TABLE_NAME = '{}'
         and cu.COLUMN_NAME = '{}'", table_name, column_name);
-        !conn.query(Query::from(sql)).await.unwrap().is_empty()
+        Ok(!Self::query(conn, SQLDialect::PostgreSQL, table_name, sql).await?.is_empty())
     }
 
-    async fn psql_is_auto_increment(conn: &dyn Queryable, table_name: &str, column_name: &str) -> bool {
-        !conn.query(Query::from(psql_is_auto_increment(table_name, column_name))).await.unwrap().is_empty()
+    async fn psql_is_auto_increment(conn: &dyn Queryable, table_name: &str, column_name: &str) -> Result<bool> {
+        Ok(!Self::query(conn, SQLDialect::PostgreSQL, table_name, psql_is_auto_increment(table_name, column_name)).await?.is_empty())
     }
 
-    pub(crate) async fn decode(row: ResultRow, dialect: SQLDialect, conn: &dyn Queryable, table_name: &str) -> SQLColumn {
+    pub(crate) async fn decode(row: ResultRow, dialect: SQLDialect, conn: &dyn Queryable, table_name: &str) -> Result<SQLColumn> {
         if dialect == SQLDialect::MySQL {
-            let field: String = row.get("Field").unwrap().to_string().unwrap();
-            let field_type_in_string: String = row.get("Type").unwrap().to_string().unwrap();
-            let null_in_string: String = row.get("Null").unwrap().to_string().unwrap();
+            let field = Self::row_string(dialect, table_name, &row, "Field")?;
+            let field_type_in_string = Self::row_string(dialect, table_name, &row, "Type")?;
+            let null_in_string = Self::row_string(dialect, table_name, &row, "Null")?;
             let null = &null_in_string == "YES";
-            let key: String = row.get("Key").unwrap().to_string().unwrap();
-            let extra: String = row.get("Extra").unwrap().to_string().unwrap();
+            let key = Self::row_string(dialect, table_name, &row, "Key")?;
+            let extra = Self::row_string(dialect, table_name, &row, "Extra")?;
             let auto_increment = extra.contains("auto_increment");
             let primary = &key == "PRI";
-            SQLColumn {
+            Ok(SQLColumn {
                 name: field,
-                r#type: SQLTypeDecoder::decode(&field_type_in_string, dialect),
+                r#type: Self::decode_type(&field_type_in_string, dialect, table_name)?,
                 not_null: !null,
                 auto_increment,
                 default: None,
                 primary_key: primary,
-            }
+            })
         } else if dialect == SQLDialect::PostgreSQL { // postgres
-            let primary_names = Self::psql_primary_field_name(conn, table_name).await;
-            let column_name: String = row.get("column_name").unwrap().to_string().unwrap();
-            let nullable_text: String = row.get("is_nullable").unwrap().to_string().unwrap();
+            let primary_names = Self::psql_primary_field_name(conn, table_name).await?;
+            let column_name = Self::row_string(dialect, table_name, &row, "column_name")?;
+            let nullable_text = Self::row_string(dialect, table_name, &row, "is_nullable")?;
             let nullable: bool = nullable_text == "YES";
-            let mut data_type: String = row.get("data_type").unwrap().to_string().unwrap();
-            let mut udt_name: String = row.get("udt_name").unwrap().to_string().unwrap();
+            let mut data_type = Self::row_string(dialect, table_name, &row, "data_type")?;
+            let mut udt_name = Self::row_string(dialect, table_name, &row, "udt_name")?;
             if data_type.as_str() == "ARRAY" {
                 udt_name.remove(0);
                 data_type = data_type + "|" + udt_name.as_str()
             }
-            SQLColumn {
+            Ok(SQLColumn {
                 name: column_name.clone(),
-                r#type: SQLTypeDecoder::decode(&data_type, dialect),
+                r#type: Self::decode_type(&data_type, dialect, table_name)?,
                 not_null: !nullable,
                 default: None,
                 primary_key: primary_names.contains(&column_name),
-                auto_increment: Self::psql_is_auto_increment(conn, table_name, &column_name).await,
-            }
+                auto_increment: Self::psql_is_auto_increment(conn, table_name, &column_name).await?,
+            })
+        } else if dialect == SQLDialect::MSSQL {
+            let column_name = Self::row_string(dialect, table_name, &row, "column_name")?;
+            let nullable_text = Self::row_string(dialect, table_name, &row, "is_nullable")?;
+            let data_type = Self::row_string(dialect, table_name, &row, "data_type")?;
+            let int_column = |name: &str| row.get(name).and_then(|v| v.as_i64().or(v.as_i32().map(|i| i as i64)));
+            let r#type = match data_type.as_str() {
+                "nvarchar" | "varchar" | "nchar" | "char" | "varbinary" | "binary" => match int_column("character_maximum_length") {
+                    Some(len) => format!("{data_type}({len})"),
+                    None => data_type.clone(),
+                },
+                "decimal" | "numeric" => format!("{data_type}({},{})", int_column("numeric_precision").unwrap_or(18), int_column("numeric_scale").unwrap_or(0)),
+                "datetime2" => format!("{data_type}({})", int_column("datetime_precision").unwrap_or(7)),
+                _ => data_type.clone(),
+            };
+            Ok(SQLColumn {
+                name: column_name,
+                r#type: Self::decode_type(&r#type, dialect, table_name)?,
+                not_null: nullable_text != "YES",
+                default: None,
+                primary_key: int_column("is_primary") == Some(1),
+                auto_increment: int_column("is_identity") == Some(1),
+            })
         } else {
-            unreachable!()
+            Err(SQLError::new(SQLErrorKind::UnsupportedType, dialect, "column introspection is not supported").with_table(Some(table_name)).into())
         }
     }
 }
@@ -59,7 +59,7 @@ impl SQLColumn {
 impl ToSQLString for SQLColumn {
     fn to_string(&self, dialect: SQLDialect) -> String {
         let name = &self.name;
-        let t = self.r#type.to_sql_string();
+        let t = self.r#type.to_dialect_sql_string(dialect);
         let not_null = if self.not_null { " NOT NULL" } else { " NULL" };
         let primary = if self.primary_key { " PRIMARY KEY" } else { "" };
         let default = if self.default.is_some() { " DEFAULT ".to_owned() + self.default.as_ref().unwrap().as_str() } else { "".to_owned() };
@@ -77,6 +77,9 @@ impl ToSQLString for SQLColumn {
                 t
             };
             format!("\"{name}\" {t_with_auto_inc}{default}{not_null}{primary}")
+        } else if dialect == SQLDialect::MSSQL {
+            let identity = if self.auto_increment { " IDENTITY(1,1)" } else { "" };
+            format!("[{name}] {t}{identity}{default}{not_null}{primary}")
         } else {
             format!("`{name}` {t}{default}{not_null}{primary}{auto_inc}")
         }
@@ -13,13 +13,6 @@ pub enum SQLDialect {
 
 impl SQLDialect {
 
-    pub(crate) fn escape(&self) -> &'static str {
-        match self {
-            SQLDialect::PostgreSQL => "\"",
-            _ => "`",
-        }
-    }
-
     pub(crate) fn is_postgres(&self) -> bool {
         match self {
             SQLDialect::PostgreSQL => true,
@@ -41,12 +34,30 @@ impl SQLDialect {
         }
     }
 
+    pub(crate) fn is_mssql(&self) -> bool {
+        match self {
+            SQLDialect::MSSQL => true,
+            _ => false,
+        }
+    }
+
+    // the maximum count of bound parameters a single statement accepts
+    pub(crate) fn max_params(&self) -> usize {
+        match self {
+            SQLDialect::MySQL => 65535,
+            SQLDialect::PostgreSQL => 65535,
+            SQLDialect::SQLite => 32766,
+            SQLDialect::MSSQL => 2100,
+        }
+    }
+
     pub(crate) fn float64_type(&self) -> DatabaseType {
         match self {
             SQLDialect::MySQL => DatabaseType::MySQLType(MySQLType::Double),
             SQLDialect::PostgreSQL => DatabaseType::PostgreSQLType(PostgreSQLType::DoublePrecision),
             SQLDialect::SQLite => DatabaseType::SQLiteType(SQLiteType::Real),
-            SQLDialect::MSSQL => panic!(),
+            // SQL Server columns are described with the MySQL type family
+            SQLDialect::MSSQL => DatabaseType::MySQLType(MySQLType::Double),
         }
     }
 
@@ -55,7 +66,16 @@ impl SQLDialect {
             SQLDialect::MySQL => DatabaseType::MySQLType(MySQLType::Int(None, true)),
             SQLDialect::PostgreSQL => DatabaseType::PostgreSQLType(PostgreSQLType::Integer),
             SQLDialect::SQLite => DatabaseType::SQLiteType(SQLiteType::Integer),
-            SQLDialect::MSSQL => panic!(),
+            SQLDialect::MSSQL => DatabaseType::MySQLType(MySQLType::BigInt(None, false)),
+        }
+    }
+
+    pub(crate) fn string_type(&self) -> DatabaseType {
+        match self {
+            SQLDialect::MySQL => DatabaseType::MySQLType(MySQLType::VarChar(255)),
+            SQLDialect::PostgreSQL => DatabaseType::PostgreSQLType(PostgreSQLType::Text),
+            SQLDialect::SQLite => DatabaseType::SQLiteType(SQLiteType::Text),
+            SQLDialect::MSSQL => DatabaseType::MySQLType(MySQLType::VarChar(255)),
         }
     }
 }
@@ -5,71 +5,84 @@ use snailquote::unescape;
 use teo_runtime::database::mysql::r#type::{MySQLEnum, MySQLType};
 use teo_runtime::database::postgres::r#type::PostgreSQLType;
 use teo_runtime::database::sqlite::r#type::SQLiteType;
+use crate::exts::database_type::DatabaseTypeToSQLString;
 use crate::schema::dialect::SQLDialect;
 
 pub(crate) struct SQLTypeDecoder { }
 
 impl SQLTypeDecoder {
-    pub(crate) fn decode(r#type: &str, dialect: SQLDialect) -> DatabaseType {
+    pub(crate) fn decode(r#type: &str, dialect: SQLDialect) -> Option<DatabaseType> {
         match dialect {
-            SQLDialect::MySQL => DatabaseType::MySQLType(mysql_type_to_database_type(r#type)),
-            SQLDialect::PostgreSQL => DatabaseType::PostgreSQLType(postgresql_type_to_database_type(r#type)),
-            SQLDialect::SQLite => DatabaseType::SQLiteType(sqlite_type_to_database_type(r#type)),
-            SQLDialect::MSSQL => panic!(),
+            SQLDialect::MySQL => mysql_type_to_database_type(r#type).map(DatabaseType::MySQLType),
+            SQLDialect::PostgreSQL => postgresql_type_to_database_type(r#type).map(DatabaseType::PostgreSQLType),
+            SQLDialect::SQLite => sqlite_type_to_database_type(r#type).map(DatabaseType::SQLiteType),
+            SQLDialect::MSSQL => mssql_type_to_database_type(r#type),
         }
     }
+
+    // several model types share one SQL Server type, they are compared as the type introspection
+    // reads back for the T-SQL they are created with
+    pub(crate) fn normalize(r#type: &DatabaseType, dialect: SQLDialect) -> DatabaseType {
+        if dialect != SQLDialect::MSSQL {
+            return r#type.clone();
+        }
+        Self::decode(&r#type.to_dialect_sql_string(dialect), dialect).unwrap_or_else(|| r#type.clone())
+    }
 }
 
-fn mysql_type_to_database_type(r#type: &str) -> MySQLType {
+fn mysql_type_to_database_type(r#type: &str) -> Option<MySQLType> {
     let r#type_string = r#type.to_lowercase();
     let r#type: &str = r#type_string.as_str();
     let regex = Regex::new("([^ \\(\\)]+)( (.+))?(\\((.+)\\))?").unwrap();
     match regex.captures(r#type) {
-        None => panic!("Unhandled database type '{}' '{}'.", r#type, regex),
+        None => None,
         Some(captures) => {
             let name = captures.get(1).unwrap().as_str();
             let trailing1 = captures.get(3).map(|m| m.as_str());
             let arg = captures.get(5).map(|m| m.as_str());
-            match name {
-                "bit" => MySQLType::Bit(arg.map(|a| i32::from_str(a).unwrap())),
-                "tinyint" => MySQLType::TinyInt(arg.map(|a| i32::from_str(a).unwrap()), trailing1.is_some()),
-                "smallint" => MySQLType::SmallInt(arg.map(|a| i32::from_str(a).unwrap()), trailing1.is_some()),
-                "mediumint" => MySQLType::MediumInt(arg.map(|a| i32::from_str(a).unwrap()), trailing1.is_some()),
-                "int" => MySQLType::Int(arg.map(|a| i32::from_str(a).unwrap()), trailing1.is_some()),
-                "bigint" => MySQLType::BigInt(arg.map(|a| i32::from_str(a).unwrap()), trailing1.is_some()),
+            // a malformed display width makes the type unsupported, the caller reports it
+            let width = || arg.map(i32::from_str).transpose().ok();
+            Some(match name {
+                "bit" => MySQLType::Bit(width()?),
+                "tinyint" => MySQLType::TinyInt(width()?, trailing1.is_some()),
+                "smallint" => MySQLType::SmallInt(width()?, trailing1.is_some()),
+                "mediumint" => MySQLType::MediumInt(width()?, trailing1.is_some()),
+                "int" => MySQLType::Int(width()?, trailing1.is_some()),
+                "bigint" => MySQLType::BigInt(width()?, trailing1.is_some()),
                 "float" => MySQLType::Float,
                 "double" => MySQLType::Double,
-                "char" => MySQLType::Char(arg.map(|a| i32::from_str(a).unwrap()).unwrap()),
-                "varchar" => MySQLType::VarChar(arg.map(|a| i32::from_str(a).unwrap()).unwrap()),
+                "char" => MySQLType::Char(i32::from_str(arg?).ok()?),
+                "varchar" => MySQLType::VarChar(i32::from_str(arg?).ok()?),
                 "text" => MySQLType::Text,
                 "mediumtext" => MySQLType::MediumText,
                 "longtext" => MySQLType::LongText,
                 "date" => MySQLType::Date,
-                "datetime" => MySQLType::DateTime(i32::from_str(arg.unwrap()).unwrap()),
+                "json" => MySQLType::Json,
+                "datetime" => MySQLType::DateTime(i32::from_str(arg?).ok()?),
                 "decimal" => {
                     if let Some(args) = arg {
                         let args = args.split(",").into_iter().collect::<Vec<&str>>();
-                        MySQLType::Decimal(args.get(0).unwrap().parse().unwrap(), args.get(1).unwrap().parse().unwrap())
+                        MySQLType::Decimal(args.get(0)?.parse().ok()?, args.get(1)?.parse().ok()?)
                     } else {
-                        panic!()
+                        return None
                     }
                 }
                 "enum" => {
-                    let choices = arg.unwrap();
+                    let choices = arg?;
                     let choices_vec = choices.split(",");
-                    let unescaped: Vec<String> = choices_vec.map(|s| unescape(s).unwrap()).collect();
+                    let unescaped: Vec<String> = choices_vec.map(|s| unescape(s).ok()).collect::<Option<Vec<String>>>()?;
                     MySQLType::Enum(MySQLEnum { variants: unescaped })
                 }
-                _ => panic!("Unhandled type '{}' '{:?}' '{:?}'.", name, trailing1, arg)
-            }
+                _ => return None
+            })
         }
     }
 }
 
-fn postgresql_type_to_database_type(r#type: &str) -> PostgreSQLType {
+fn postgresql_type_to_database_type(r#type: &str) -> Option<PostgreSQLType> {
     let lower = r#type.to_lowercase();
     let lower_str = lower.as_str();
-    match lower_str {
+    Some(match lower_str {
         "integer" | "int4" => PostgreSQLType::Integer,
         "text" => PostgreSQLType::Text,
         "timestamp without time zone" | "timestamp" => PostgreSQLType::Timestamp(3, false),
@@ -79,39 +92,119 @@ fn postgresql_type_to_database_type(r#type: &str) -> PostgreSQLType {
         "real" | "float4" => PostgreSQLType::Real,
         "date" => PostgreSQLType::Date,
         "numeric" => PostgreSQLType::Decimal(65, 30),
+        "json" => PostgreSQLType::Json,
+        "jsonb" => PostgreSQLType::JsonB,
         _ => if lower_str.starts_with("array|") {
             let inner = &lower_str[6..];
-            PostgreSQLType::Array(Box::new(postgresql_type_to_database_type(inner)))
+            PostgreSQLType::Array(Box::new(postgresql_type_to_database_type(inner)?))
         } else {
-            panic!("Unhandled database type {}", r#type)
+            return None
         }
-    }
+    })
 }
 
-fn sqlite_type_to_database_type(r#type: &str) -> SQLiteType {
+fn sqlite_type_to_database_type(r#type: &str) -> Option<SQLiteType> {
     let r#type_string = r#type.to_lowercase();
     let r#type: &str = r#type_string.as_str();
     let regex = Regex::new("([^ \\(\\)]+)( (.+))?(\\((.+)\\))?").unwrap();
     match regex.captures(r#type) {
-        None => panic!("Unhandled database type '{}' '{}'.", r#type, regex),
+        None => None,
         Some(captures) => {
             let name = captures.get(1).unwrap().as_str();
-            let trailing1 = captures.get(3).map(|m| m.as_str());
-            let arg = captures.get(5).map(|m| m.as_str());
-            match name {
+            Some(match name {
                 "integer" => SQLiteType::Integer,
                 "text" => SQLiteType::Text,
                 "real" => SQLiteType::Real,
                 "double" => SQLiteType::Real,
                 "decimal" => SQLiteType::Decimal,
-                _ => panic!("Unhandled type '{}' '{:?}' '{:?}'.", name, trailing1, arg)
-            }
+                _ => return None
+            })
         }
     }
 }
 
-fn mssql_type_to_database_type(r#type: &str) -> DatabaseType {
-    match r#type.to_lowercase().as_str() {
-        _ => panic!("Unhandled database type.")
+// SQL Server columns are described with the MySQL type family
+fn mssql_type_to_database_type(r#type: &str) -> Option<DatabaseType> {
+    let r#type_string = r#type.to_lowercase();
+    let r#type: &str = r#type_string.as_str();
+    let regex = Regex::new("([^ \\(\\)]+)(\\((.+)\\))?").unwrap();
+    let captures = regex.captures(r#type)?;
+    let name = captures.get(1).unwrap().as_str();
+    let arg = captures.get(3).map(|m| m.as_str());
+    let is_max = arg.map_or(false, |a| a == "max" || a == "-1");
+    Some(DatabaseType::MySQLType(match name {
+        "nvarchar" | "varchar" => if is_max { MySQLType::Text } else { MySQLType::VarChar(i32::from_str(arg?).ok()?) },
+        "nchar" | "char" => MySQLType::Char(i32::from_str(arg?).ok()?),
+        "ntext" | "text" => MySQLType::Text,
+        "bit" => MySQLType::Bit(None),
+        "tinyint" => MySQLType::TinyInt(None, false),
+        "smallint" => MySQLType::SmallInt(None, false),
+        "int" => MySQLType::Int(None, false),
+        "bigint" => MySQLType::BigInt(None, false),
+        "real" => MySQLType::Float,
+        "float" => MySQLType::Double,
+        "decimal" | "numeric" => {
+            let args = arg?.split(",").map(|a| a.trim()).collect::<Vec<&str>>();
+            MySQLType::Decimal(args.get(0)?.parse().ok()?, args.get(1)?.parse().ok()?)
+        }
+        "datetime2" => MySQLType::DateTime(arg.map_or(Some(7), |a| i32::from_str(a).ok())?),
+        "datetime" => MySQLType::DateTime(3),
+        "date" => MySQLType::Date,
+        "time" => MySQLType::Time(arg.map_or(Some(7), |a| i32::from_str(a).ok())?),
+        "varbinary" | "image" => MySQLType::LongBlob,
+        "binary" => MySQLType::Binary,
+        _ => return None
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use teo_runtime::database::mysql::r#type::{MySQLEnum, MySQLType};
+    use teo_runtime::database::r#type::DatabaseType;
+    use crate::exts::database_type::DatabaseTypeToSQLString;
+    use crate::schema::dialect::SQLDialect;
+    use super::SQLTypeDecoder;
+
+    #[test]
+    fn mssql_types_round_trip() {
+        let dialect = SQLDialect::MSSQL;
+        // model types and the type strings introspection builds for their columns
+        let types = vec![
+            (MySQLType::VarChar(255), "nvarchar(255)"),
+            (MySQLType::Char(10), "nchar(10)"),
+            (MySQLType::Text, "nvarchar(-1)"),
+            (MySQLType::TinyText, "nvarchar(-1)"),
+            (MySQLType::MediumText, "nvarchar(-1)"),
+            (MySQLType::LongText, "nvarchar(-1)"),
+            (MySQLType::Json, "nvarchar(-1)"),
+            (MySQLType::Enum(MySQLEnum { variants: vec!["a".to_owned(), "b".to_owned()] }), "nvarchar(255)"),
+            (MySQLType::Bit(None), "bit"),
+            (MySQLType::TinyInt(None, false), "tinyint"),
+            (MySQLType::SmallInt(None, false), "smallint"),
+            (MySQLType::Year, "smallint"),
+            (MySQLType::MediumInt(None, false), "int"),
+            (MySQLType::Int(None, false), "int"),
+            (MySQLType::BigInt(None, false), "bigint"),
+            (MySQLType::Float, "real"),
+            (MySQLType::Double, "float"),
+            (MySQLType::Decimal(10, 2), "decimal(10,2)"),
+            (MySQLType::DateTime(3), "datetime2(3)"),
+            (MySQLType::Timestamp(3), "datetime2(7)"),
+            (MySQLType::Date, "date"),
+            (MySQLType::Time(0), "time"),
+            (MySQLType::Binary, "binary(1)"),
+            (MySQLType::VarBinary, "varbinary(-1)"),
+            (MySQLType::TinyBlob, "varbinary(-1)"),
+            (MySQLType::Blob, "varbinary(-1)"),
+            (MySQLType::MediumBlob, "varbinary(-1)"),
+            (MySQLType::LongBlob, "varbinary(-1)"),
+        ];
+        for (r#type, introspected) in types {
+            let r#type = DatabaseType::MySQLType(r#type);
+            let normalized = SQLTypeDecoder::normalize(&r#type, dialect);
+            assert_eq!(SQLTypeDecoder::decode(introspected, dialect), Some(normalized.clone()), "{introspected}");
+            assert_eq!(normalized.to_dialect_sql_string(dialect), r#type.to_dialect_sql_string(dialect));
+            assert_eq!(SQLTypeDecoder::normalize(&normalized, dialect), normalized);
+        }
     }
 }
@@ -1,9 +1,15 @@
 use crate::schema::dialect::SQLDialect;
+use crate::schema::value::encode::is_json_type;
 use teo_teon::value::Value;
-use chrono::{NaiveDate, DateTime, Utc};
+use std::str::FromStr;
+use bigdecimal::BigDecimal;
+use chrono::{NaiveDate, NaiveDateTime, DateTime, Utc};
+use serde_json::Value as JsonValue;
 use indexmap::IndexMap;
 use quaint_forked::prelude::{ResultRow, ResultSet, Value as QuaintValue};
 use teo_parser::r#type::Type;
+use teo_result::{Error, Result};
+use base64::prelude::*;
 
 pub(crate) struct RowDecoder { }
 
@@ -65,6 +71,18 @@ impl RowDecoder {
                     None => Value::Null,
                 }
             }
+            quaint_forked::Value::Json(j) => {
+                match j {
+                    Some(j) => Self::json_to_value(j),
+                    None => Value::Null,
+                }
+            }
+            quaint_forked::Value::Array(a) => {
+                match a {
+                    Some(a) => Value::Array(a.iter().map(Self::decode_raw).collect()),
+                    None => Value::Null,
+                }
+            }
             _ => unreachable!()
         }
     }
@@ -100,153 +118,263 @@ impl RowDecoder {
         }
     }
 
-    pub(crate) fn decode_value(r#type: &Type, optional: bool, value: Option<&quaint_forked::Value>, dialect: SQLDialect) -> Value {
+    pub(crate) fn decode_value(r#type: &Type, optional: bool, value: Option<&quaint_forked::Value>, dialect: SQLDialect) -> Result<Value> {
         if optional {
             if value.is_none() {
-                return Value::Null;
+                return Ok(Value::Null);
             }
         }
         let r#type = r#type.unwrap_optional();
         let value = value.unwrap();
+        if is_json_type(r#type) {
+            return Ok(match value {
+                QuaintValue::Json(Some(json)) => Self::json_to_value(json),
+                QuaintValue::Text(Some(text)) => Self::json_to_value(&serde_json::from_str(text.as_ref()).unwrap_or(JsonValue::String(text.as_ref().to_owned()))),
+                _ => Value::Null,
+            });
+        }
         if r#type.is_bool() {
             if let Some(v) = value.as_bool() {
-                return Value::Bool(v)
+                return Ok(Value::Bool(v))
             } else {
-                return Value::Null;
+                return Ok(Value::Null);
             }
         }
         if r#type.is_string() {
             if let Some(v) = value.as_str() {
-                return Value::String(v.to_owned())
+                return Ok(Value::String(v.to_owned()))
             } else {
-                return Value::Null;
+                return Ok(Value::Null);
             }
         }
         if r#type.is_int() {
             if let Some(v) = value.as_i32() {
-                return Value::Int(v);
+                return Ok(Value::Int(v));
             } else {
-                return Value::Null;
+                return Ok(Value::Null);
             }
         }
         if r#type.is_int64() {
             if let Some(v) = value.as_i64() {
-                return Value::Int64(v);
+                return Ok(Value::Int64(v));
             } else if let Some(v) = value.as_i32() {
-                return Value::Int(v);
+                return Ok(Value::Int(v));
             } else {
-                return Value::Null;
+                return Ok(Value::Null);
             }
         }
         if r#type.is_float32() || r#type.is_float() {
             if let Some(f64_val) = value.as_f64() {
-                return if r#type.is_float() {
+                return Ok(if r#type.is_float() {
                     Value::Float(f64_val)
                 } else if r#type.is_float32() {
                     Value::Float32(f64_val as f32)
                 } else {
                     unreachable!()
-                };
+                });
             } else if let Some(f32_val) = value.as_f32() {
-                return if r#type.is_float() {
+                return Ok(if r#type.is_float() {
                     Value::Float(f32_val as f64)
                 } else if r#type.is_float32() {
                     Value::Float32(f32_val)
                 } else {
                     unreachable!()
-                };
+                });
             } else {
-                return Value::Null;
+                return Ok(Value::Null);
             }
         }
         if r#type.is_date() {
             if dialect == SQLDialect::PostgreSQL {
                 if let Some(naive_date) = value.as_date() {
-                    return Value::Date(naive_date);
+                    return Ok(Value::Date(naive_date));
                 } else {
-                    return Value::Null;
+                    return Ok(Value::Null);
                 }
             } else if dialect == SQLDialect::SQLite {
                 if let Some(timestamp) = value.as_str() {
                     let naive_date = NaiveDate::parse_from_str(timestamp, "%Y-%m-%d").unwrap();
-                    return Value::Date(naive_date);
+                    return Ok(Value::Date(naive_date));
                 } else {
-                    return Value::Null;
+                    return Ok(Value::Null);
                 }
             } else if dialect == SQLDialect::MySQL {
                 if let Some(datetime) = value.as_datetime() {
                     let naive_date = datetime.date_naive();
-                    return Value::Date(naive_date);
+                    return Ok(Value::Date(naive_date));
                 } else {
-                    return Value::Null;
+                    return Ok(Value::Null);
                 }
             } else {
                 if let Some(naive_date) = value.as_date() {
-                    return Value::Date(naive_date);
+                    return Ok(Value::Date(naive_date));
                 } else {
-                    return Value::Null;
+                    return Ok(Value::Null);
                 }
             }
         }
         if r#type.is_datetime() {
             if dialect == SQLDialect::PostgreSQL {
                 if let Some(datetime) = value.as_datetime() {
-                    return Value::DateTime(datetime);
+                    return Ok(Value::DateTime(datetime));
                 } else {
-                    return Value::Null;
+                    return Ok(Value::Null);
                 }
             } else if dialect == SQLDialect::SQLite {
                 if let Some(timestamp) = value.as_str() {
-                    return Value::DateTime(DateTime::parse_from_rfc3339(timestamp).unwrap().with_timezone(&Utc));
+                    return Ok(Value::DateTime(DateTime::parse_from_rfc3339(timestamp).unwrap().with_timezone(&Utc)));
                 } else {
-                    return Value::Null;
+                    return Ok(Value::Null);
                 }
             } else {
                 if let Some(datetime) = value.as_datetime() {
-                    return Value::DateTime(datetime);
+                    return Ok(Value::DateTime(datetime));
                 } else {
-                    return Value::Null;
+                    return Ok(Value::Null);
                 }
             }
         }
         if r#type.is_decimal() {
             if let Some(val) = value.as_numeric() {
-                return Value::Decimal(val.clone());
+                return Ok(Value::Decimal(val.clone()));
             } else {
-                return Value::Null;
+                return Ok(Value::Null);
             }
         }
         if r#type.is_array() {
             if let Some(vals) = value.as_array() {
                 let inner = r#type.as_array().unwrap();
-                return Value::Array(vals.iter().map(|v| Self::decode_value(inner.unwrap_optional(), inner.is_optional(), Some(v), dialect)).collect());
+                return Ok(Value::Array(vals.iter().map(|v| Self::decode_value(inner.unwrap_optional(), inner.is_optional(), Some(v), dialect)).collect::<Result<Vec<Value>>>()?));
             } else {
-                return Value::Null;
+                return Ok(Value::Null);
             }
         }
         if r#type.is_enum_variant() {
             match value {
                 QuaintValue::Enum(v) => {
                     if let Some(v) = v {
-                        return Value::String(v.as_ref().to_owned());
+                        return Ok(Value::String(v.as_ref().to_owned()));
                     } else {
-                        return Value::Null;
+                        return Ok(Value::Null);
                     }
                 }
                 QuaintValue::Text(v) => {
                     if let Some(v) = v {
-                        return Value::String(v.as_ref().to_owned());
+                        return Ok(Value::String(v.as_ref().to_owned()));
                     } else {
-                        return Value::Null;
+                        return Ok(Value::Null);
                     }
                 }
-                _ => panic!("unhandled enum variant"),
+                _ => return Err(Error::new("cannot decode enum variant from a non-text value")),
             }
         }
-        panic!("Unhandled database when decoding type.")
+        if r#type.is_bytes() {
+            return Ok(value.as_bytes().map(|b| Value::Bytes(b.to_vec())).unwrap_or(Value::Null));
+        }
+        Err(Error::new(format!("cannot decode value of type `{}'", r#type)))
+    }
+
+    // relations loaded with JSON aggregation come back as text on every database except PostgreSQL
+    pub(crate) fn decode_json_column(value: Option<&QuaintValue>) -> JsonValue {
+        match value {
+            Some(QuaintValue::Json(Some(json))) => json.clone(),
+            Some(QuaintValue::Text(Some(text))) => serde_json::from_str(text.as_ref()).unwrap_or(JsonValue::Array(vec![])),
+            _ => JsonValue::Array(vec![]),
+        }
+    }
+
+    pub(crate) fn decode_json(r#type: &Type, value: &JsonValue) -> Result<Value> {
+        if value.is_null() {
+            return Ok(Value::Null);
+        }
+        let r#type = r#type.unwrap_optional();
+        if is_json_type(r#type) {
+            // stored as text on SQLite and SQL Server, the document is nested as a string
+            let parsed = value.as_str().and_then(|s| serde_json::from_str::<JsonValue>(s).ok()).filter(|j| j.is_object() || j.is_array());
+            return Ok(Self::json_to_value(parsed.as_ref().unwrap_or(value)));
+        }
+        if r#type.is_bool() {
+            return Ok(value.as_bool().or_else(|| value.as_i64().map(|i| i != 0)).map(Value::Bool).unwrap_or(Value::Null));
+        }
+        if r#type.is_string() || r#type.is_enum_variant() {
+            return Ok(value.as_str().map(|s| Value::String(s.to_owned())).unwrap_or(Value::Null));
+        }
+        if r#type.is_int() {
+            return Ok(value.as_i64().map(|i| Value::Int(i as i32)).unwrap_or(Value::Null));
+        }
+        if r#type.is_int64() {
+            return Ok(value.as_i64().map(Value::Int64).unwrap_or(Value::Null));
+        }
+        if r#type.is_float() {
+            return Ok(Self::json_f64(value).map(Value::Float).unwrap_or(Value::Null));
+        }
+        if r#type.is_float32() {
+            return Ok(Self::json_f64(value).map(|f| Value::Float32(f as f32)).unwrap_or(Value::Null));
+        }
+        if r#type.is_decimal() {
+            let text = value.as_str().map(|s| s.to_owned()).unwrap_or_else(|| value.to_string());
+            return Ok(BigDecimal::from_str(&text).map(Value::Decimal).unwrap_or(Value::Null));
+        }
+        if r#type.is_date() {
+            return Ok(value.as_str().and_then(|s| NaiveDate::parse_from_str(s.get(0..10).unwrap_or(s), "%Y-%m-%d").ok()).map(Value::Date).unwrap_or(Value::Null));
+        }
+        if r#type.is_datetime() {
+            return Ok(value.as_str().and_then(Self::json_datetime).map(Value::DateTime).unwrap_or(Value::Null));
+        }
+        if r#type.is_array() {
+            let inner = r#type.as_array().unwrap();
+            let Some(vals) = value.as_array() else {
+                return Ok(Value::Null);
+            };
+            return Ok(Value::Array(vals.iter().map(|v| Self::decode_json(inner, v)).collect::<Result<Vec<Value>>>()?));
+        }
+        if r#type.is_bytes() {
+            return Ok(value.as_str().and_then(Self::json_bytes).map(Value::Bytes).unwrap_or(Value::Null));
+        }
+        Err(Error::new(format!("cannot decode value of type `{}'", r#type)))
+    }
+
+    pub(crate) fn json_to_value(value: &JsonValue) -> Value {
+        match value {
+            JsonValue::Null => Value::Null,
+            JsonValue::Bool(b) => Value::Bool(*b),
+            JsonValue::Number(n) => if let Some(i) = n.as_i64() {
+                Value::Int64(i)
+            } else {
+                Value::Float(n.as_f64().unwrap_or(0.0))
+            },
+            JsonValue::String(s) => Value::String(s.clone()),
+            JsonValue::Array(values) => Value::Array(values.iter().map(Self::json_to_value).collect()),
+            JsonValue::Object(map) => Value::Dictionary(map.iter().map(|(k, v)| (k.clone(), Self::json_to_value(v))).collect()),
+        }
+    }
+
+    fn json_f64(value: &JsonValue) -> Option<f64> {
+        value.as_f64().or_else(|| value.as_str().and_then(|s| s.parse().ok()))
+    }
+
+    // PostgreSQL aggregates binary as `\x` prefixed hex, MySQL as `base64:typeN:` prefixed base64
+    fn json_bytes(text: &str) -> Option<Vec<u8>> {
+        if let Some(hex) = text.strip_prefix("\\x") {
+            return hex::decode(hex).ok();
+        }
+        let encoded = match text.strip_prefix("base64:") {
+            Some(rest) => rest.split_once(':').map_or(rest, |(_, data)| data),
+            None => text,
+        };
+        BASE64_STANDARD.decode(encoded).ok()
+    }
+
+    fn json_datetime(text: &str) -> Option<DateTime<Utc>> {
+        if let Ok(datetime) = DateTime::parse_from_rfc3339(text) {
+            return Some(datetime.with_timezone(&Utc));
+        }
+        ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"].iter().find_map(|format| {
+            NaiveDateTime::parse_from_str(text, format).ok()
+        }).map(|naive| DateTime::<Utc>::from_naive_utc_and_offset(naive, Utc))
     }
 
-    pub(crate) fn decode(r#type: &Type, optional: bool, row: &ResultRow, column_name: &str, dialect: SQLDialect) -> Value {
+    pub(crate) fn decode(r#type: &Type, optional: bool, row: &ResultRow, column_name: &str, dialect: SQLDialect) -> Result<Value> {
         let result = row.get(column_name);
         Self::decode_value(r#type, optional, result.clone(), dialect)
     }
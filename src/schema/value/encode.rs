@@ -1,6 +1,9 @@
+use std::borrow::Cow;
 use bigdecimal::BigDecimal;
 use chrono::{NaiveDate, Utc, DateTime, SecondsFormat};
 use itertools::Itertools;
+use quaint_forked::prelude::Value as QuaintValue;
+use serde_json::{Map, Number, Value as JsonValue};
 use teo_parser::r#type::Type;
 use teo_teon::types::enum_variant::EnumVariant;
 use crate::schema::dialect::SQLDialect;
@@ -160,6 +163,158 @@ impl PSQLArrayToSQLString for Value {
     }
 }
 
+pub(crate) trait ToSQLParam {
+    fn to_sql_param(&self, r#type: &Type, dialect: SQLDialect) -> QuaintValue<'static>;
+}
+
+impl ToSQLParam for Value {
+    fn to_sql_param(&self, r#type: &Type, dialect: SQLDialect) -> QuaintValue<'static> {
+        if self.is_null() {
+            return null_sql_param(r#type, dialect);
+        }
+        if is_json_type(r#type) {
+            let json = value_to_json(self);
+            return if dialect == SQLDialect::PostgreSQL {
+                QuaintValue::Json(Some(json))
+            } else {
+                QuaintValue::Text(Some(Cow::Owned(json.to_string())))
+            };
+        }
+        match self {
+            Value::String(s) => QuaintValue::Text(Some(Cow::Owned(s.clone()))),
+            Value::Int(i) => QuaintValue::Int32(Some(*i)),
+            Value::Int64(i) => QuaintValue::Int64(Some(*i)),
+            Value::Float32(f) => QuaintValue::Float(Some(*f)),
+            Value::Float(f) => QuaintValue::Double(Some(*f)),
+            Value::Bool(b) => QuaintValue::Boolean(Some(*b)),
+            // SQL Server binds native date types, the others use the textual format of the literal encoders
+            Value::Date(d) if dialect == SQLDialect::MSSQL => QuaintValue::Date(Some(*d)),
+            Value::DateTime(d) if dialect == SQLDialect::MSSQL => QuaintValue::DateTime(Some(*d)),
+            Value::Date(d) => QuaintValue::Text(Some(Cow::Owned(d.format("%Y-%m-%d").to_string()))),
+            Value::DateTime(d) => QuaintValue::Text(Some(Cow::Owned(if dialect == SQLDialect::SQLite {
+                d.to_rfc3339_opts(SecondsFormat::Millis, true)
+            } else {
+                d.format("%Y-%m-%d %H:%M:%S.%3f").to_string()
+            }))),
+            Value::Decimal(d) => QuaintValue::Numeric(Some(d.normalized())),
+            Value::EnumVariant(e) => QuaintValue::Text(Some(Cow::Owned(e.value.clone()))),
+            Value::Array(values) => {
+                let element_type = r#type.unwrap_optional().as_array().map(|t| t.unwrap_optional()).unwrap_or(&Type::Undetermined);
+                QuaintValue::Array(Some(values.iter().map(|v| v.to_sql_param(element_type, dialect)).collect()))
+            }
+            _ => panic!("unhandled value: {:?}", self),
+        }
+    }
+}
+
+// drivers bind a null with the type of the column it is compared with or stored into
+fn null_sql_param(r#type: &Type, dialect: SQLDialect) -> QuaintValue<'static> {
+    match r#type.unwrap_optional() {
+        Type::Dictionary(_) | Type::Any if dialect == SQLDialect::PostgreSQL => QuaintValue::Json(None),
+        Type::Int => QuaintValue::Int32(None),
+        Type::Int64 => QuaintValue::Int64(None),
+        Type::Float32 => QuaintValue::Float(None),
+        Type::Float => QuaintValue::Double(None),
+        Type::Bool => QuaintValue::Boolean(None),
+        Type::Decimal => QuaintValue::Numeric(None),
+        Type::Date if dialect == SQLDialect::MSSQL => QuaintValue::Date(None),
+        Type::DateTime if dialect == SQLDialect::MSSQL => QuaintValue::DateTime(None),
+        Type::Array(_) => QuaintValue::Array(None),
+        _ => QuaintValue::Text(None),
+    }
+}
+
+pub(crate) fn psql_cast(value: &Value, r#type: &Type) -> Option<String> {
+    if is_json_type(r#type) {
+        return Some("jsonb".to_owned());
+    }
+    match value {
+        Value::Date(_) => Some("date".to_owned()),
+        Value::DateTime(_) => Some("timestamp".to_owned()),
+        Value::Decimal(_) => Some("numeric".to_owned()),
+        Value::Array(values) => {
+            let element_type = r#type.unwrap_optional().as_array().map(|t| t.unwrap_optional());
+            let element_psql = if let Some(element_type) = element_type {
+                psql_type_name(element_type)
+            } else {
+                values.first().map(|v| psql_value_type_name(v)).flatten()
+            };
+            element_psql.map(|t| format!("{}[]", t))
+        }
+        _ => None,
+    }
+}
+
+fn psql_type_name(field_type: &Type) -> Option<&'static str> {
+    match field_type {
+        Type::Decimal | Type::Int | Type::Int64 | Type::Float32 | Type::Float |
+        Type::String | Type::Bool | Type::Date | Type::DateTime => Some(field_type_to_psql(field_type)),
+        _ => None,
+    }
+}
+
+fn psql_value_type_name(value: &Value) -> Option<&'static str> {
+    match value {
+        Value::Decimal(_) => Some("decimal"),
+        Value::Int(_) | Value::Int64(_) => Some("integer"),
+        Value::Float32(_) | Value::Float(_) => Some("double precision"),
+        Value::String(_) => Some("text"),
+        Value::Bool(_) => Some("boolean"),
+        Value::Date(_) => Some("date"),
+        Value::DateTime(_) => Some("timestamp"),
+        _ => None,
+    }
+}
+
+// JSON columns are declared with a dictionary or `Any` type
+pub(crate) fn is_json_type(r#type: &Type) -> bool {
+    matches!(r#type.unwrap_optional(), Type::Dictionary(_) | Type::Any)
+}
+
+pub(crate) fn value_to_json(value: &Value) -> JsonValue {
+    match value {
+        Value::Null => JsonValue::Null,
+        Value::Bool(b) => JsonValue::Bool(*b),
+        Value::Int(i) => JsonValue::Number(Number::from(*i)),
+        Value::Int64(i) => JsonValue::Number(Number::from(*i)),
+        Value::Float32(f) => Number::from_f64(*f as f64).map(JsonValue::Number).unwrap_or(JsonValue::Null),
+        Value::Float(f) => Number::from_f64(*f).map(JsonValue::Number).unwrap_or(JsonValue::Null),
+        Value::Decimal(d) => JsonValue::String(d.normalized().to_string()),
+        Value::String(s) => JsonValue::String(s.clone()),
+        Value::Date(d) => JsonValue::String(d.format("%Y-%m-%d").to_string()),
+        Value::DateTime(d) => JsonValue::String(d.to_rfc3339_opts(SecondsFormat::Millis, true)),
+        Value::EnumVariant(e) => JsonValue::String(e.value.clone()),
+        Value::Array(values) => JsonValue::Array(values.iter().map(value_to_json).collect()),
+        Value::Dictionary(map) => JsonValue::Object(map.iter().map(|(k, v)| (k.clone(), value_to_json(v))).collect::<Map<String, JsonValue>>()),
+        _ => panic!("unhandled value: {:?}", value),
+    }
+}
+
+// a JSON path is given as `["a", "b", "0"]` or `"a.b.0"`, numeric segments index arrays
+pub(crate) fn json_path_segments(path: &Value) -> Vec<String> {
+    if let Some(path) = path.as_str() {
+        path.trim_start_matches('$').split('.').filter(|s| !s.is_empty()).map(|s| s.to_owned()).collect()
+    } else {
+        path.as_array().unwrap().iter().map(|s| match s {
+            Value::Int(i) => i.to_string(),
+            Value::Int64(i) => i.to_string(),
+            _ => s.as_str().unwrap().to_owned(),
+        }).collect()
+    }
+}
+
+pub(crate) fn json_path_string(segments: &Vec<String>) -> String {
+    let mut result = "$".to_owned();
+    for segment in segments {
+        if segment.parse::<usize>().is_ok() {
+            result += &format!("[{segment}]");
+        } else {
+            result += &format!(".\"{segment}\"");
+        }
+    }
+    result
+}
+
 pub trait ToWrapped {
     fn to_wrapped(&self) -> String;
 }
@@ -261,26 +416,6 @@ impl ToSQLInputDialect for DateTime<Utc> {
     }
 }
 
-pub trait IfIMode {
-    fn to_i_mode(&self, i_mode: bool) -> String;
-}
-
-impl IfIMode for &str {
-    fn to_i_mode(&self, i_mode: bool) -> String {
-        if i_mode {
-            "LOWER(".to_owned() + self + ")"
-        } else {
-            self.to_string()
-        }
-    }
-}
-
-impl IfIMode for String {
-    fn to_i_mode(&self, i_mode: bool) -> String {
-        self.as_str().to_i_mode(i_mode)
-    }
-}
-
 pub trait ToLike {
     fn to_like(&self, left: bool, right: bool) -> String;
 }
@@ -330,12 +465,13 @@ pub trait SQLEscape {
 
 impl SQLEscape for &str {
     fn is_escaped(&self) -> bool {
-        self.starts_with("'") || self.starts_with("\"") || self.starts_with("`")
+        self.starts_with("'") || self.starts_with("\"") || self.starts_with("`") || self.starts_with("[")
     }
     fn escape(&self, dialect: SQLDialect) -> String {
         match dialect {
             SQLDialect::MySQL => format!("`{}`", self),
             SQLDialect::PostgreSQL => format!("\"{}\"", self),
+            SQLDialect::MSSQL => format!("[{}]", self),
             _ => format!("`{}`", self),
         }
     }
@@ -344,15 +480,11 @@ impl SQLEscape for &str {
 impl SQLEscape for String {
 
     fn is_escaped(&self) -> bool {
-        self.starts_with("'") || self.starts_with("\"") || self.starts_with("`")
+        self.as_str().is_escaped()
     }
 
     fn escape(&self, dialect: SQLDialect) -> String {
-        match dialect {
-            SQLDialect::MySQL => format!("`{}`", self),
-            SQLDialect::PostgreSQL => format!("\"{}\"", self),
-            _ => format!("`{}`", self),
-        }
+        self.as_str().escape(dialect)
     }
 }
 
@@ -1,6 +1,6 @@
 use crate::schema::column::SQLColumn;
 use crate::schema::dialect::SQLDialect;
-use crate::schema::value::encode::ToSQLString;
+use crate::schema::value::encode::{SQLEscape, ToSQLString};
 
 pub struct SQLAlterTableAddStatement {
     pub(crate) table: String,
@@ -11,7 +11,6 @@ impl ToSQLString for SQLAlterTableAddStatement {
     fn to_string(&self, dialect: SQLDialect) -> String {
         let table = &self.table;
         let def = self.column_def.to_string(dialect);
-        let escape = if dialect == SQLDialect::PostgreSQL { "\"" } else { "`" };
-        format!("ALTER TABLE {escape}{table}{escape} ADD {def}")
+        format!("ALTER TABLE {} ADD {def}", table.escape(dialect))
     }
 }
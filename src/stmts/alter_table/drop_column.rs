@@ -1,5 +1,5 @@
 use crate::schema::dialect::SQLDialect;
-use crate::schema::value::encode::ToSQLString;
+use crate::schema::value::encode::{SQLEscape, ToSQLString};
 
 pub struct SQLAlterTableDropColumnStatement {
     pub(crate) table: String,
@@ -7,9 +7,9 @@ pub struct SQLAlterTableDropColumnStatement {
 }
 
 impl ToSQLString for SQLAlterTableDropColumnStatement {
-    fn to_string(&self, _dialect: SQLDialect) -> String {
-        let table = &self.table;
-        let column = &self.column;
-        format!("ALTER TABLE `{table}` DROP COLUMN `{column}`")
+    fn to_string(&self, dialect: SQLDialect) -> String {
+        let table = self.table.escape(dialect);
+        let column = self.column.escape(dialect);
+        format!("ALTER TABLE {table} DROP COLUMN {column}")
     }
 }
@@ -1,6 +1,6 @@
 use crate::schema::column::SQLColumn;
 use crate::schema::dialect::SQLDialect;
-use crate::schema::value::encode::ToSQLString;
+use crate::schema::value::encode::{SQLEscape, ToSQLString};
 
 pub struct SQLAlterTableModifyStatement {
     pub(crate) table: String,
@@ -11,14 +11,16 @@ impl ToSQLString for SQLAlterTableModifyStatement {
     fn to_string(&self, dialect: SQLDialect) -> String {
         let table = &self.table;
         let def = self.column.to_string(dialect);
-        let escape = dialect.escape();
+        let table = table.escape(dialect);
         if dialect == SQLDialect::SQLite {
-            format!("ALTER TABLE {escape}{table}{escape} ({def})")
+            format!("ALTER TABLE {table} ({def})")
         } else if dialect == SQLDialect::PostgreSQL {
-            let c_name = self.column.name();
-            format!("ALTER TABLE {escape}{table}{escape} ALTER COLUMN {escape}{c_name}{escape} TYPE column_definition;")
+            let c_name = self.column.name().escape(dialect);
+            format!("ALTER TABLE {table} ALTER COLUMN {c_name} TYPE column_definition;")
+        } else if dialect == SQLDialect::MSSQL {
+            format!("ALTER TABLE {table} ALTER COLUMN {def}")
         } else {
-            format!("ALTER TABLE {escape}{table}{escape} MODIFY {def}")
+            format!("ALTER TABLE {table} MODIFY {def}")
         }
     }
 }
@@ -1,5 +1,5 @@
 use crate::schema::dialect::SQLDialect;
-use crate::schema::value::encode::ToSQLString;
+use crate::schema::value::encode::{SQLEscape, ToSQLString};
 use teo_runtime::model::{Index, index::Item};
 use crate::exts::index::IndexExt;
 
@@ -28,7 +28,11 @@ impl ToSQLString for SQLCreateIndexOnStatement {
         let index = &self.index;
         let table = &self.table;
         let def = self.columns.iter().map(|c| Index::sql_format_item(dialect, c, false)).collect::<Vec<String>>().join(", ");
-        format!("CREATE{unique} INDEX `{index}` ON `{table}`({def})")
+        if dialect == SQLDialect::MSSQL {
+            format!("CREATE{unique} INDEX {} ON {}({def})", index.escape(dialect), table.escape(dialect))
+        } else {
+            format!("CREATE{unique} INDEX `{index}` ON `{table}`({def})")
+        }
     }
 }
 
@@ -1,7 +1,7 @@
 use array_tool::vec::Join;
 use crate::schema::column::SQLColumn;
 use crate::schema::dialect::SQLDialect;
-use crate::schema::value::encode::ToSQLString;
+use crate::schema::value::encode::{SQLEscape, ToSQLString};
 use teo_runtime::model::Index;
 use crate::exts::index::IndexExt;
 
@@ -49,6 +49,13 @@ impl ToSQLString for SQLCreateTableStatement {
         }
         if dialect == SQLDialect::PostgreSQL {
             format!("CREATE TABLE{if_not_exists} \"{table_name}\"( {columns} );")
+        } else if dialect == SQLDialect::MSSQL {
+            let create = format!("CREATE TABLE {}( {columns} );", table_name.escape(dialect));
+            if self.if_not_exists {
+                format!("IF OBJECT_ID(N'{table_name}', N'U') IS NULL {create}")
+            } else {
+                create
+            }
         } else {
             format!("CREATE TABLE{if_not_exists} `{table_name}`( {columns} );")
         }
@@ -1,9 +1,12 @@
+use quaint_forked::prelude::Value as QuaintValue;
 use crate::schema::dialect::SQLDialect;
-use crate::schema::value::encode::ToSQLString;
+use crate::schema::value::encode::{SQLEscape, ToSQLString};
+use crate::stmts::params::SQLParams;
 
 pub struct SQLDeleteFromStatement<'a> {
     pub(crate) from: &'a str,
     pub(crate) r#where: Option<String>,
+    pub(crate) params: Option<SQLParams>,
 }
 
 impl<'a> SQLDeleteFromStatement<'a> {
@@ -12,6 +15,20 @@ impl<'a> SQLDeleteFromStatement<'a> {
         self.r#where = Some(r#where);
         self
     }
+
+    pub(crate) fn params(&mut self, params: SQLParams) -> &mut Self {
+        self.params = Some(params);
+        self
+    }
+
+    // the SQL text with the values bound to its placeholders
+    pub(crate) fn to_statement(&mut self, dialect: SQLDialect) -> (String, Vec<QuaintValue<'static>>) {
+        let sql = self.to_string(dialect);
+        match self.params.take() {
+            Some(params) => params.finish(sql),
+            None => (sql, vec![]),
+        }
+    }
 }
 
 impl<'a> ToSQLString for SQLDeleteFromStatement<'a> {
@@ -25,7 +42,35 @@ impl<'a> ToSQLString for SQLDeleteFromStatement<'a> {
         } else {
             "".to_owned()
         };
-        let escape = dialect.escape();
-        format!("DELETE FROM {}{}{}{}", escape, self.from, escape, r#where)
+        format!("DELETE FROM {}{}", self.from.escape(dialect), r#where)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use teo_teon::Value;
+    use crate::query::Query;
+    use crate::schema::dialect::SQLDialect;
+    use crate::schema::value::encode::SQLEscape;
+    use crate::stmts::params::SQLParams;
+    use crate::stmts::SQL;
+
+    const DIALECTS: [SQLDialect; 4] = [SQLDialect::PostgreSQL, SQLDialect::MySQL, SQLDialect::SQLite, SQLDialect::MSSQL];
+
+    #[test]
+    fn delete_many_is_one_statement() {
+        for dialect in DIALECTS {
+            let mut params = SQLParams::new(dialect);
+            let r#where = Query::where_item("active".escape(dialect), "=", &params.push_untyped(&Value::Bool(false)));
+            let (sql, values) = SQL::delete_from("users").r#where(r#where).params(params).to_statement(dialect);
+            let expected = match dialect {
+                SQLDialect::PostgreSQL => r#"DELETE FROM "users" WHERE "active" = $1"#,
+                SQLDialect::MySQL => "DELETE FROM `users` WHERE `active` = ?",
+                SQLDialect::SQLite => "DELETE FROM `users` WHERE `active` = ?1",
+                SQLDialect::MSSQL => "DELETE FROM [users] WHERE [active] = @P1",
+            };
+            assert_eq!(sql, expected);
+            assert_eq!(values.len(), 1);
+        }
     }
 }
@@ -1,5 +1,5 @@
 use crate::schema::dialect::SQLDialect;
-use crate::schema::value::encode::ToSQLString;
+use crate::schema::value::encode::{SQLEscape, ToSQLString};
 
 pub(crate) struct SQLDropDatabaseStatement {
     pub(crate) database: String,
@@ -14,9 +14,13 @@ impl SQLDropDatabaseStatement {
 }
 
 impl ToSQLString for SQLDropDatabaseStatement {
-    fn to_string(&self, _dialect: SQLDialect) -> String {
+    fn to_string(&self, dialect: SQLDialect) -> String {
         let database = &self.database;
         let if_exists = if self.if_exists { " IF EXISTS" } else { "" };
-        format!("DROP DATABASE{if_exists} `{database}`;")
+        if dialect == SQLDialect::MSSQL {
+            format!("DROP DATABASE{if_exists} {};", database.escape(dialect))
+        } else {
+            format!("DROP DATABASE{if_exists} `{database}`;")
+        }
     }
 }
@@ -1,5 +1,5 @@
 use crate::schema::dialect::SQLDialect;
-use crate::schema::value::encode::ToSQLString;
+use crate::schema::value::encode::{SQLEscape, ToSQLString};
 
 pub(crate) struct SQLDropIndexOnStatement {
     pub(crate) index: String,
@@ -7,10 +7,14 @@ pub(crate) struct SQLDropIndexOnStatement {
 }
 
 impl ToSQLString for SQLDropIndexOnStatement {
-    fn to_string(&self, _dialect: SQLDialect) -> String {
+    fn to_string(&self, dialect: SQLDialect) -> String {
         let index = &self.index;
         let table = &self.table;
-        format!("DROP INDEX `{index}` on `{table}`")
+        if dialect == SQLDialect::MSSQL {
+            format!("DROP INDEX {} ON {}", index.escape(dialect), table.escape(dialect))
+        } else {
+            format!("DROP INDEX `{index}` on `{table}`")
+        }
     }
 }
 
@@ -1,20 +1,43 @@
+use quaint_forked::prelude::Value as QuaintValue;
 use crate::schema::dialect::SQLDialect;
-use crate::schema::value::encode::ToSQLString;
+use crate::schema::value::encode::{SQLEscape, ToSQLString};
+use crate::stmts::params::SQLParams;
 
 pub(crate) struct SQLInsertIntoStatement<'a> {
     pub(crate) table: &'a str,
-    pub(crate) values: Vec<(&'a str, &'a str)>,
+    pub(crate) columns: Vec<&'a str>,
+    pub(crate) rows: Vec<Vec<&'a str>>,
     pub(crate) returning: Vec<String>,
+    pub(crate) on_conflict: Vec<&'a str>,
+    pub(crate) do_update: Vec<(&'a str, &'a str)>,
+    pub(crate) params: Option<SQLParams>,
 }
 
 impl<'a> SQLInsertIntoStatement<'a> {
     pub(crate) fn value(&mut self, pair: (&'a str, &'a str)) -> &mut Self {
-        self.values.push(pair);
+        if self.rows.is_empty() {
+            self.rows.push(vec![]);
+        }
+        self.columns.push(pair.0);
+        self.rows[0].push(pair.1);
         self
     }
 
     pub(crate) fn values(&mut self, pairs: Vec<(&'a str, &'a str)>) -> &mut Self {
-        self.values.extend(pairs);
+        for pair in pairs {
+            self.value(pair);
+        }
+        self
+    }
+
+    pub(crate) fn columns(&mut self, columns: Vec<&'a str>) -> &mut Self {
+        self.columns = columns;
+        self
+    }
+
+    // values are listed in the order of `columns`
+    pub(crate) fn row(&mut self, values: Vec<&'a str>) -> &mut Self {
+        self.rows.push(values);
         self
     }
 
@@ -22,24 +45,146 @@ impl<'a> SQLInsertIntoStatement<'a> {
         self.returning = keys.iter().map(|k| k.clone().into()).collect();
         self
     }
+
+    // MySQL ignores the conflict target and matches any unique key
+    pub(crate) fn on_conflict(&mut self, columns: Vec<&'a str>) -> &mut Self {
+        self.on_conflict = columns;
+        self
+    }
+
+    pub(crate) fn do_update(&mut self, pairs: Vec<(&'a str, &'a str)>) -> &mut Self {
+        self.do_update = pairs;
+        self
+    }
+
+    pub(crate) fn params(&mut self, params: SQLParams) -> &mut Self {
+        self.params = Some(params);
+        self
+    }
+
+    // the SQL text with the values bound to its placeholders
+    pub(crate) fn to_statement(&mut self, dialect: SQLDialect) -> (String, Vec<QuaintValue<'static>>) {
+        let sql = self.to_string(dialect);
+        match self.params.take() {
+            Some(params) => params.finish(sql),
+            None => (sql, vec![]),
+        }
+    }
+
+    fn upsert_clause(&self, dialect: SQLDialect) -> String {
+        if self.on_conflict.is_empty() {
+            return "".to_owned();
+        }
+        let updates: Vec<String> = self.do_update.iter().map(|(k, v)| format!("{} = {}", k.escape(dialect), v)).collect();
+        match dialect {
+            SQLDialect::MySQL => if updates.is_empty() {
+                let column = self.on_conflict.first().unwrap().escape(dialect);
+                format!(" ON DUPLICATE KEY UPDATE {column} = {column}")
+            } else {
+                format!(" ON DUPLICATE KEY UPDATE {}", updates.join(","))
+            },
+            SQLDialect::PostgreSQL | SQLDialect::SQLite => {
+                let target = self.on_conflict.iter().map(|k| k.escape(dialect)).collect::<Vec<String>>().join(",");
+                if updates.is_empty() {
+                    format!(" ON CONFLICT ({target}) DO NOTHING")
+                } else {
+                    format!(" ON CONFLICT ({target}) DO UPDATE SET {}", updates.join(","))
+                }
+            }
+            SQLDialect::MSSQL => "".to_owned(),
+        }
+    }
 }
 
 impl<'a> ToSQLString for SQLInsertIntoStatement<'a> {
     fn to_string(&self, dialect: SQLDialect) -> String {
-        let mut keys: Vec<&str> = vec![];
-        let mut values: Vec<&str> = vec![];
-        for (k, v) in self.values.iter() {
-            keys.push(k);
-            values.push(v);
-        }
-        if dialect == SQLDialect::PostgreSQL {
-            format!("INSERT INTO \"{}\"({}) VALUES({}){};", self.table, keys.iter().map(|k| format!("\"{}\"", k)).collect::<Vec<String>>().join(","), values.join(","), if self.returning.is_empty() {
+        let keys = &self.columns;
+        let upsert = self.upsert_clause(dialect);
+        let values = if self.rows.is_empty() {
+            "()".to_owned()
+        } else {
+            self.rows.iter().map(|row| format!("({})", row.join(","))).collect::<Vec<String>>().join(",")
+        };
+        if dialect == SQLDialect::MSSQL {
+            let output = if self.returning.is_empty() {
+                "".to_owned()
+            } else {
+                " OUTPUT ".to_owned() + &self.returning.iter().map(|k| format!("INSERTED.{}", k.escape(dialect))).collect::<Vec<String>>().join(",")
+            };
+            if keys.is_empty() {
+                format!("INSERT INTO {}{} DEFAULT VALUES;", self.table.escape(dialect), output)
+            } else {
+                format!("INSERT INTO {}({}){} VALUES{};", self.table.escape(dialect), keys.iter().map(|k| k.escape(dialect)).collect::<Vec<String>>().join(","), output, values)
+            }
+        } else if dialect == SQLDialect::PostgreSQL || dialect == SQLDialect::SQLite {
+            // an empty column list is only valid on MySQL
+            let values = if keys.is_empty() {
+                " DEFAULT VALUES".to_owned()
+            } else {
+                format!("({}) VALUES{}", keys.iter().map(|k| k.escape(dialect)).collect::<Vec<String>>().join(","), values)
+            };
+            let returning = if self.returning.is_empty() {
                 "".to_owned()
             } else {
-                "  RETURNING ".to_owned() + &self.returning.join(",")
-            })
+                " RETURNING ".to_owned() + &self.returning.iter().map(|k| k.escape(dialect)).collect::<Vec<String>>().join(",")
+            };
+            format!("INSERT INTO {}{}{}{};", self.table.escape(dialect), values, upsert, returning)
         } else {
-            format!("INSERT INTO `{}`({}) VALUES({});", self.table, keys.iter().map(|k| format!("`{k}`")).collect::<Vec<String>>().join(","), values.join(","))
+            format!("INSERT INTO `{}`({}) VALUES{}{};", self.table, keys.iter().map(|k| format!("`{k}`")).collect::<Vec<String>>().join(","), values, upsert)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use teo_teon::Value;
+    use crate::schema::dialect::SQLDialect;
+    use crate::stmts::params::SQLParams;
+    use crate::stmts::SQL;
+
+    const DIALECTS: [SQLDialect; 4] = [SQLDialect::PostgreSQL, SQLDialect::MySQL, SQLDialect::SQLite, SQLDialect::MSSQL];
+
+    #[test]
+    fn bulk_insert_returns_the_keys_rows_are_matched_by() {
+        for dialect in DIALECTS {
+            let mut params = SQLParams::new(dialect);
+            let rows: Vec<Vec<String>> = ["a@teocloud.io", "b@teocloud.io"].iter().map(|email| {
+                vec![params.push_untyped(&Value::String(email.to_string())), params.push_untyped(&Value::Int(1))]
+            }).collect();
+            let mut stmt = SQL::insert_into("users");
+            stmt.columns(vec!["email", "age"]);
+            for row in &rows {
+                stmt.row(row.iter().map(|v| v.as_str()).collect());
+            }
+            let (sql, values) = stmt.returning(&vec!["id", "email"]).params(params).to_statement(dialect);
+            let expected = match dialect {
+                SQLDialect::PostgreSQL => r#"INSERT INTO "users"("email","age") VALUES($1,$2),($3,$4) RETURNING "id","email";"#,
+                SQLDialect::MySQL => "INSERT INTO `users`(`email`,`age`) VALUES(?,?),(?,?);",
+                SQLDialect::SQLite => "INSERT INTO `users`(`email`,`age`) VALUES(?1,?2),(?3,?4) RETURNING `id`,`email`;",
+                SQLDialect::MSSQL => "INSERT INTO [users]([email],[age]) OUTPUT INSERTED.[id],INSERTED.[email] VALUES(@P1,@P2),(@P3,@P4);",
+            };
+            assert_eq!(sql, expected);
+            assert_eq!(values.len(), 4);
+        }
+    }
+
+    #[test]
+    fn upsert_updates_the_row_holding_the_unique_key() {
+        for dialect in DIALECTS {
+            let mut params = SQLParams::new(dialect);
+            let email = params.push_untyped(&Value::String("a@teocloud.io".to_owned()));
+            let name = params.push_untyped(&Value::String("A".to_owned()));
+            let update = params.push_untyped(&Value::String("B".to_owned()));
+            let (sql, values) = SQL::insert_into("users").values(vec![("email", email.as_str()), ("name", name.as_str())]).on_conflict(vec!["email"]).do_update(vec![("name", update.as_str())]).params(params).to_statement(dialect);
+            let expected = match dialect {
+                SQLDialect::PostgreSQL => r#"INSERT INTO "users"("email","name") VALUES($1,$2) ON CONFLICT ("email") DO UPDATE SET "name" = $3;"#,
+                SQLDialect::MySQL => "INSERT INTO `users`(`email`,`name`) VALUES(?,?) ON DUPLICATE KEY UPDATE `name` = ?;",
+                SQLDialect::SQLite => "INSERT INTO `users`(`email`,`name`) VALUES(?1,?2) ON CONFLICT (`email`) DO UPDATE SET `name` = ?3;",
+                // upserts are rejected on SQL Server before a statement is built
+                SQLDialect::MSSQL => "INSERT INTO [users]([email],[name]) VALUES(@P1,@P2);",
+            };
+            assert_eq!(sql, expected);
+            assert_eq!(values.len(), 3);
         }
     }
 }
@@ -19,6 +19,7 @@ pub mod alter_table;
 pub mod select;
 pub mod update;
 pub mod delete_from;
+pub mod params;
 
 pub(crate) struct SQL { }
 
@@ -48,18 +49,18 @@ impl SQL {
     }
 
     pub(crate) fn insert_into(table: &str) -> SQLInsertIntoStatement {
-        SQLInsertIntoStatement { table, values: vec![], returning: vec![] }
+        SQLInsertIntoStatement { table, columns: vec![], rows: vec![], returning: vec![], on_conflict: vec![], do_update: vec![], params: None }
     }
 
     pub(crate) fn update(table: &str) -> SQLUpdateStatement {
-        SQLUpdateStatement { table, values: vec![], r#where: "" }
+        SQLUpdateStatement { table, values: vec![], r#where: "", params: None }
     }
 
     pub(crate) fn delete_from(from: &str) -> SQLDeleteFromStatement {
-        SQLDeleteFromStatement { from, r#where: None }
+        SQLDeleteFromStatement { from, r#where: None, params: None }
     }
 
     pub(crate) fn select<'a>(columns: Option<&'a Vec<&'a str>>, from: &'a str) -> SQLSelectStatement<'a> {
-        SQLSelectStatement { columns, from, r#where: None, order_by: None, limit: None, left_join: None, inner_join: None }
+        SQLSelectStatement { columns, from, r#where: None, order_by: None, limit: None, left_join: None, inner_join: None, lock: None, params: None }
     }
 }
@@ -0,0 +1,179 @@
+use quaint_forked::prelude::Value as QuaintValue;
+use serde_json::Value as JsonValue;
+use teo_parser::r#type::Type;
+use teo_teon::Value;
+use teo_result::{Error, Result};
+use crate::schema::dialect::SQLDialect;
+use crate::schema::value::encode::{json_path_string, psql_cast, value_to_json, ToSQLParam};
+
+pub(crate) struct SQLParams {
+    dialect: SQLDialect,
+    values: Vec<QuaintValue<'static>>,
+}
+
+impl SQLParams {
+
+    pub(crate) fn new(dialect: SQLDialect) -> Self {
+        Self { dialect, values: vec![] }
+    }
+
+    pub(crate) fn dialect(&self) -> SQLDialect {
+        self.dialect
+    }
+
+    // placeholders are numbered in binding order, fragments may be assembled in any order
+    pub(crate) fn push(&mut self, value: &Value, r#type: &Type) -> String {
+        if value.is_null() {
+            return "NULL".to_owned();
+        }
+        self.values.push(value.to_sql_param(r#type, self.dialect));
+        let placeholder = self.placeholder(self.values.len());
+        if self.dialect == SQLDialect::PostgreSQL {
+            if let Some(cast) = psql_cast(value, r#type) {
+                return format!("{placeholder}::{cast}");
+            }
+        }
+        placeholder
+    }
+
+    pub(crate) fn push_untyped(&mut self, value: &Value) -> String {
+        self.push(value, &Type::Undetermined)
+    }
+
+    // wildcards in the searched text match literally, `!` avoids the backslash rules of MySQL
+    // string literals; SQL Server also treats `[` as a wildcard
+    pub(crate) fn push_like(&mut self, value: &Value, left: bool, right: bool) -> Result<String> {
+        let Some(value) = value.as_str() else {
+            return Err(Error::new("string filter operand should be a string"));
+        };
+        let mut pattern = String::new();
+        if left {
+            pattern.push('%');
+        }
+        for ch in value.chars() {
+            if matches!(ch, '!' | '%' | '_') || (ch == '[' && self.dialect == SQLDialect::MSSQL) {
+                pattern.push('!');
+            }
+            pattern.push(ch);
+        }
+        if right {
+            pattern.push('%');
+        }
+        let placeholder = self.push(&Value::String(pattern), &Type::String);
+        Ok(format!("{placeholder} ESCAPE '!'"))
+    }
+
+    // a JSON document usable as an operand of the JSON functions of each database
+    pub(crate) fn push_json(&mut self, value: &Value) -> String {
+        let json = value_to_json(value);
+        if self.dialect == SQLDialect::MSSQL && !json.is_object() && !json.is_array() {
+            // scalars are compared and stored as their SQL counterparts
+            return match json {
+                JsonValue::String(s) => self.push(&Value::String(s), &Type::String),
+                other => self.push(&Value::String(other.to_string()), &Type::String),
+            };
+        }
+        let placeholder = self.push(&Value::String(json.to_string()), &Type::String);
+        match self.dialect {
+            SQLDialect::PostgreSQL => format!("{placeholder}::jsonb"),
+            SQLDialect::MySQL => format!("CAST({placeholder} AS JSON)"),
+            SQLDialect::SQLite => format!("json({placeholder})"),
+            SQLDialect::MSSQL => format!("JSON_QUERY({placeholder})"),
+        }
+    }
+
+    // PostgreSQL takes paths as text arrays, the others as `$."a"[0]` strings
+    pub(crate) fn push_json_path(&mut self, segments: &Vec<String>) -> String {
+        if self.dialect == SQLDialect::PostgreSQL {
+            let path = Value::Array(segments.iter().map(|s| Value::String(s.clone())).collect());
+            self.push(&path, &Type::Array(Box::new(Type::String)))
+        } else {
+            self.push(&Value::String(json_path_string(segments)), &Type::String)
+        }
+    }
+
+    fn placeholder(&self, index: usize) -> String {
+        match self.dialect {
+            SQLDialect::PostgreSQL => format!("${index}"),
+            SQLDialect::MSSQL => format!("@P{index}"),
+            _ => format!("?{index}"),
+        }
+    }
+
+    pub(crate) fn finish(self, sql: String) -> (String, Vec<QuaintValue<'static>>) {
+        if self.dialect != SQLDialect::MySQL {
+            return (sql, self.values);
+        }
+        // MySQL only understands anonymous placeholders, values follow their appearance
+        let mut result = String::with_capacity(sql.len());
+        let mut values = Vec::with_capacity(self.values.len());
+        let mut quote: Option<char> = None;
+        let mut chars = sql.chars().peekable();
+        while let Some(ch) = chars.next() {
+            if let Some(q) = quote {
+                result.push(ch);
+                if ch == '\\' && q != '`' {
+                    // the escaped character never closes the literal
+                    if let Some(escaped) = chars.next() {
+                        result.push(escaped);
+                    }
+                } else if ch == q {
+                    quote = None;
+                }
+            } else if ch == '\'' || ch == '"' || ch == '`' {
+                quote = Some(ch);
+                result.push(ch);
+            } else if ch == '?' && chars.peek().map_or(false, |c| c.is_ascii_digit()) {
+                let mut index = 0usize;
+                while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+                    index = index * 10 + digit as usize;
+                    chars.next();
+                }
+                values.push(self.values[index - 1].clone());
+                result.push('?');
+            } else {
+                result.push(ch);
+            }
+        }
+        (result, values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quaint_forked::prelude::Value as QuaintValue;
+    use teo_parser::r#type::Type;
+    use teo_teon::Value;
+    use crate::schema::dialect::SQLDialect;
+    use super::SQLParams;
+
+    #[test]
+    fn like_patterns_match_wildcards_literally() {
+        let mut params = SQLParams::new(SQLDialect::MSSQL);
+        assert_eq!(params.push_like(&Value::String("50%_[a]!".to_owned()), true, false).unwrap(), "@P1 ESCAPE '!'");
+        assert!(params.push_like(&Value::Int(50), true, false).is_err());
+        let (_, values) = params.finish(String::new());
+        assert_eq!(values[0].to_string().unwrap(), "%50!%!_![a]!!");
+    }
+
+    #[test]
+    fn mysql_placeholders_skip_quoted_text() {
+        let mut params = SQLParams::new(SQLDialect::MySQL);
+        let first = params.push_untyped(&Value::String("a".to_owned()));
+        let second = params.push_untyped(&Value::String("b".to_owned()));
+        let sql = format!("SELECT 'it\\'s ?1', \"say \\\"?2\\\"\", `?1` WHERE x = {second} AND y = {first}");
+        let (sql, values) = params.finish(sql);
+        assert_eq!(sql, "SELECT 'it\\'s ?1', \"say \\\"?2\\\"\", `?1` WHERE x = ? AND y = ?");
+        assert_eq!(values[0].to_string().unwrap(), "b");
+        assert_eq!(values[1].to_string().unwrap(), "a");
+    }
+
+    #[test]
+    fn null_array_elements_bind_with_the_element_type() {
+        let mut params = SQLParams::new(SQLDialect::PostgreSQL);
+        let r#type = Type::Array(Box::new(Type::Optional(Box::new(Type::Int64))));
+        assert_eq!(params.push(&Value::Array(vec![Value::Int64(1), Value::Null]), &r#type), "$1::integer[]");
+        let (_, values) = params.finish(String::new());
+        assert_eq!(values[0], QuaintValue::Array(Some(vec![QuaintValue::Int64(Some(1)), QuaintValue::Int64(None)])));
+    }
+}
@@ -0,0 +1,55 @@
+use crate::schema::dialect::SQLDialect;
+use crate::schema::value::encode::{SQLEscape, ToSQLString};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SQLLockMode {
+    Update,
+    Share,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SQLLockWait {
+    Wait,
+    NoWait,
+    SkipLocked,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SQLLock {
+    pub(crate) mode: SQLLockMode,
+    pub(crate) wait: SQLLockWait,
+    pub(crate) of: Option<String>,
+}
+
+impl SQLLock {
+
+    pub(crate) fn new(mode: SQLLockMode, wait: SQLLockWait) -> Self {
+        Self { mode, wait, of: None }
+    }
+
+    // restricts locking to one table of a joined select
+    pub(crate) fn of(mut self, table: impl Into<String>) -> Self {
+        self.of = Some(table.into());
+        self
+    }
+}
+
+impl ToSQLString for SQLLock {
+    fn to_string(&self, dialect: SQLDialect) -> String {
+        let mode = match self.mode {
+            SQLLockMode::Update => "FOR UPDATE",
+            SQLLockMode::Share => "FOR SHARE",
+        };
+        let of = if let Some(of) = &self.of {
+            format!(" OF {}", of.escape(dialect))
+        } else {
+            "".to_owned()
+        };
+        let wait = match self.wait {
+            SQLLockWait::Wait => "",
+            SQLLockWait::NoWait => " NOWAIT",
+            SQLLockWait::SkipLocked => " SKIP LOCKED",
+        };
+        format!("{mode}{of}{wait}")
+    }
+}
@@ -1,9 +1,13 @@
 use std::borrow::Cow;
+use quaint_forked::prelude::Value as QuaintValue;
 use crate::query::escape_wisdom;
 use crate::schema::dialect::SQLDialect;
 use crate::schema::value::encode::{SQLEscape, ToSQLString};
+use crate::stmts::params::SQLParams;
+use crate::stmts::select::lock::SQLLock;
 
 pub mod r#where;
+pub mod lock;
 
 pub struct SQLSelectStatement<'a> {
     pub(crate) columns: Option<&'a Vec<&'a str>>,
@@ -13,6 +17,8 @@ pub struct SQLSelectStatement<'a> {
     pub(crate) inner_join: Option<String>,
     pub(crate) order_by: Option<String>,
     pub(crate) limit: Option<(u64, u64)>,
+    pub(crate) lock: Option<SQLLock>,
+    pub(crate) params: Option<SQLParams>,
 }
 
 impl<'a> SQLSelectStatement<'a> {
@@ -45,6 +51,25 @@ impl<'a> SQLSelectStatement<'a> {
         self.limit = Some((limit, skip));
         self
     }
+
+    pub fn lock(&mut self, lock: SQLLock) -> &mut Self {
+        self.lock = Some(lock);
+        self
+    }
+
+    pub(crate) fn params(&mut self, params: SQLParams) -> &mut Self {
+        self.params = Some(params);
+        self
+    }
+
+    // the SQL text with the values bound to its placeholders
+    pub(crate) fn to_statement(&mut self, dialect: SQLDialect) -> (String, Vec<QuaintValue<'static>>) {
+        let sql = self.to_string(dialect);
+        match self.params.take() {
+            Some(params) => params.finish(sql),
+            None => (sql, vec![]),
+        }
+    }
 }
 
 impl<'a> ToSQLString for SQLSelectStatement<'a> {
@@ -77,11 +102,22 @@ impl<'a> ToSQLString for SQLSelectStatement<'a> {
         };
         let order_by = if let Some(order_by) = &self.order_by {
             " ORDER BY ".to_owned() + order_by
+        } else if dialect == SQLDialect::MSSQL && self.limit.is_some_and(|(_, skip)| skip > 0) {
+            // OFFSET FETCH is only valid after an ORDER BY clause
+            " ORDER BY (SELECT NULL)".to_owned()
         } else {
             "".to_owned()
         };
+        let mut top = "".to_owned();
         let limit = if let Some(limit) = &self.limit {
-            if dialect == SQLDialect::PostgreSQL {
+            if dialect == SQLDialect::MSSQL {
+                if limit.1 == 0 && self.order_by.is_none() {
+                    top = format!("TOP ({}) ", limit.0);
+                    "".to_owned()
+                } else {
+                    format!(" OFFSET {} ROWS FETCH NEXT {} ROWS ONLY", limit.1, limit.0)
+                }
+            } else if dialect == SQLDialect::PostgreSQL {
                 format!(" LIMIT {} OFFSET {}", limit.0, limit.1)
             } else {
                 format!(" LIMIT {},{}", limit.1, limit.0)
@@ -89,12 +125,17 @@ impl<'a> ToSQLString for SQLSelectStatement<'a> {
         } else {
             "".to_owned()
         };
-        let escape = dialect.escape();
-        let from_escaped = if self.from.starts_with("'") || self.from.starts_with("\"") || self.from.starts_with("`") {
+        let lock = if let Some(lock) = &self.lock {
+            " ".to_owned() + &lock.to_string(dialect)
+        } else {
+            "".to_owned()
+        };
+        // derived tables `(SELECT ...) AS d` are taken as they are
+        let from_escaped = if self.from.is_escaped() || self.from.starts_with('(') {
             Cow::Borrowed(self.from)
         } else {
-            Cow::Owned(format!("{}{}{}", escape, self.from, escape))
+            Cow::Owned(self.from.escape(dialect))
         };
-        format!("SELECT {columns} from {}{}{}{}{}{}", from_escaped, left_join, inner_join, r#where, order_by, limit)
+        format!("SELECT {top}{columns} from {}{}{}{}{}{}{}", from_escaped, left_join, inner_join, r#where, order_by, limit, lock)
     }
 }
@@ -1,10 +1,13 @@
+use quaint_forked::prelude::Value as QuaintValue;
 use crate::schema::dialect::SQLDialect;
-use crate::schema::value::encode::ToSQLString;
+use crate::schema::value::encode::{SQLEscape, ToSQLString};
+use crate::stmts::params::SQLParams;
 
 pub struct SQLUpdateStatement<'a> {
     pub(crate) table: &'a str,
     pub(crate) values: Vec<(&'a str, &'a str)>,
     pub(crate) r#where: &'a str,
+    pub(crate) params: Option<SQLParams>,
 }
 
 impl<'a> SQLUpdateStatement<'a> {
@@ -22,6 +25,20 @@ impl<'a> SQLUpdateStatement<'a> {
         self.r#where = r#where;
         self
     }
+
+    pub(crate) fn params(&mut self, params: SQLParams) -> &mut Self {
+        self.params = Some(params);
+        self
+    }
+
+    // the SQL text with the values bound to its placeholders
+    pub(crate) fn to_statement(&mut self, dialect: SQLDialect) -> (String, Vec<QuaintValue<'static>>) {
+        let sql = self.to_string(dialect);
+        match self.params.take() {
+            Some(params) => params.finish(sql),
+            None => (sql, vec![]),
+        }
+    }
 }
 
 impl<'a> ToSQLString for SQLUpdateStatement<'a> {
@@ -30,6 +47,8 @@ impl<'a> ToSQLString for SQLUpdateStatement<'a> {
         for (k, v) in self.values.iter() {
             if dialect == SQLDialect::PostgreSQL {
                 exprs.push(format!("\"{}\" = {}", k, v));
+            } else if dialect == SQLDialect::MSSQL {
+                exprs.push(format!("{} = {}", k.escape(dialect), v));
             } else {
                 exprs.push(format!("`{}` = {}", k, v));
             }
@@ -41,8 +60,40 @@ impl<'a> ToSQLString for SQLUpdateStatement<'a> {
         };
         if dialect == SQLDialect::PostgreSQL {
             format!("UPDATE \"{}\" SET {}{};", self.table, exprs.join(","), r#where)
+        } else if dialect == SQLDialect::MSSQL {
+            format!("UPDATE {} SET {}{};", self.table.escape(dialect), exprs.join(","), r#where)
         } else {
             format!("UPDATE `{}` SET {}{};", self.table, exprs.join(","), r#where)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use teo_teon::Value;
+    use crate::query::Query;
+    use crate::schema::dialect::SQLDialect;
+    use crate::schema::value::encode::SQLEscape;
+    use crate::stmts::params::SQLParams;
+    use crate::stmts::SQL;
+
+    const DIALECTS: [SQLDialect; 4] = [SQLDialect::PostgreSQL, SQLDialect::MySQL, SQLDialect::SQLite, SQLDialect::MSSQL];
+
+    #[test]
+    fn update_many_is_one_statement_with_atomic_updators() {
+        for dialect in DIALECTS {
+            let mut params = SQLParams::new(dialect);
+            let increment = format!("age + {}", params.push_untyped(&Value::Int(1)));
+            let r#where = Query::where_item("active".escape(dialect), "=", &params.push_untyped(&Value::Bool(true)));
+            let (sql, values) = SQL::update("users").values(vec![("age", increment.as_str())]).r#where(&r#where).params(params).to_statement(dialect);
+            let expected = match dialect {
+                SQLDialect::PostgreSQL => r#"UPDATE "users" SET "age" = age + $1 WHERE "active" = $2;"#,
+                SQLDialect::MySQL => "UPDATE `users` SET `age` = age + ? WHERE `active` = ?;",
+                SQLDialect::SQLite => "UPDATE `users` SET `age` = age + ?1 WHERE `active` = ?2;",
+                SQLDialect::MSSQL => "UPDATE [users] SET [age] = age + @P1 WHERE [active] = @P2;",
+            };
+            assert_eq!(sql, expected);
+            assert_eq!(values.len(), 2);
+        }
+    }
+}
@@ -1,13 +1,17 @@
 use crate::schema::dialect::SQLDialect;
-use crate::schema::value::encode::ToSQLString;
+use crate::schema::value::encode::{SQLEscape, ToSQLString};
 
 pub(crate) struct SQLUseDatabaseStatement {
     pub(crate) database: String
 }
 
 impl ToSQLString for SQLUseDatabaseStatement {
-    fn to_string(&self, _dialect: SQLDialect) -> String {
+    fn to_string(&self, dialect: SQLDialect) -> String {
         let database = &self.database;
-        format!("USE `{database}`")
+        if dialect == SQLDialect::MSSQL {
+            format!("USE {}", database.escape(dialect))
+        } else {
+            format!("USE `{database}`")
+        }
     }
 }
@@ -1,8 +1,11 @@
 pub(crate) mod url_utils {
     use std::path::PathBuf;
     use path_absolutize::Absolutize;
+    use percent_encoding::percent_decode_str;
     use url::Url;
     use whoami::Platform;
+    use crate::connector::isolation::{IsolationLevel, ISOLATION_LEVEL_PARAM, parse_isolation_level};
+    use crate::error::{SQLError, SQLErrorKind};
     use crate::schema::dialect::SQLDialect;
 
     pub(crate) fn remove_scheme(url: &str) -> &str {
@@ -34,18 +37,18 @@ pub(crate) mod url_utils {
         path.absolutize().unwrap().into_owned()
     }
 
-    pub(crate) fn normalized_url(dialect: SQLDialect, url: &str) -> Url {
-        let url_result = Url::parse(url);
-        if let Err(err) = url_result {
-            panic!("URL '{}' parse error: {}", url, err);
-        }
-        let mut url = url_result.unwrap();
+    pub(crate) fn normalized_url(dialect: SQLDialect, url: &str) -> Result<Url, SQLError> {
+        let original = url;
+        let mut url = Url::parse(original).map_err(|err| {
+            SQLError::new(SQLErrorKind::InvalidURL, dialect, format!("URL '{}' parse error: {}", original, err))
+        })?;
+        let invalid = |_: ()| SQLError::new(SQLErrorKind::InvalidURL, dialect, format!("URL '{}' cannot carry credentials", original));
         match dialect {
             SQLDialect::MySQL => {
                 if url.username() == "" {
-                    url.set_username("root").unwrap();
+                    url.set_username("root").map_err(invalid)?;
                     if url.password().is_none() {
-                        url.set_password(Some("")).unwrap();
+                        url.set_password(Some("")).map_err(invalid)?;
                     }
                 }
             }
@@ -53,24 +56,92 @@ pub(crate) mod url_utils {
                 if url.username() == "" {
                     if whoami::platform() == Platform::MacOS && url.port().is_some() && url.port().unwrap() == 5432 {
                         let username = whoami::username();
-                        url.set_username(username.as_str()).unwrap();
+                        url.set_username(username.as_str()).map_err(invalid)?;
                     } else {
-                        url.set_username("postgres").unwrap();
+                        url.set_username("postgres").map_err(invalid)?;
                     }
                     if url.password().is_none() {
-                        url.set_password(Some("")).unwrap();
+                        url.set_password(Some("")).map_err(invalid)?;
                     }
                 }
             }
+            SQLDialect::MSSQL => {
+                if url.username() == "" {
+                    url.set_username("sa").map_err(invalid)?;
+                }
+            }
             _ => (),
         }
-        url
+        Ok(url)
+    }
+
+    // the driver doesn't know this parameter, so it's taken out of the url
+    pub(crate) fn take_isolation_level(dialect: SQLDialect, url: &mut Url) -> Result<Option<IsolationLevel>, SQLError> {
+        let mut level = None;
+        let mut rest: Vec<(String, String)> = vec![];
+        for (k, v) in url.query_pairs() {
+            if k == ISOLATION_LEVEL_PARAM {
+                level = Some(parse_isolation_level(&v).ok_or_else(|| {
+                    SQLError::new(SQLErrorKind::InvalidURL, dialect, format!("unknown isolation level '{}'", v))
+                })?);
+            } else {
+                rest.push((k.into_owned(), v.into_owned()));
+            }
+        }
+        if level.is_some() {
+            if rest.is_empty() {
+                url.set_query(None);
+            } else {
+                url.query_pairs_mut().clear().extend_pairs(rest);
+            }
+        }
+        Ok(level)
+    }
+
+    // SQL Server connections are configured with a JDBC style string, the server certificate
+    // is only trusted when the url asks for it with `trustServerCertificate=true`
+    pub(crate) fn connection_string(dialect: SQLDialect, url: &Url) -> Result<String, SQLError> {
+        if dialect != SQLDialect::MSSQL {
+            return Ok(url.to_string());
+        }
+        let decoded = |s: &str| percent_decode_str(s).decode_utf8_lossy().into_owned();
+        let host = url.host_str().unwrap_or("localhost");
+        let port = url.port().unwrap_or(1433);
+        let mut result = format!("sqlserver://{host}:{port}");
+        let database = decoded(url.path().trim_start_matches('/'));
+        if !database.is_empty() {
+            result += &format!(";database={}", connection_string_value(&database));
+        }
+        if url.username() != "" {
+            result += &format!(";user={}", connection_string_value(&decoded(url.username())));
+        }
+        if let Some(password) = url.password() {
+            result += &format!(";password={}", connection_string_value(&decoded(password)));
+        }
+        for (k, v) in url.query_pairs() {
+            if k.is_empty() || k.contains([';', '=', '{', '}']) {
+                return Err(SQLError::new(SQLErrorKind::InvalidURL, dialect, format!("invalid connection parameter '{}'", k)));
+            }
+            result += &format!(";{k}={}", connection_string_value(&v));
+        }
+        Ok(result)
+    }
+
+    // values with separators or braces are wrapped in braces, closing braces are doubled
+    fn connection_string_value(value: &str) -> String {
+        if value.contains([';', '=', '{', '}']) || value.trim() != value {
+            format!("{{{}}}", value.replace('}', "}}"))
+        } else {
+            value.to_owned()
+        }
     }
 
     pub(crate) fn remove_db_path(dialect: SQLDialect, url: &Url) -> Url {
         let mut retval = url.clone();
         if dialect == SQLDialect::PostgreSQL {
             retval.set_path("/postgres");
+        } else if dialect == SQLDialect::MSSQL {
+            retval.set_path("/master");
         } else {
             retval.set_path("/");
         }